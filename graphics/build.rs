@@ -0,0 +1,19 @@
+fn main() {
+    #[cfg(feature = "slint")]
+    slint();
+}
+
+/// Compiles the Slint dashboard, see the `ui` module
+#[cfg(feature = "slint")]
+fn slint() {
+    // The dashboard picks its font size from the width of the display, so the sizes to embed the
+    // glyphs at cannot be found in the `.slint` file itself
+    std::env::set_var("SLINT_FONT_SIZES", "10,13,15");
+
+    slint_build::compile_with_config(
+        "ui/dashboard.slint",
+        slint_build::CompilerConfiguration::new()
+            .embed_resources(slint_build::EmbedResourcesKind::EmbedForSoftwareRenderer),
+    )
+    .unwrap();
+}
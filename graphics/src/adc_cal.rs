@@ -0,0 +1,166 @@
+//! The piecewise-linear ADC calibration tables, and the names of the ADC channels they belong to
+//!
+//! Recording the calibration points, reading the ADC and persisting the tables in NVS is left to
+//! the demo, so that the tables can be tested on the host.
+
+use core::fmt;
+use core::str::FromStr;
+
+/// The most points of a calibration table
+pub const MAX_POINTS: usize = 16;
+
+/// An ADC channel, at one of its attenuations
+///
+/// The fields are those of the ESP-IDF `adc_unit_t`, `adc_channel_t` and `adc_atten_t`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ChannelId {
+    pub unit: u32,
+    pub channel: u32,
+    pub atten: u32,
+}
+
+impl fmt::Display for ChannelId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Doubles as the NVS key, hence needs to stay below 16 characters
+        write!(f, "adc{}_ch{}_a{}", self.unit + 1, self.channel, self.atten)
+    }
+}
+
+impl FromStr for ChannelId {
+    type Err = CalibrationError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || CalibrationError::InvalidChannel(s.into());
+
+        let (unit, channel, atten) = s
+            .strip_prefix("adc")
+            .and_then(|s| s.split_once("_ch"))
+            .and_then(|(unit, s)| {
+                s.split_once("_a")
+                    .map(|(channel, atten)| (unit, channel, atten))
+            })
+            .ok_or_else(invalid)?;
+
+        let unit: u32 = unit.parse().map_err(|_| invalid())?;
+        if unit == 0 {
+            return Err(invalid());
+        }
+
+        Ok(Self {
+            unit: unit - 1,
+            channel: channel.parse().map_err(|_| invalid())?,
+            atten: atten.parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct CalibrationPoint {
+    pub raw: u16,
+    pub mv: u16,
+}
+
+/// A piecewise-linear mapping from raw ADC readings to millivolts
+#[derive(Clone, Debug)]
+pub struct CalibrationTable(Vec<CalibrationPoint>);
+
+impl CalibrationTable {
+    pub fn new(points: &[CalibrationPoint]) -> Result<Self, CalibrationError> {
+        let mut points = points.to_vec();
+
+        points.sort_by_key(|point| point.raw);
+        points.dedup();
+
+        if points.len() < 2 {
+            return Err(CalibrationError::TooFewPoints);
+        }
+
+        if points.len() > MAX_POINTS {
+            return Err(CalibrationError::TooManyPoints);
+        }
+
+        if points.windows(2).any(|w| w[0].raw == w[1].raw) {
+            return Err(CalibrationError::Conflicting);
+        }
+
+        Ok(Self(points))
+    }
+
+    pub fn points(&self) -> &[CalibrationPoint] {
+        &self.0
+    }
+
+    /// Converts a raw reading to millivolts by interpolating between the two enclosing points
+    /// (or by extrapolating the first/last segment if the reading is outside of the table)
+    pub fn apply(&self, raw: u16) -> u16 {
+        let segment = self
+            .0
+            .windows(2)
+            .position(|w| raw <= w[1].raw)
+            .unwrap_or(self.0.len() - 2);
+
+        let from = self.0[segment];
+        let to = self.0[segment + 1];
+
+        let mv = from.mv as i32
+            + (raw as i32 - from.raw as i32) * (to.mv as i32 - from.mv as i32)
+                / (to.raw as i32 - from.raw as i32);
+
+        mv.clamp(0, u16::MAX as i32) as u16
+    }
+
+    /// The table as persisted, 4 bytes per point
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.0
+            .iter()
+            .flat_map(|point| [point.raw.to_le_bytes(), point.mv.to_le_bytes()])
+            .flatten()
+            .collect()
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self, CalibrationError> {
+        if !data.len().is_multiple_of(4) {
+            return Err(CalibrationError::Corrupted);
+        }
+
+        let points = data
+            .chunks_exact(4)
+            .map(|chunk| CalibrationPoint {
+                raw: u16::from_le_bytes([chunk[0], chunk[1]]),
+                mv: u16::from_le_bytes([chunk[2], chunk[3]]),
+            })
+            .collect::<Vec<_>>();
+
+        Self::new(&points)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CalibrationError {
+    TooFewPoints,
+    TooManyPoints,
+    Conflicting,
+    Corrupted,
+    InvalidChannel(String),
+}
+
+impl fmt::Display for CalibrationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TooFewPoints => {
+                write!(f, "At least two distinct calibration points are necessary")
+            }
+            Self::TooManyPoints => {
+                write!(f, "At most {MAX_POINTS} calibration points are supported")
+            }
+            Self::Conflicting => write!(
+                f,
+                "Calibration points with the same raw reading but different voltages"
+            ),
+            Self::Corrupted => write!(f, "Corrupted calibration table"),
+            Self::InvalidChannel(channel) => write!(f, "Invalid ADC channel: {channel}"),
+        }
+    }
+}
+
+impl std::error::Error for CalibrationError {}
@@ -0,0 +1,121 @@
+//! BMP encoding of frames, for the screenshots of the display
+//!
+//! The rows are produced one at a time, so that a screenshot can be streamed to an HTTP client
+//! without a second copy of the whole frame in memory.
+
+use embedded_graphics::pixelcolor::*;
+use embedded_graphics::prelude::*;
+
+pub trait BmpColor: PixelColor {
+    const BITS_PER_PIXEL: u16;
+
+    /// `BI_RGB` (0), or `BI_BITFIELDS` (3) for the 16 bpp formats other than RGB555
+    const COMPRESSION: u32;
+
+    /// The palette or the bitfield masks following the header
+    const COLOR_TABLE: &'static [u8];
+
+    fn encode_row(pixels: impl Iterator<Item = Self>, row: &mut [u8]);
+}
+
+impl BmpColor for Rgb565 {
+    const BITS_PER_PIXEL: u16 = 16;
+    const COMPRESSION: u32 = 3;
+
+    const COLOR_TABLE: &'static [u8] = &[
+        0x00, 0xf8, 0x00, 0x00, // red
+        0xe0, 0x07, 0x00, 0x00, // green
+        0x1f, 0x00, 0x00, 0x00, // blue
+    ];
+
+    fn encode_row(pixels: impl Iterator<Item = Self>, row: &mut [u8]) {
+        for (pixel, bytes) in pixels.zip(row.chunks_exact_mut(2)) {
+            bytes.copy_from_slice(&pixel.into_storage().to_le_bytes());
+        }
+    }
+}
+
+/// `BinaryColor::On` is white, as on the OLED displays
+impl BmpColor for BinaryColor {
+    const BITS_PER_PIXEL: u16 = 1;
+    const COMPRESSION: u32 = 0;
+
+    const COLOR_TABLE: &'static [u8] = &[
+        0x00, 0x00, 0x00, 0x00, // black
+        0xff, 0xff, 0xff, 0x00, // white
+    ];
+
+    fn encode_row(pixels: impl Iterator<Item = Self>, row: &mut [u8]) {
+        row.fill(0);
+
+        for (x, pixel) in pixels.enumerate() {
+            if pixel.is_on() {
+                row[x / 8] |= 0x80 >> (x % 8);
+            }
+        }
+    }
+}
+
+/// Encodes the frame of the given size as BMP, passing the encoded bytes to `out` in pieces
+pub fn encode<C, E>(
+    size: Size,
+    pixel: impl Fn(Point) -> C,
+    out: impl FnMut(&[u8]) -> Result<(), E>,
+) -> Result<(), E>
+where
+    C: BmpColor,
+{
+    encode_rows::<C, E>(
+        size,
+        |y, row| C::encode_row((0..size.width as i32).map(|x| pixel(Point::new(x, y))), row),
+        out,
+    )
+}
+
+/// Like `encode`, but with each row encoded by `encode_row(y, row)` right before it is passed
+/// to `out`
+pub fn encode_rows<C, E>(
+    size: Size,
+    mut encode_row: impl FnMut(i32, &mut [u8]),
+    mut out: impl FnMut(&[u8]) -> Result<(), E>,
+) -> Result<(), E>
+where
+    C: BmpColor,
+{
+    let stride = (size.width as usize * C::BITS_PER_PIXEL as usize).div_ceil(32) * 4;
+
+    let offset = 14 + 40 + C::COLOR_TABLE.len() as u32;
+    let file_size = offset + (stride as u32 * size.height);
+
+    let mut header = Vec::with_capacity(offset as usize);
+
+    header.extend(b"BM");
+    header.extend(file_size.to_le_bytes());
+    header.extend([0; 4]);
+    header.extend(offset.to_le_bytes());
+
+    header.extend(40_u32.to_le_bytes());
+    header.extend((size.width as i32).to_le_bytes());
+    // Negative, as the rows are top-down
+    header.extend((-(size.height as i32)).to_le_bytes());
+    header.extend(1_u16.to_le_bytes());
+    header.extend(C::BITS_PER_PIXEL.to_le_bytes());
+    header.extend(C::COMPRESSION.to_le_bytes());
+    header.extend((stride as u32 * size.height).to_le_bytes());
+    // Resolution, and the number of the used and important colors
+    header.extend([0; 4 * 4]);
+
+    header.extend(C::COLOR_TABLE);
+
+    out(&header)?;
+
+    let mut row = vec![0; stride];
+
+    for y in 0..size.height as i32 {
+        encode_row(y, &mut row);
+
+        out(&row)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,283 @@
+//! A line chart of the recent history of a sensor reading, like the A2 ADC channel
+//!
+//! The vertical axis is scaled to the range of the samples, which is labeled with its minimum and
+//! maximum. The most recent sample is on the right edge.
+
+use std::collections::VecDeque;
+
+use embedded_graphics::mono_font::{ascii::*, MonoFont, MonoTextStyleBuilder};
+use embedded_graphics::pixelcolor::*;
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::*;
+use embedded_graphics::text::*;
+
+/// How many samples the history keeps by default; two minutes of readings taken every second
+pub const DEFAULT_CAPACITY: usize = 120;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct History {
+    samples: VecDeque<i32>,
+    capacity: usize,
+    // Samples pushed since the creation, to tell whether the history changed
+    pushed: u64,
+}
+
+impl History {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            samples: VecDeque::with_capacity(capacity),
+            capacity: capacity.max(2),
+            pushed: 0,
+        }
+    }
+
+    /// Appends the sample, dropping the oldest one if the history is full
+    pub fn push(&mut self, sample: i32) {
+        if self.samples.len() == self.capacity {
+            self.samples.pop_front();
+        }
+
+        self.samples.push_back(sample);
+        self.pushed += 1;
+    }
+
+    /// Oldest first
+    pub fn samples(&self) -> impl ExactSizeIterator<Item = i32> + '_ {
+        self.samples.iter().copied()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn latest(&self) -> Option<i32> {
+        self.samples.back().copied()
+    }
+
+    pub fn min(&self) -> Option<i32> {
+        self.samples().min()
+    }
+
+    pub fn max(&self) -> Option<i32> {
+        self.samples().max()
+    }
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY)
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct ChartPalette<C> {
+    pub background: C,
+    pub title: C,
+    pub axis: C,
+    pub label: C,
+    pub line: C,
+}
+
+impl<C> ChartPalette<C>
+where
+    C: RgbColor,
+{
+    pub fn rgb() -> Self {
+        Self {
+            background: C::BLACK,
+            title: C::YELLOW,
+            axis: C::WHITE,
+            label: C::CYAN,
+            line: C::GREEN,
+        }
+    }
+}
+
+impl ChartPalette<BinaryColor> {
+    pub fn binary() -> Self {
+        Self {
+            background: BinaryColor::Off,
+            title: BinaryColor::On,
+            axis: BinaryColor::On,
+            label: BinaryColor::On,
+            line: BinaryColor::On,
+        }
+    }
+}
+
+pub struct Chart<C> {
+    palette: ChartPalette<C>,
+    title: &'static str,
+    unit: &'static str,
+    // The value of `History::pushed` currently on the display; `None` until the display is cleared
+    drawn: Option<u64>,
+}
+
+impl<C> Chart<C>
+where
+    C: PixelColor,
+{
+    /// The title and the unit are shown in the top row, along with the latest sample
+    pub fn new(palette: ChartPalette<C>, title: &'static str, unit: &'static str) -> Self {
+        Self {
+            palette,
+            title,
+            unit,
+            drawn: None,
+        }
+    }
+
+    /// Renders the history, but only if it changed since the previous call
+    pub fn draw<D>(&mut self, display: &mut D, history: &History) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = C>,
+    {
+        if self.drawn == Some(history.pushed) {
+            return Ok(());
+        }
+
+        display.clear(self.palette.background)?;
+
+        let bbox = display.bounding_box();
+        let font = font(bbox.size);
+
+        let text = |color| {
+            MonoTextStyleBuilder::new()
+                .font(font)
+                .text_color(color)
+                .background_color(self.palette.background)
+                .build()
+        };
+
+        let title = match history.latest() {
+            Some(latest) => format!("{} {} {}", self.title, latest, self.unit),
+            None => format!("{} - {}", self.title, self.unit),
+        };
+
+        Text::with_baseline(
+            &title,
+            bbox.top_left,
+            text(self.palette.title),
+            Baseline::Top,
+        )
+        .draw(display)?;
+
+        let char_size = font.character_size;
+
+        // Below the title, with half a line of spacing
+        let top = bbox.top_left.y + (char_size.height + char_size.height / 2) as i32;
+        let bottom = bbox.top_left.y + bbox.size.height as i32 - 1;
+
+        let (min, max) = match (history.min(), history.max()) {
+            (Some(min), Some(max)) => (min, max),
+            _ => {
+                Text::with_baseline(
+                    "No data yet",
+                    Point::new(bbox.top_left.x, top),
+                    text(self.palette.label),
+                    Baseline::Top,
+                )
+                .draw(display)?;
+
+                self.drawn = Some(history.pushed);
+
+                return Ok(());
+            }
+        };
+
+        let (min_label, max_label) = (min.to_string(), max.to_string());
+
+        let label_width = min_label.len().max(max_label.len()) as i32 * char_size.width as i32 + 2;
+
+        let label = text(self.palette.label);
+        let right_aligned = TextStyleBuilder::new()
+            .alignment(Alignment::Right)
+            .baseline(Baseline::Top)
+            .build();
+
+        let label_x = bbox.top_left.x + label_width - 2;
+
+        Text::with_text_style(&max_label, Point::new(label_x, top), label, right_aligned)
+            .draw(display)?;
+
+        Text::with_text_style(
+            &min_label,
+            Point::new(label_x, bottom + 1 - char_size.height as i32),
+            label,
+            right_aligned,
+        )
+        .draw(display)?;
+
+        // The axes
+        let left = bbox.top_left.x + label_width;
+        let right = bbox.top_left.x + bbox.size.width as i32 - 1;
+
+        let axis = PrimitiveStyle::with_stroke(self.palette.axis, 1);
+
+        Polyline::new(&[
+            Point::new(left, top),
+            Point::new(left, bottom),
+            Point::new(right, bottom),
+        ])
+        .into_styled(axis)
+        .draw(display)?;
+
+        // The plot, inside of the axes
+        let plot =
+            Rectangle::with_corners(Point::new(left + 1, top), Point::new(right, bottom - 1));
+
+        let points = plot_points(history, &plot, min, max);
+
+        let line = PrimitiveStyle::with_stroke(self.palette.line, 1);
+
+        if let [point] = points[..] {
+            Pixel(point, self.palette.line).draw(display)?;
+        } else {
+            Polyline::new(&points).into_styled(line).draw(display)?;
+        }
+
+        self.drawn = Some(history.pushed);
+
+        Ok(())
+    }
+}
+
+fn font(size: Size) -> &'static MonoFont<'static> {
+    if size.width >= 200 && size.height >= 200 {
+        &FONT_8X13
+    } else {
+        &FONT_6X10
+    }
+}
+
+/// Maps the samples into the plot, scaling `min..=max` to its full height
+fn plot_points(history: &History, plot: &Rectangle, min: i32, max: i32) -> Vec<Point> {
+    let width = plot.size.width.max(1) as i64 - 1;
+    let height = plot.size.height.max(1) as i64 - 1;
+
+    // A flat history is plotted in the middle
+    let (min, range) = if max > min {
+        (min as i64, (max - min) as i64)
+    } else {
+        (min as i64 - 1, 2)
+    };
+
+    let right = plot.top_left.x as i64 + width;
+    let bottom = plot.top_left.y as i64 + height;
+
+    let samples = history.samples();
+    let count = samples.len() as i64;
+    let spacing = history.capacity() as i64 - 1;
+
+    samples
+        .enumerate()
+        .map(|(index, sample)| {
+            let age = count - 1 - index as i64;
+
+            Point::new(
+                (right - age * width / spacing) as i32,
+                (bottom - (sample as i64 - min) * height / range) as i32,
+            )
+        })
+        .collect()
+}
@@ -0,0 +1,219 @@
+//! A scrolling text console, showing the most recent log records
+//!
+//! Records are word-wrapped to the width of the display and colored by level on color displays.
+
+use log::Level;
+
+use embedded_graphics::mono_font::{ascii::*, MonoFont, MonoTextStyleBuilder};
+use embedded_graphics::pixelcolor::*;
+use embedded_graphics::prelude::*;
+use embedded_graphics::text::*;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Record {
+    pub level: Level,
+    pub message: String,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct ConsolePalette<C> {
+    pub background: C,
+    pub error: C,
+    pub warn: C,
+    pub info: C,
+    pub debug: C,
+}
+
+impl<C> ConsolePalette<C>
+where
+    C: Copy,
+{
+    fn color(&self, level: Level) -> C {
+        match level {
+            Level::Error => self.error,
+            Level::Warn => self.warn,
+            Level::Info => self.info,
+            Level::Debug | Level::Trace => self.debug,
+        }
+    }
+}
+
+impl<C> ConsolePalette<C>
+where
+    C: RgbColor,
+{
+    /// The colors of the ESP-IDF serial console
+    pub fn rgb() -> Self {
+        Self {
+            background: C::BLACK,
+            error: C::RED,
+            warn: C::YELLOW,
+            info: C::GREEN,
+            debug: C::WHITE,
+        }
+    }
+}
+
+impl ConsolePalette<BinaryColor> {
+    pub fn binary() -> Self {
+        Self {
+            background: BinaryColor::Off,
+            error: BinaryColor::On,
+            warn: BinaryColor::On,
+            info: BinaryColor::On,
+            debug: BinaryColor::On,
+        }
+    }
+}
+
+pub struct Console<C> {
+    palette: ConsolePalette<C>,
+    // What is currently on the display, row by row; `None` until the display is cleared
+    drawn: Option<Vec<(Level, String)>>,
+}
+
+impl<C> Console<C>
+where
+    C: PixelColor,
+{
+    pub fn new(palette: ConsolePalette<C>) -> Self {
+        Self {
+            palette,
+            drawn: None,
+        }
+    }
+
+    /// Renders as many of the most recent records as fit on the display, oldest first
+    ///
+    /// Only the rows which changed since the previous call are redrawn.
+    pub fn draw<D>(&mut self, display: &mut D, records: &[Record]) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = C>,
+    {
+        let bbox = display.bounding_box();
+        let font = font(bbox.size);
+
+        let cols = (bbox.size.width / font.character_size.width).max(3) as usize;
+        let rows = (bbox.size.height / font.character_size.height) as usize;
+
+        let mut lines = Vec::new();
+
+        // Walk back from the most recent record until the display is full
+        for record in records.iter().rev() {
+            let wrapped = wrap(&record.message, cols - 2);
+
+            for (index, line) in wrapped.iter().enumerate().rev() {
+                let prefix = if index == 0 {
+                    marker(record.level)
+                } else {
+                    " "
+                };
+
+                lines.push((record.level, format!("{} {:<2$}", prefix, line, cols - 2)));
+            }
+
+            if lines.len() >= rows {
+                break;
+            }
+        }
+
+        lines.truncate(rows);
+        lines.reverse();
+        lines.resize(rows, (Level::Info, " ".repeat(cols)));
+
+        if self.drawn.as_ref().map(Vec::len) != Some(rows) {
+            display.clear(self.palette.background)?;
+
+            self.drawn = Some(vec![(Level::Info, " ".repeat(cols)); rows]);
+        }
+
+        let drawn = self.drawn.as_mut().unwrap();
+
+        for (row, (line, previous)) in lines.into_iter().zip(drawn.iter_mut()).enumerate() {
+            if line == *previous {
+                continue;
+            }
+
+            let style = MonoTextStyleBuilder::new()
+                .font(font)
+                .text_color(self.palette.color(line.0))
+                .background_color(self.palette.background)
+                .build();
+
+            Text::with_baseline(
+                &line.1,
+                bbox.top_left + Point::new(0, (row as u32 * font.character_size.height) as i32),
+                style,
+                Baseline::Top,
+            )
+            .draw(display)?;
+
+            *previous = line;
+        }
+
+        Ok(())
+    }
+}
+
+fn font(size: Size) -> &'static MonoFont<'static> {
+    if size.width >= 240 {
+        &FONT_7X13
+    } else {
+        &FONT_6X10
+    }
+}
+
+fn marker(level: Level) -> &'static str {
+    match level {
+        Level::Error => "E",
+        Level::Warn => "W",
+        Level::Info => "I",
+        Level::Debug => "D",
+        Level::Trace => "V",
+    }
+}
+
+/// Word-wraps the text into lines of at most `width` characters
+///
+/// Words longer than `width` are broken up, and line breaks in the text are preserved.
+pub fn wrap(text: &str, width: usize) -> Vec<String> {
+    let width = width.max(1);
+
+    let mut lines = Vec::new();
+
+    for paragraph in text.lines() {
+        let mut line = String::new();
+        let mut line_len = 0;
+
+        for word in paragraph.split_whitespace() {
+            let mut word: Vec<char> = word.chars().collect();
+
+            if line_len > 0 && line_len + 1 + word.len() <= width {
+                line.push(' ');
+                line.extend(word.iter());
+                line_len += 1 + word.len();
+
+                continue;
+            }
+
+            if line_len > 0 {
+                lines.push(core::mem::take(&mut line));
+            }
+
+            while word.len() > width {
+                lines.push(word.drain(..width).collect());
+            }
+
+            line_len = word.len();
+            line.extend(word);
+        }
+
+        lines.push(line);
+    }
+
+    if lines.is_empty() {
+        lines.push(String::new());
+    }
+
+    lines
+}
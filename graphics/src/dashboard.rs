@@ -0,0 +1,253 @@
+//! The status dashboard, see the `dashboard` module of the demo for the renderer thread
+//!
+//! The layout adapts to the size of the display and the palette to its color depth.
+
+use core::fmt;
+use core::time::Duration;
+
+use std::net::Ipv4Addr;
+
+use embedded_graphics::mono_font::{ascii::*, MonoFont, MonoTextStyleBuilder};
+use embedded_graphics::pixelcolor::*;
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::*;
+use embedded_graphics::text::*;
+
+#[derive(Clone, Debug, Default)]
+pub struct Snapshot {
+    pub ip: Option<Ipv4Addr>,
+    pub rssi: Option<i8>,
+    /// Wall clock time since the UNIX epoch, if synced via SNTP
+    pub time: Option<Duration>,
+    pub uptime: Duration,
+    pub adc_mv: Option<u16>,
+}
+
+impl Snapshot {
+    pub(crate) fn lines(&self) -> [(&'static str, String); 5] {
+        let secs = self.uptime.as_secs();
+
+        let uptime = if secs >= 86400 {
+            format!("{}d {}", secs / 86400, Hms(secs % 86400))
+        } else {
+            Hms(secs).to_string()
+        };
+
+        [
+            ("IP", or_dash(self.ip)),
+            ("RSSI", or_dash(self.rssi.map(|dbm| format!("{} dBm", dbm)))),
+            (
+                "Time",
+                or_dash(
+                    self.time
+                        .map(|time| format!("{} UTC", Hms(time.as_secs() % 86400))),
+                ),
+            ),
+            ("Up", uptime),
+            ("ADC", or_dash(self.adc_mv.map(|mv| format!("{} mV", mv)))),
+        ]
+    }
+}
+
+fn or_dash(value: Option<impl fmt::Display>) -> String {
+    value
+        .map(|value| value.to_string())
+        .unwrap_or_else(|| "-".into())
+}
+
+struct Hms(u64);
+
+impl fmt::Display for Hms {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:02}:{:02}:{:02}",
+            self.0 / 3600,
+            self.0 / 60 % 60,
+            self.0 % 60
+        )
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Palette<C> {
+    pub background: C,
+    pub foreground: C,
+    pub label: C,
+    pub header_background: C,
+    pub header_foreground: C,
+}
+
+impl<C> Palette<C>
+where
+    C: RgbColor,
+{
+    pub fn rgb() -> Self {
+        Self {
+            background: C::BLACK,
+            foreground: C::WHITE,
+            label: C::CYAN,
+            header_background: C::BLUE,
+            header_foreground: C::YELLOW,
+        }
+    }
+}
+
+impl Palette<BinaryColor> {
+    pub fn binary() -> Self {
+        Self {
+            background: BinaryColor::Off,
+            foreground: BinaryColor::On,
+            label: BinaryColor::On,
+            header_background: BinaryColor::On,
+            header_foreground: BinaryColor::Off,
+        }
+    }
+}
+
+const HEADER: &str = "Status";
+
+// Width of the label column, in characters
+const LABEL_CHARS: usize = 5;
+// Width of the longest value ("255.255.255.255"), in characters
+const VALUE_CHARS: usize = 15;
+
+// Largest first
+const FONTS: &[&MonoFont<'static>] = &[&FONT_10X20, &FONT_9X15, &FONT_7X13, &FONT_6X10];
+
+#[derive(Copy, Clone)]
+struct Layout {
+    font: &'static MonoFont<'static>,
+    // Labels and values on separate lines, for narrow displays
+    stacked: bool,
+    line_height: u32,
+}
+
+impl Layout {
+    fn fit(size: Size, lines: usize) -> Self {
+        let candidates = FONTS.iter().flat_map(|font| {
+            [false, true].into_iter().map(move |stacked| {
+                let (cols, rows) = if stacked {
+                    (VALUE_CHARS, 1 + lines * 2)
+                } else {
+                    (LABEL_CHARS + VALUE_CHARS, 1 + lines)
+                };
+
+                (font, stacked, cols as u32, rows as u32)
+            })
+        });
+
+        for (font, stacked, cols, rows) in candidates {
+            let char_size = font.character_size;
+
+            if cols * char_size.width <= size.width && rows * char_size.height <= size.height {
+                // Distribute the vertical slack as line spacing, but keep the lines together
+                let spacing = ((size.height - rows * char_size.height) / rows).min(4);
+
+                return Self {
+                    font,
+                    stacked,
+                    line_height: char_size.height + spacing,
+                };
+            }
+        }
+
+        // Too small for anything; render with the smallest font and let the display crop
+        Self {
+            font: FONTS[FONTS.len() - 1],
+            stacked: false,
+            line_height: FONTS[FONTS.len() - 1].character_size.height,
+        }
+    }
+}
+
+pub struct Dashboard<C> {
+    palette: Palette<C>,
+    cleared: bool,
+}
+
+impl<C> Dashboard<C>
+where
+    C: PixelColor,
+{
+    pub fn new(palette: Palette<C>) -> Self {
+        Self {
+            palette,
+            cleared: false,
+        }
+    }
+
+    /// Renders the snapshot
+    ///
+    /// Only the first call clears the display; subsequent calls overdraw the previous values
+    /// including their background, which avoids flicker on displays without a frame buffer.
+    pub fn draw<D>(&mut self, display: &mut D, snapshot: &Snapshot) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = C>,
+    {
+        let fresh = !self.cleared;
+
+        if fresh {
+            display.clear(self.palette.background)?;
+            self.cleared = true;
+        }
+
+        let bbox = display.bounding_box();
+        let lines = snapshot.lines();
+        let layout = Layout::fit(bbox.size, lines.len());
+
+        let char_width = layout.font.character_size.width as i32;
+
+        let text = |color, background| {
+            MonoTextStyleBuilder::new()
+                .font(layout.font)
+                .text_color(color)
+                .background_color(background)
+                .build()
+        };
+
+        let header = text(
+            self.palette.header_foreground,
+            self.palette.header_background,
+        );
+        let label = text(self.palette.label, self.palette.background);
+        let value = text(self.palette.foreground, self.palette.background);
+
+        let mut position = bbox.top_left;
+
+        // The header never changes, so only draw it over the cleared display
+        if fresh {
+            Rectangle::new(position, Size::new(bbox.size.width, layout.line_height))
+                .into_styled(PrimitiveStyle::with_fill(self.palette.header_background))
+                .draw(display)?;
+
+            Text::with_baseline(HEADER, position, header, Baseline::Top).draw(display)?;
+        }
+
+        position.y += layout.line_height as i32;
+
+        for (name, val) in &lines {
+            Text::with_baseline(name, position, label, Baseline::Top).draw(display)?;
+
+            let value_position = if layout.stacked {
+                position.y += layout.line_height as i32;
+                position
+            } else {
+                position + Point::new(LABEL_CHARS as i32 * char_width, 0)
+            };
+
+            // Pad to the full width, so that the remainder of a previous, longer value is erased
+            Text::with_baseline(
+                &format!("{:<width$}", val, width = VALUE_CHARS),
+                value_position,
+                value,
+                Baseline::Top,
+            )
+            .draw(display)?;
+
+            position.y += layout.line_height as i32;
+        }
+
+        Ok(())
+    }
+}
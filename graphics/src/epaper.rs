@@ -0,0 +1,125 @@
+//! Deciding how to refresh an e-paper panel, given the previous and the new frame
+//!
+//! A full refresh flashes the whole panel for seconds, while a partial refresh of the changed
+//! window is quick but leaves ghosting behind. So small changes are refreshed partially, and
+//! every so often a full refresh clears the accumulated ghosting.
+//!
+//! Frames are 1 bpp buffers, packed 8 pixels per byte with the MSB first, as used by the
+//! `epd-waveshare` displays.
+
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::Rectangle;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Refresh {
+    /// The frame did not change
+    None,
+    Full,
+    /// Only the window changed; its horizontal edges are aligned to bytes
+    Partial(Rectangle),
+}
+
+pub struct RefreshPolicy {
+    size: Size,
+    full_every: u32,
+    partial_since_full: u32,
+    previous: Option<Vec<u8>>,
+}
+
+impl RefreshPolicy {
+    /// Does a full refresh for the first frame, and after `full_every` partial refreshes
+    pub fn new(size: Size, full_every: u32) -> Self {
+        Self {
+            size,
+            full_every,
+            partial_since_full: 0,
+            previous: None,
+        }
+    }
+
+    /// Decides how to refresh the panel with the frame, and remembers it as the one on the panel
+    pub fn next(&mut self, frame: &[u8]) -> Refresh {
+        let refresh = match &self.previous {
+            None => Refresh::Full,
+            Some(previous) => match dirty_window(previous, frame, self.size) {
+                None => Refresh::None,
+                Some(_) if self.partial_since_full >= self.full_every => Refresh::Full,
+                Some(window) => Refresh::Partial(window),
+            },
+        };
+
+        match refresh {
+            Refresh::None => (),
+            Refresh::Full => self.partial_since_full = 0,
+            Refresh::Partial(_) => self.partial_since_full += 1,
+        }
+
+        if refresh != Refresh::None {
+            self.previous = Some(frame.to_vec());
+        }
+
+        refresh
+    }
+
+    /// Forces a full refresh of the next frame, e.g. because the panel lost its memory
+    pub fn reset(&mut self) {
+        self.previous = None;
+    }
+}
+
+/// The smallest window containing all pixels which differ between the two frames
+///
+/// The window is widened to whole bytes, as partial refreshes can only address those.
+pub fn dirty_window(previous: &[u8], current: &[u8], size: Size) -> Option<Rectangle> {
+    let stride = stride(size);
+
+    let mut min = (usize::MAX, usize::MAX);
+    let mut max = (0, 0);
+
+    let rows = previous.chunks(stride).zip(current.chunks(stride));
+
+    for (y, (previous, current)) in rows.enumerate() {
+        let changed = |x: &usize| previous[*x] != current[*x];
+
+        let mut bytes = 0..previous.len().min(current.len());
+
+        if let Some(first) = bytes.find(changed) {
+            let last = bytes.rfind(changed).unwrap_or(first);
+
+            min = (min.0.min(first), min.1.min(y));
+            max = (max.0.max(last), max.1.max(y));
+        }
+    }
+
+    (min.1 != usize::MAX).then(|| {
+        Rectangle::with_corners(
+            Point::new(min.0 as i32 * 8, min.1 as i32),
+            Point::new(
+                (max.0 as i32 * 8 + 7).min(size.width as i32 - 1),
+                max.1 as i32,
+            ),
+        )
+    })
+}
+
+/// Copies the window, as returned by `dirty_window`, out of the frame
+///
+/// The rows of the window are padded to whole bytes.
+pub fn window(frame: &[u8], size: Size, window: &Rectangle) -> Vec<u8> {
+    let stride = stride(size);
+
+    let first = window.top_left.x as usize / 8;
+    let bytes = (window.size.width as usize).div_ceil(8);
+
+    frame
+        .chunks(stride)
+        .skip(window.top_left.y as usize)
+        .take(window.size.height as usize)
+        .flat_map(|row| &row[first..first + bytes])
+        .copied()
+        .collect()
+}
+
+fn stride(size: Size) -> usize {
+    (size.width as usize).div_ceil(8)
+}
@@ -0,0 +1,57 @@
+//! The `Hello Rust!` greeting drawn by the board functions at boot
+
+use log::*;
+
+use embedded_graphics::mono_font::{ascii::FONT_10X20, MonoTextStyle};
+use embedded_graphics::pixelcolor::*;
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::*;
+use embedded_graphics::text::*;
+
+pub fn led_draw<D>(display: &mut D) -> Result<(), D::Error>
+where
+    D: DrawTarget + Dimensions,
+    D::Color: RgbColor,
+{
+    led_draw_custom(
+        display,
+        RgbColor::BLACK,
+        RgbColor::WHITE,
+        RgbColor::BLUE,
+        RgbColor::YELLOW,
+    )
+}
+
+pub fn led_draw_custom<D>(
+    display: &mut D,
+    bg: D::Color,
+    fg: D::Color,
+    fill: D::Color,
+    stroke: D::Color,
+) -> Result<(), D::Error>
+where
+    D: DrawTarget + Dimensions,
+{
+    display.clear(bg)?;
+
+    Rectangle::new(display.bounding_box().top_left, display.bounding_box().size)
+        .into_styled(
+            PrimitiveStyleBuilder::new()
+                .fill_color(fill)
+                .stroke_color(stroke)
+                .stroke_width(1)
+                .build(),
+        )
+        .draw(display)?;
+
+    Text::new(
+        "Hello Rust!",
+        Point::new(10, (display.bounding_box().size.height - 10) as i32 / 2),
+        MonoTextStyle::new(&FONT_10X20, fg),
+    )
+    .draw(display)?;
+
+    info!("LED rendering done");
+
+    Ok(())
+}
@@ -0,0 +1,25 @@
+//! The drawing code of the demo, and the other parts of it which can be tested on the host
+//!
+//! Only depends on `embedded-graphics` and not on ESP-IDF, so that it can be built and
+//! snapshot-tested on the host, using the in-memory display of the `sim` module:
+//! `cd graphics && cargo test`
+
+pub mod adc_cal;
+pub mod bmp;
+pub mod chart;
+pub mod console;
+pub mod dashboard;
+pub mod epaper;
+pub mod hello;
+pub mod message;
+pub mod mirror;
+pub mod picture;
+pub mod power;
+pub mod qr;
+pub mod screen;
+pub mod sim;
+#[cfg(feature = "slint")]
+pub mod ui;
+
+pub use hello::{led_draw, led_draw_custom};
+pub use screen::{Frame, Page, Screen, Theme};
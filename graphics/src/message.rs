@@ -0,0 +1,309 @@
+//! Text messages shown on the display for a while, e.g. as received over MQTT
+//!
+//! A message is either plain text, or JSON like
+//! `{"text": "Hello!", "size": "large", "color": "#ff8000", "duration": 30}`, where all fields
+//! but `text` are optional and `duration` is in seconds.
+
+use core::fmt;
+use core::time::Duration;
+
+use serde::Deserialize;
+
+use embedded_graphics::mono_font::ascii::{FONT_10X20, FONT_6X10, FONT_8X13};
+use embedded_graphics::mono_font::{MonoFont, MonoTextStyle};
+use embedded_graphics::pixelcolor::*;
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::*;
+use embedded_graphics::text::{Alignment, Baseline, Text, TextStyleBuilder};
+
+pub const DEFAULT_DURATION: Duration = Duration::from_secs(10);
+pub const MAX_DURATION: Duration = Duration::from_secs(3600);
+
+// Longer texts would not fit on any of the displays anyway
+pub const MAX_TEXT_LEN: usize = 512;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FontSize {
+    Small,
+    #[default]
+    Medium,
+    Large,
+}
+
+impl FontSize {
+    fn font(&self) -> &'static MonoFont<'static> {
+        match self {
+            Self::Small => &FONT_6X10,
+            Self::Medium => &FONT_8X13,
+            Self::Large => &FONT_10X20,
+        }
+    }
+
+    fn smaller(&self) -> Option<Self> {
+        match self {
+            Self::Small => None,
+            Self::Medium => Some(Self::Small),
+            Self::Large => Some(Self::Medium),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Message {
+    pub text: String,
+    pub size: FontSize,
+    /// The color of the text; only honored on color displays
+    pub color: Option<Rgb888>,
+    /// How long the message stays on the display
+    pub duration: Duration,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Json {
+    text: String,
+    #[serde(default)]
+    size: FontSize,
+    color: Option<String>,
+    duration: Option<f32>,
+}
+
+impl Message {
+    pub fn new(text: impl Into<String>) -> Self {
+        Self {
+            text: text.into(),
+            size: FontSize::default(),
+            color: None,
+            duration: DEFAULT_DURATION,
+        }
+    }
+
+    /// Parses a JSON object as described in the module documentation, or else plain text
+    pub fn parse(payload: &[u8]) -> Result<Self, MessageError> {
+        let payload = core::str::from_utf8(payload).map_err(|_| MessageError::NotUtf8)?;
+        let trimmed = payload.trim();
+
+        let message = if trimmed.starts_with('{') {
+            let json: Json = serde_json::from_str(trimmed)
+                .map_err(|err| MessageError::Invalid(err.to_string()))?;
+
+            let duration = match json.duration {
+                Some(secs) if secs.is_finite() && secs > 0.0 => {
+                    Duration::from_secs_f32(secs.min(MAX_DURATION.as_secs_f32()))
+                }
+                Some(_) => return Err(MessageError::Invalid("Invalid duration".into())),
+                None => DEFAULT_DURATION,
+            };
+
+            Self {
+                text: json.text,
+                size: json.size,
+                color: json.color.as_deref().map(parse_color).transpose()?,
+                duration,
+            }
+        } else {
+            Self::new(trimmed)
+        };
+
+        if message.text.trim().is_empty() {
+            Err(MessageError::Empty)
+        } else if message.text.len() > MAX_TEXT_LEN {
+            Err(MessageError::TooLong)
+        } else {
+            Ok(message)
+        }
+    }
+}
+
+/// `#rrggbb`, or `rrggbb`
+fn parse_color(color: &str) -> Result<Rgb888, MessageError> {
+    let hex = color.strip_prefix('#').unwrap_or(color);
+
+    let rgb = (hex.len() == 6)
+        .then(|| u32::from_str_radix(hex, 16).ok())
+        .flatten()
+        .ok_or_else(|| MessageError::Invalid(format!("Invalid color {color}")))?;
+
+    Ok(Rgb888::new((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8))
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MessageError {
+    NotUtf8,
+    Empty,
+    TooLong,
+    Invalid(String),
+}
+
+impl fmt::Display for MessageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotUtf8 => write!(f, "The message is not UTF-8"),
+            Self::Empty => write!(f, "The message is empty"),
+            Self::TooLong => write!(f, "Messages are limited to {MAX_TEXT_LEN} bytes"),
+            Self::Invalid(err) => write!(f, "Invalid message: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for MessageError {}
+
+#[derive(Copy, Clone, Debug)]
+pub struct MessageStyle<C> {
+    pub background: C,
+    /// Also the color of the border
+    pub text: C,
+    /// How the color of the message is converted; without one, the message color is ignored
+    pub convert: Option<fn(Rgb888) -> C>,
+}
+
+impl<C> MessageStyle<C>
+where
+    C: RgbColor + From<Rgb888>,
+{
+    pub fn rgb() -> Self {
+        Self {
+            background: C::BLACK,
+            text: C::WHITE,
+            convert: Some(C::from),
+        }
+    }
+}
+
+impl MessageStyle<BinaryColor> {
+    /// For displays where `BinaryColor::On` is a lit pixel, like the SSD1306 OLEDs
+    pub fn binary() -> Self {
+        Self {
+            background: BinaryColor::Off,
+            text: BinaryColor::On,
+            convert: None,
+        }
+    }
+}
+
+pub struct MessageView<C> {
+    style: MessageStyle<C>,
+    drawn: bool,
+}
+
+impl<C> MessageView<C>
+where
+    C: PixelColor,
+{
+    pub fn new(style: MessageStyle<C>) -> Self {
+        Self {
+            style,
+            drawn: false,
+        }
+    }
+
+    /// Renders the message framed like the `Hello Rust!` greeting, centered and word-wrapped
+    ///
+    /// If the text does not fit with its font, smaller fonts are tried, and with the smallest
+    /// one the text is cut off. The display is only drawn on the first call.
+    pub fn draw<D>(&mut self, display: &mut D, message: &Message) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = C>,
+    {
+        if self.drawn {
+            return Ok(());
+        }
+
+        let bbox = display.bounding_box();
+
+        display.clear(self.style.background)?;
+
+        bbox.into_styled(
+            PrimitiveStyleBuilder::new()
+                .fill_color(self.style.background)
+                .stroke_color(self.style.text)
+                .stroke_width(1)
+                .build(),
+        )
+        .draw(display)?;
+
+        // Inside the border, with a pixel of spacing
+        let area = bbox.offset(-2);
+
+        let mut size = message.size;
+
+        let lines = loop {
+            let font = size.font();
+
+            let columns = (area.size.width / font.character_size.width).max(1) as usize;
+            let rows = (area.size.height / font.character_size.height).max(1) as usize;
+
+            let lines = wrap(&message.text, columns);
+
+            match size.smaller() {
+                Some(smaller) if lines.len() > rows => size = smaller,
+                _ => break lines.into_iter().take(rows).collect::<Vec<_>>(),
+            }
+        };
+
+        let font = size.font();
+
+        let color = match (message.color, self.style.convert) {
+            (Some(color), Some(convert)) => convert(color),
+            _ => self.style.text,
+        };
+
+        let height = font.character_size.height * lines.len() as u32;
+        let mut position = Point::new(
+            area.center().x,
+            area.center().y - height as i32 / 2 + font.baseline as i32,
+        );
+
+        let character_style = MonoTextStyle::new(font, color);
+        let text_style = TextStyleBuilder::new()
+            .alignment(Alignment::Center)
+            .baseline(Baseline::Alphabetic)
+            .build();
+
+        for line in lines {
+            Text::with_text_style(&line, position, character_style, text_style).draw(display)?;
+
+            position.y += font.character_size.height as i32;
+        }
+
+        self.drawn = true;
+
+        Ok(())
+    }
+}
+
+/// Splits the text into lines of at most `columns` characters, at whitespace where possible
+pub fn wrap(text: &str, columns: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+
+    for paragraph in text.lines() {
+        let mut line = String::new();
+
+        for word in paragraph.split_whitespace() {
+            let mut word = word.chars().collect::<Vec<_>>();
+
+            let len = line.chars().count();
+
+            if len > 0 && len + 1 + word.len() > columns {
+                lines.push(core::mem::take(&mut line));
+            } else if len > 0 {
+                line.push(' ');
+            }
+
+            // Words longer than a line are broken wherever the line ends
+            while line.chars().count() + word.len() > columns {
+                let split = columns - line.chars().count();
+
+                line.extend(word.drain(..split));
+                lines.push(core::mem::take(&mut line));
+            }
+
+            line.extend(word);
+        }
+
+        lines.push(line);
+    }
+
+    lines
+}
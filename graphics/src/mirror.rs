@@ -0,0 +1,355 @@
+//! A draw target which keeps an in-memory copy of everything drawn to the display
+//!
+//! Panels are write-only, so this is the only way to know what they show, e.g. for screenshots.
+//! The mirror also tracks the area which changed, for streaming the display's content live.
+//!
+//! The mirror is either drawn through `Mirrored`, alongside the display, or drawn into directly as
+//! the back buffer of the display, whose changed areas are then copied to the display by `flush()`.
+//!
+//! The pixels are stored packed - one bit per pixel for the monochrome displays - as the mirror
+//! lives on the heap of the device for as long as the display.
+
+use core::convert::Infallible;
+use core::marker::PhantomData;
+
+use std::collections::TryReserveError;
+use std::sync::Mutex;
+
+use embedded_graphics::pixelcolor::raw::RawU16;
+use embedded_graphics::pixelcolor::*;
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::Rectangle;
+
+use crate::bmp::{self, BmpColor};
+use crate::sim::Framebuffer;
+
+/// The colors which the mirror can store, in as few bits as they need
+pub trait PackedColor: PixelColor {
+    const BITS_PER_PIXEL: usize;
+
+    fn unpack(data: &[u8], index: usize) -> Self;
+
+    fn pack(self, data: &mut [u8], index: usize);
+}
+
+impl PackedColor for BinaryColor {
+    const BITS_PER_PIXEL: usize = 1;
+
+    fn unpack(data: &[u8], index: usize) -> Self {
+        (data[index / 8] & (0x80 >> (index % 8)) != 0).into()
+    }
+
+    fn pack(self, data: &mut [u8], index: usize) {
+        let mask = 0x80 >> (index % 8);
+
+        if self.is_on() {
+            data[index / 8] |= mask;
+        } else {
+            data[index / 8] &= !mask;
+        }
+    }
+}
+
+impl PackedColor for Rgb565 {
+    const BITS_PER_PIXEL: usize = 16;
+
+    fn unpack(data: &[u8], index: usize) -> Self {
+        RawU16::new(u16::from_le_bytes([data[index * 2], data[index * 2 + 1]])).into()
+    }
+
+    fn pack(self, data: &mut [u8], index: usize) {
+        data[index * 2..index * 2 + 2].copy_from_slice(&self.into_storage().to_le_bytes());
+    }
+}
+
+pub struct Mirror<C> {
+    size: Size,
+    data: Vec<u8>,
+    dirty: Option<Rectangle>,
+    // Like `dirty`, but since the previous flush to the display
+    unflushed: Option<Rectangle>,
+    _color: PhantomData<C>,
+}
+
+impl<C> Mirror<C>
+where
+    C: PackedColor,
+{
+    /// The mirror should be of the size of the display
+    ///
+    /// All of it is initially unflushed, as what the display shows at that time is unknown.
+    pub fn new(size: Size, background: C) -> Self {
+        Self::try_new(size, background).unwrap()
+    }
+
+    /// Like `new`, but fails instead of aborting if the heap has no room for the mirror
+    pub fn try_new(size: Size, background: C) -> Result<Self, TryReserveError> {
+        let mut data = Vec::new();
+        data.try_reserve_exact(Self::bytes(size))?;
+        data.resize(Self::bytes(size), 0);
+
+        for index in 0..(size.width * size.height) as usize {
+            background.pack(&mut data, index);
+        }
+
+        Ok(Self {
+            size,
+            data,
+            dirty: None,
+            unflushed: Some(Rectangle::new(Point::zero(), size))
+                .filter(|area| !area.is_zero_sized()),
+            _color: PhantomData,
+        })
+    }
+
+    /// The memory taken by the pixels of a mirror of the given size
+    pub fn bytes(size: Size) -> usize {
+        (size.width as usize * size.height as usize * C::BITS_PER_PIXEL).div_ceil(8)
+    }
+
+    pub fn pixel(&self, point: Point) -> Option<C> {
+        self.index(point).map(|index| C::unpack(&self.data, index))
+    }
+
+    /// A copy of the mirrored frame
+    pub fn to_framebuffer(&self) -> Framebuffer<C> {
+        let mut frame = Framebuffer::new(self.size, C::unpack(&self.data, 0));
+
+        frame
+            .fill_contiguous(
+                &self.bounding_box(),
+                (0..(self.size.width * self.size.height) as usize)
+                    .map(|index| C::unpack(&self.data, index)),
+            )
+            .unwrap();
+
+        frame
+    }
+
+    /// The smallest rectangle containing all pixels changed since the previous call
+    pub fn take_dirty(&mut self) -> Option<Rectangle> {
+        self.dirty.take()
+    }
+
+    /// The smallest rectangle containing all pixels changed since the previous call
+    pub fn take_unflushed(&mut self) -> Option<Rectangle> {
+        self.unflushed.take()
+    }
+
+    fn index(&self, point: Point) -> Option<usize> {
+        let (x, y) = (point.x as u32, point.y as u32);
+
+        (point.x >= 0 && point.y >= 0 && x < self.size.width && y < self.size.height)
+            .then(|| (y * self.size.width + x) as usize)
+    }
+
+    fn set(&mut self, point: Point, color: C) {
+        let Some(index) = self.index(point) else {
+            return;
+        };
+
+        if C::unpack(&self.data, index) == color {
+            return;
+        }
+
+        color.pack(&mut self.data, index);
+
+        let pixel = Rectangle::new(point, Size::new(1, 1));
+
+        for area in [&mut self.dirty, &mut self.unflushed] {
+            *area = Some(match area {
+                Some(area) => union(area, &pixel),
+                None => pixel,
+            });
+        }
+    }
+}
+
+/// Drawing into the mirror only, as the back buffer of the display
+impl<C> DrawTarget for Mirror<C>
+where
+    C: PackedColor,
+{
+    type Color = C;
+    type Error = Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        for Pixel(point, color) in pixels {
+            self.set(point, color);
+        }
+
+        Ok(())
+    }
+}
+
+impl<C> OriginDimensions for Mirror<C> {
+    fn size(&self) -> Size {
+        self.size
+    }
+}
+
+/// Copies the area of the mirror changed since the previous flush to the display
+///
+/// The area is copied in strips of at most `strip_pixels`, and the mirror is only locked while
+/// copying a strip out of it, so drawing into the mirror does not wait for a slow display.
+/// Returns the flushed area, if anything changed.
+pub fn flush<D>(
+    mirror: &Mutex<Mirror<D::Color>>,
+    display: &mut D,
+    strip_pixels: usize,
+) -> Result<Option<Rectangle>, D::Error>
+where
+    D: DrawTarget,
+    D::Color: PackedColor,
+{
+    let Some(area) = mirror.lock().unwrap().take_unflushed() else {
+        return Ok(None);
+    };
+
+    let rows = (strip_pixels / area.size.width as usize).max(1) as u32;
+
+    let mut strip = Vec::with_capacity((rows * area.size.width) as usize);
+
+    for top in (0..area.size.height).step_by(rows as usize) {
+        let band = Rectangle::new(
+            area.top_left + Point::new(0, top as i32),
+            Size::new(area.size.width, rows.min(area.size.height - top)),
+        );
+
+        strip.clear();
+
+        {
+            let mirror = mirror.lock().unwrap();
+
+            strip.extend(band.points().map(|point| mirror.pixel(point).unwrap()));
+        }
+
+        display.fill_contiguous(&band, strip.iter().copied())?;
+    }
+
+    Ok(Some(area))
+}
+
+/// Writes the area of the mirror as a BMP file, piece by piece
+///
+/// Like `flush()`, the mirror is only locked while copying a row out of it, so drawing into the
+/// mirror does not wait for a slow client.
+pub fn write_bmp<C, E>(
+    mirror: &Mutex<Mirror<C>>,
+    area: &Rectangle,
+    out: impl FnMut(&[u8]) -> Result<(), E>,
+) -> Result<(), E>
+where
+    C: PackedColor + BmpColor,
+{
+    bmp::encode_rows::<C, E>(
+        area.size,
+        |y, row| {
+            let mirror = mirror.lock().unwrap();
+
+            C::encode_row(
+                (0..area.size.width as i32)
+                    .map(|x| mirror.pixel(area.top_left + Point::new(x, y)).unwrap()),
+                row,
+            );
+        },
+        out,
+    )
+}
+
+/// The smallest rectangle containing both, which must not be empty
+pub fn union(a: &Rectangle, b: &Rectangle) -> Rectangle {
+    Rectangle::with_corners(
+        a.top_left.component_min(b.top_left),
+        a.bottom_right()
+            .unwrap()
+            .component_max(b.bottom_right().unwrap()),
+    )
+}
+
+pub struct Mirrored<'a, D>
+where
+    D: DrawTarget,
+{
+    display: &'a mut D,
+    mirror: &'a mut Mirror<D::Color>,
+}
+
+impl<'a, D> Mirrored<'a, D>
+where
+    D: DrawTarget,
+    D::Color: PackedColor,
+{
+    pub fn new(display: &'a mut D, mirror: &'a mut Mirror<D::Color>) -> Self {
+        Self { display, mirror }
+    }
+}
+
+impl<D> DrawTarget for Mirrored<'_, D>
+where
+    D: DrawTarget,
+    D::Color: PackedColor,
+{
+    type Color = D::Color;
+    type Error = D::Error;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        let mirror = &mut *self.mirror;
+
+        self.display.draw_iter(
+            pixels
+                .into_iter()
+                .inspect(|Pixel(point, color)| mirror.set(*point, *color)),
+        )
+    }
+
+    // Overridden, so that the faster implementations of the display are still used
+
+    fn fill_contiguous<I>(&mut self, area: &Rectangle, colors: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Self::Color>,
+    {
+        let mirror = &mut *self.mirror;
+        let mut points = area.points();
+
+        self.display.fill_contiguous(
+            area,
+            colors.into_iter().inspect(|color| {
+                if let Some(point) = points.next() {
+                    mirror.set(point, *color);
+                }
+            }),
+        )
+    }
+
+    fn fill_solid(&mut self, area: &Rectangle, color: Self::Color) -> Result<(), Self::Error> {
+        for point in area.points() {
+            self.mirror.set(point, color);
+        }
+
+        self.display.fill_solid(area, color)
+    }
+
+    fn clear(&mut self, color: Self::Color) -> Result<(), Self::Error> {
+        for point in self.mirror.bounding_box().points() {
+            self.mirror.set(point, color);
+        }
+
+        self.display.clear(color)
+    }
+}
+
+impl<D> Dimensions for Mirrored<'_, D>
+where
+    D: DrawTarget,
+{
+    fn bounding_box(&self) -> Rectangle {
+        self.display.bounding_box()
+    }
+}
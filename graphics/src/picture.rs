@@ -0,0 +1,571 @@
+//! Pictures uploaded to the device, decoded for the display they are shown on
+//!
+//! BMP (uncompressed, 1 to 32 bits per pixel) and PNG files are supported. While decoding, the
+//! picture is scaled to fit the display with its aspect ratio preserved, and converted to the
+//! colors of the display - on monochrome displays with Floyd-Steinberg dithering - so that only
+//! the packed pixels of the display are kept: 15KB for the 400x300 e-paper display.
+
+use core::fmt;
+
+use embedded_graphics::mono_font::{ascii::FONT_6X10, MonoTextStyle};
+use embedded_graphics::pixelcolor::*;
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::*;
+use embedded_graphics::text::*;
+
+use std::sync::Arc;
+
+use crate::mirror::PackedColor;
+
+/// The largest supported picture
+///
+/// Matches the resolution of the largest supported display, the Waveshare 4.2" e-paper. Only the
+/// rows of a picture are decoded one by one, except for interlaced PNGs, which have to fit in RAM.
+pub const MAX_PIXELS: u32 = 400 * 300;
+
+// The `BITMAPV5HEADER`, the largest of the BMP headers
+const MAX_BMP_HEADER_SIZE: usize = 124;
+
+#[derive(Clone, PartialEq, Eq)]
+pub struct Picture {
+    size: Size,
+    bits_per_pixel: usize,
+    // Row by row, packed as by the `PackedColor` of the display
+    data: Vec<u8>,
+}
+
+impl Picture {
+    /// Decodes a BMP or a PNG file, depending on its signature, for a display of the size `area`
+    ///
+    /// Transparent PNG pixels are blended over white.
+    pub fn decode<C>(data: &[u8], area: Size, mode: ColorMode<C>) -> Result<Self, DecodeError>
+    where
+        C: PackedColor,
+    {
+        if data.starts_with(b"BM") {
+            decode_bmp(data, area, mode)
+        } else if data.starts_with(b"\x89PNG") {
+            decode_png(data, area, mode)
+        } else {
+            Err(DecodeError::UnknownFormat)
+        }
+    }
+
+    /// The size of the picture, as scaled to fit the display
+    pub fn size(&self) -> Size {
+        self.size
+    }
+
+    /// The pixels, row by row, or `None` if the picture was decoded for other colors than `C`
+    pub fn pixels<C>(&self) -> Option<impl Iterator<Item = C> + '_>
+    where
+        C: PackedColor,
+    {
+        (C::BITS_PER_PIXEL == self.bits_per_pixel).then(|| {
+            (0..self.size.width as usize * self.size.height as usize)
+                .map(|index| C::unpack(&self.data, index))
+        })
+    }
+
+    /// Scales the picture of size `src` to fit `area`, and converts it to the colors of `mode`
+    ///
+    /// `read_row` decodes the pixels at the given columns of a row of the picture. It is called
+    /// for each row at most once, from top to bottom.
+    fn scaled<C, R>(
+        src: Size,
+        area: Size,
+        mode: ColorMode<C>,
+        mut read_row: R,
+    ) -> Result<Self, DecodeError>
+    where
+        C: PackedColor,
+        R: FnMut(u32, &[u32], &mut Vec<Rgb888>) -> Result<(), DecodeError>,
+    {
+        // The largest size with the aspect ratio of the picture which fits the area
+        let size = if src.width as u64 * area.height as u64 > src.height as u64 * area.width as u64
+        {
+            Size::new(
+                area.width,
+                (src.height as u64 * area.width as u64 / src.width as u64).max(1) as u32,
+            )
+        } else {
+            Size::new(
+                (src.width as u64 * area.height as u64 / src.height as u64).max(1) as u32,
+                area.height,
+            )
+        };
+
+        let len = (size.width as usize * size.height as usize * C::BITS_PER_PIXEL).div_ceil(8);
+
+        let mut data = try_vec(len)?;
+        data.resize(len, 0);
+
+        // Nearest-neighbour sampling at the centers of the display pixels
+        let sample = |x: u32, size: u32, src: u32| {
+            ((2 * x + 1) as u64 * src as u64 / (2 * size as u64)) as u32
+        };
+
+        let columns = (0..size.width)
+            .map(|x| sample(x, size.width, src.width))
+            .collect::<Vec<_>>();
+
+        let mut row = Vec::with_capacity(size.width as usize);
+        let mut row_y = None;
+
+        // The quantization error diffused to the current and to the next row
+        let mut errors = vec![0_i16; size.width as usize + 2];
+        let mut next_errors = vec![0_i16; size.width as usize + 2];
+
+        for y in 0..size.height {
+            let src_y = sample(y, size.height, src.height);
+
+            // Rows are repeated when enlarging the picture
+            if row_y != Some(src_y) {
+                row.clear();
+                read_row(src_y, &columns, &mut row)?;
+
+                row_y = Some(src_y);
+            }
+
+            let start = y as usize * size.width as usize;
+
+            match mode {
+                ColorMode::Convert(convert) => {
+                    for (x, color) in row.iter().enumerate() {
+                        convert(*color).pack(&mut data, start + x);
+                    }
+                }
+                ColorMode::Dither { dark, light } => {
+                    next_errors.fill(0);
+
+                    for (x, color) in row.iter().enumerate() {
+                        // Errors are offset by one, so that the left neighbour of the first pixel exists
+                        let i = x + 1;
+
+                        let value = luma(*color) + errors[i];
+                        let (color, error) = if value >= 128 {
+                            (light, value - 255)
+                        } else {
+                            (dark, value)
+                        };
+
+                        errors[i + 1] += error * 7 / 16;
+                        next_errors[i - 1] += error * 3 / 16;
+                        next_errors[i] += error * 5 / 16;
+                        next_errors[i + 1] += error / 16;
+
+                        color.pack(&mut data, start + x);
+                    }
+
+                    core::mem::swap(&mut errors, &mut next_errors);
+                }
+            }
+        }
+
+        Ok(Self {
+            size,
+            bits_per_pixel: C::BITS_PER_PIXEL,
+            data,
+        })
+    }
+}
+
+// The pixels are of no interest when debugging a `Frame`
+impl fmt::Debug for Picture {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Picture").field("size", &self.size).finish()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DecodeError {
+    UnknownFormat,
+    Unsupported,
+    Invalid,
+    TooLarge,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownFormat => write!(f, "Not a BMP or a PNG file"),
+            Self::Unsupported => write!(f, "Unsupported picture encoding"),
+            Self::Invalid => write!(f, "Corrupted or truncated picture"),
+            Self::TooLarge => write!(
+                f,
+                "Picture larger than {} pixels or than the free memory",
+                MAX_PIXELS
+            ),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+// The size of a picture, if it is supported
+fn picture_size(width: u32, height: u32) -> Result<Size, DecodeError> {
+    if width == 0 || height == 0 {
+        return Err(DecodeError::Invalid);
+    }
+
+    if width.saturating_mul(height) > MAX_PIXELS {
+        return Err(DecodeError::TooLarge);
+    }
+
+    Ok(Size::new(width, height))
+}
+
+fn decode_bmp<C>(data: &[u8], area: Size, mode: ColorMode<C>) -> Result<Picture, DecodeError>
+where
+    C: PackedColor,
+{
+    let u16_at = |offset: usize| {
+        data.get(offset..offset + 2)
+            .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]))
+            .ok_or(DecodeError::Invalid)
+    };
+
+    let u32_at = |offset: usize| {
+        data.get(offset..offset + 4)
+            .map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+            .ok_or(DecodeError::Invalid)
+    };
+
+    let data_offset = u32_at(10)? as usize;
+    let header_size = u32_at(14)? as usize;
+
+    // The OS/2 `BITMAPCOREHEADER` is not worth supporting
+    if header_size < 40 {
+        return Err(DecodeError::Unsupported);
+    }
+
+    if header_size > MAX_BMP_HEADER_SIZE {
+        return Err(DecodeError::Invalid);
+    }
+
+    let width = u32_at(18)? as i32;
+    let height = u32_at(22)? as i32;
+    let bpp = u16_at(28)?;
+    let compression = u32_at(30)?;
+    let colors_used = u32_at(46)?;
+
+    if width <= 0 || height == 0 {
+        return Err(DecodeError::Invalid);
+    }
+
+    // Rows are stored bottom-up, unless the height is negative
+    let top_down = height < 0;
+    let size = picture_size(width as u32, height.unsigned_abs())?;
+
+    // Red, green and blue masks of the 16 and 32 bpp encodings
+    let masks = match (compression, bpp) {
+        (0, 1 | 4 | 8 | 24) => None,
+        (0, 16) => Some([0x7c00, 0x03e0, 0x001f]),
+        (0, 32) => Some([0xff0000, 0x00ff00, 0x0000ff]),
+        // `BI_BITFIELDS`; the masks follow the 40 bytes of `BITMAPINFOHEADER`
+        (3, 16 | 32) => Some([u32_at(54)?, u32_at(58)?, u32_at(62)?]),
+        _ => return Err(DecodeError::Unsupported),
+    };
+
+    let palette = if bpp <= 8 {
+        let len = if colors_used == 0 {
+            1 << bpp
+        } else {
+            colors_used.min(256) as usize
+        };
+
+        let offset = header_size.checked_add(14).ok_or(DecodeError::Invalid)?;
+
+        data.get(offset..)
+            .and_then(|palette| palette.get(..len * 4))
+            .ok_or(DecodeError::Invalid)?
+            .chunks_exact(4)
+            .map(|bgr| Rgb888::new(bgr[2], bgr[1], bgr[0]))
+            .collect()
+    } else {
+        Vec::new()
+    };
+
+    let stride = (size.width as usize * bpp as usize).div_ceil(32) * 4;
+
+    Picture::scaled(size, area, mode, |y, columns, pixels| {
+        let row = if top_down { y } else { size.height - 1 - y } as usize;
+        let offset = data_offset
+            .checked_add(row * stride)
+            .ok_or(DecodeError::Invalid)?;
+
+        let row = data
+            .get(offset..)
+            .and_then(|row| row.get(..stride))
+            .ok_or(DecodeError::Invalid)?;
+
+        for &x in columns {
+            let x = x as usize;
+
+            let color = match bpp {
+                1 | 4 | 8 => {
+                    let bit = x * bpp as usize;
+                    let index =
+                        (row[bit / 8] as usize >> (8 - bpp as usize - bit % 8)) & ((1 << bpp) - 1);
+
+                    *palette.get(index).ok_or(DecodeError::Invalid)?
+                }
+                24 => Rgb888::new(row[x * 3 + 2], row[x * 3 + 1], row[x * 3]),
+                _ => {
+                    let bytes = bpp as usize / 8;
+                    let value = row[x * bytes..(x + 1) * bytes]
+                        .iter()
+                        .rev()
+                        .fold(0, |value, byte| (value << 8) | *byte as u32);
+
+                    let [r, g, b] = masks.unwrap().map(|mask| channel(value, mask));
+
+                    Rgb888::new(r, g, b)
+                }
+            };
+
+            pixels.push(color);
+        }
+
+        Ok(())
+    })
+}
+
+// An empty `Vec` with the given capacity, or an error if there is not enough free memory
+fn try_vec<T>(capacity: usize) -> Result<Vec<T>, DecodeError> {
+    let mut vec = Vec::new();
+
+    vec.try_reserve_exact(capacity)
+        .map_err(|_| DecodeError::TooLarge)?;
+
+    Ok(vec)
+}
+
+// Extracts the masked channel of a pixel, scaled to 8 bits
+fn channel(value: u32, mask: u32) -> u8 {
+    if mask == 0 {
+        return 0;
+    }
+
+    let max = (mask >> mask.trailing_zeros()) as u64;
+
+    (((value & mask) >> mask.trailing_zeros()) as u64 * 255 / max) as u8
+}
+
+fn decode_png<C>(data: &[u8], area: Size, mode: ColorMode<C>) -> Result<Picture, DecodeError>
+where
+    C: PackedColor,
+{
+    let mut decoder = png::Decoder::new(data);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+
+    let mut reader = decoder.read_info().map_err(|_| DecodeError::Invalid)?;
+
+    let info = reader.info();
+
+    let size = picture_size(info.width, info.height)?;
+    let interlaced = info.interlaced;
+    let (color_type, _) = reader.output_color_type();
+
+    if interlaced {
+        // The passes of an interlaced PNG only make up its rows together, so the picture is decoded
+        // as a whole first
+        let mut buf = try_vec(reader.output_buffer_size())?;
+        buf.resize(reader.output_buffer_size(), 0);
+        let frame = reader
+            .next_frame(&mut buf)
+            .map_err(|_| DecodeError::Invalid)?;
+
+        Picture::scaled(size, area, mode, |y, columns, pixels| {
+            png_row(
+                &buf[y as usize * frame.line_size..],
+                color_type,
+                columns,
+                pixels,
+            )
+        })
+    } else {
+        let mut next = 0;
+
+        Picture::scaled(size, area, mode, |y, columns, pixels| loop {
+            let row = reader
+                .next_row()
+                .map_err(|_| DecodeError::Invalid)?
+                .ok_or(DecodeError::Invalid)?;
+
+            next += 1;
+
+            // The rows which are not sampled are skipped
+            if next > y {
+                return png_row(row.data(), color_type, columns, pixels);
+            }
+        })
+    }
+}
+
+// Decodes the pixels at the given columns of a row of a PNG
+fn png_row(
+    row: &[u8],
+    color_type: png::ColorType,
+    columns: &[u32],
+    pixels: &mut Vec<Rgb888>,
+) -> Result<(), DecodeError> {
+    let over_white = |value: u8, alpha: u8| {
+        ((value as u32 * alpha as u32 + 255 * (255 - alpha as u32)) / 255) as u8
+    };
+
+    let samples = color_type.samples();
+
+    for &x in columns {
+        let c = row
+            .get(x as usize * samples..(x as usize + 1) * samples)
+            .ok_or(DecodeError::Invalid)?;
+
+        pixels.push(match color_type {
+            png::ColorType::Grayscale => Rgb888::new(c[0], c[0], c[0]),
+            png::ColorType::GrayscaleAlpha => {
+                let l = over_white(c[0], c[1]);
+
+                Rgb888::new(l, l, l)
+            }
+            png::ColorType::Rgb => Rgb888::new(c[0], c[1], c[2]),
+            png::ColorType::Rgba => Rgb888::new(
+                over_white(c[0], c[3]),
+                over_white(c[1], c[3]),
+                over_white(c[2], c[3]),
+            ),
+            // Expanded by the `normalize_to_color8` transformation
+            png::ColorType::Indexed => return Err(DecodeError::Unsupported),
+        });
+    }
+
+    Ok(())
+}
+
+/// How the colors of a picture are mapped to the colors of the display
+#[derive(Copy, Clone, Debug)]
+pub enum ColorMode<C> {
+    /// The closest color, for RGB displays
+    Convert(fn(Rgb888) -> C),
+    /// Floyd-Steinberg dithering to two colors, for monochrome displays
+    Dither { dark: C, light: C },
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct PictureStyle<C> {
+    pub background: C,
+    pub text: C,
+    pub mode: ColorMode<C>,
+}
+
+impl<C> PictureStyle<C>
+where
+    C: RgbColor + From<Rgb888>,
+{
+    pub fn rgb() -> Self {
+        Self {
+            background: C::BLACK,
+            text: C::WHITE,
+            mode: ColorMode::Convert(C::from),
+        }
+    }
+}
+
+impl PictureStyle<BinaryColor> {
+    /// For displays where `BinaryColor::On` is a lit pixel, like the SSD1306 OLEDs
+    pub fn binary() -> Self {
+        Self {
+            background: BinaryColor::Off,
+            text: BinaryColor::On,
+            mode: ColorMode::Dither {
+                dark: BinaryColor::Off,
+                light: BinaryColor::On,
+            },
+        }
+    }
+
+    /// For displays where `BinaryColor::On` is a black pixel, like the e-paper displays
+    pub fn epaper() -> Self {
+        Self {
+            background: BinaryColor::Off,
+            text: BinaryColor::On,
+            mode: ColorMode::Dither {
+                dark: BinaryColor::On,
+                light: BinaryColor::Off,
+            },
+        }
+    }
+}
+
+pub struct PictureView<C> {
+    style: PictureStyle<C>,
+    // The picture currently on the display; `Some(None)` for the placeholder text
+    drawn: Option<Option<Arc<Picture>>>,
+}
+
+impl<C> PictureView<C>
+where
+    C: PackedColor,
+{
+    pub fn new(style: PictureStyle<C>) -> Self {
+        Self { style, drawn: None }
+    }
+
+    /// Renders the picture, centered
+    ///
+    /// The display is only redrawn if the picture changed since the previous call. Pictures
+    /// decoded for other colors than those of the display are not shown.
+    pub fn draw<D>(
+        &mut self,
+        display: &mut D,
+        picture: Option<&Arc<Picture>>,
+    ) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = C>,
+    {
+        let unchanged = match (&self.drawn, picture) {
+            (Some(Some(drawn)), Some(picture)) => Arc::ptr_eq(drawn, picture),
+            (Some(None), None) => true,
+            _ => false,
+        };
+
+        if unchanged {
+            return Ok(());
+        }
+
+        display.clear(self.style.background)?;
+
+        let bbox = display.bounding_box();
+
+        let pixels = picture.and_then(|picture| Some((picture.size(), picture.pixels::<C>()?)));
+
+        if let Some((size, pixels)) = pixels {
+            let top_left = bbox.top_left
+                + Point::new(
+                    (bbox.size.width as i32 - size.width as i32) / 2,
+                    (bbox.size.height as i32 - size.height as i32) / 2,
+                );
+
+            display.fill_contiguous(&Rectangle::new(top_left, size), pixels)?;
+        } else {
+            Text::with_text_style(
+                "No picture uploaded",
+                bbox.center(),
+                MonoTextStyle::new(&FONT_6X10, self.style.text),
+                TextStyleBuilder::new()
+                    .alignment(Alignment::Center)
+                    .baseline(Baseline::Middle)
+                    .build(),
+            )
+            .draw(display)?;
+        }
+
+        self.drawn = Some(picture.cloned());
+
+        Ok(())
+    }
+}
+
+fn luma(color: Rgb888) -> i16 {
+    ((color.r() as u32 * 299 + color.g() as u32 * 587 + color.b() as u32 * 114) / 1000) as i16
+}
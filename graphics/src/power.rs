@@ -0,0 +1,87 @@
+//! When to dim and when to blank the display, depending on how long ago anything happened
+//!
+//! The time is passed in as the time since boot, so that the policy can be tested on the host.
+
+use core::fmt;
+use core::time::Duration;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum PowerState {
+    #[default]
+    On,
+    /// The backlight (or the OLED) at a low brightness
+    Dimmed,
+    /// The backlight off, or the panel asleep; nothing needs to be drawn
+    Blanked,
+}
+
+impl fmt::Display for PowerState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::On => "on",
+            Self::Dimmed => "dimmed",
+            Self::Blanked => "blanked",
+        })
+    }
+}
+
+pub struct PowerPolicy {
+    dim_after: Option<Duration>,
+    blank_after: Option<Duration>,
+    last_activity: Duration,
+    state: PowerState,
+}
+
+impl PowerPolicy {
+    /// Dims the display after `dim_after` and blanks it after `blank_after` of inactivity
+    ///
+    /// Either can be `None` to never dim or blank.
+    pub fn new(dim_after: Option<Duration>, blank_after: Option<Duration>, now: Duration) -> Self {
+        Self {
+            dim_after,
+            blank_after,
+            last_activity: now,
+            state: PowerState::On,
+        }
+    }
+
+    pub fn state(&self) -> PowerState {
+        self.state
+    }
+
+    /// Records activity - a button press, a touch, a message - which turns the display back on
+    ///
+    /// Returns the new state, if it changed.
+    pub fn wake(&mut self, now: Duration) -> Option<PowerState> {
+        self.last_activity = now;
+
+        self.set(PowerState::On)
+    }
+
+    /// Updates the state with the time passed since the last activity
+    ///
+    /// Returns the new state, if it changed.
+    pub fn tick(&mut self, now: Duration) -> Option<PowerState> {
+        let idle = now.saturating_sub(self.last_activity);
+
+        let elapsed = |after: Option<Duration>| after.is_some_and(|after| idle >= after);
+
+        let state = if elapsed(self.blank_after) {
+            PowerState::Blanked
+        } else if elapsed(self.dim_after) {
+            PowerState::Dimmed
+        } else {
+            PowerState::On
+        };
+
+        self.set(state)
+    }
+
+    fn set(&mut self, state: PowerState) -> Option<PowerState> {
+        (self.state != state).then(|| {
+            self.state = state;
+
+            state
+        })
+    }
+}
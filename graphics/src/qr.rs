@@ -0,0 +1,265 @@
+//! QR codes for the device's HTTP URL and for joining its Wifi SoftAP
+//!
+//! The modules of the code are scaled to the largest integer size which fits the display, and the
+//! caption is placed next to the code on landscape displays and below it on portrait ones.
+
+use std::net::Ipv4Addr;
+
+use embedded_graphics::mono_font::{ascii::*, MonoFont, MonoTextStyle};
+use embedded_graphics::pixelcolor::*;
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::*;
+use embedded_graphics::text::*;
+
+use qrcodegen::{QrCode, QrCodeEcc};
+
+use crate::console;
+
+// The margin around the code required by the spec, in modules
+const QUIET_ZONE: i32 = 4;
+
+pub fn device_url(ip: Ipv4Addr) -> String {
+    format!("http://{}/", ip)
+}
+
+/// The `WIFI:` string understood by the camera apps of the mobile phones, for joining a Wifi network
+pub fn wifi_join(ssid: &str, password: Option<&str>) -> String {
+    fn escape(s: &str) -> String {
+        s.chars()
+            .flat_map(|c| {
+                let escaped = matches!(c, '\\' | ';' | ',' | '"' | ':').then_some('\\');
+
+                escaped.into_iter().chain([c])
+            })
+            .collect()
+    }
+
+    match password {
+        Some(password) => format!("WIFI:T:WPA;S:{};P:{};;", escape(ssid), escape(password)),
+        None => format!("WIFI:T:nopass;S:{};;", escape(ssid)),
+    }
+}
+
+/// A Wifi network, for rendering its join string
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WifiNetwork {
+    pub ssid: String,
+    /// `None` for open networks
+    pub password: Option<String>,
+}
+
+impl WifiNetwork {
+    pub fn join_string(&self) -> String {
+        wifi_join(&self.ssid, self.password.as_deref())
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct QrPalette<C> {
+    pub background: C,
+    pub text: C,
+    /// The color of the dark modules; needs to be darker than `light` even on displays
+    /// where `BinaryColor::On` means a lit pixel
+    pub dark: C,
+    pub light: C,
+}
+
+impl<C> QrPalette<C>
+where
+    C: RgbColor,
+{
+    pub fn rgb() -> Self {
+        Self {
+            background: C::BLACK,
+            text: C::WHITE,
+            dark: C::BLACK,
+            light: C::WHITE,
+        }
+    }
+}
+
+impl QrPalette<BinaryColor> {
+    /// For displays where `BinaryColor::On` is a lit pixel, like the SSD1306 OLEDs
+    pub fn binary() -> Self {
+        Self {
+            background: BinaryColor::Off,
+            text: BinaryColor::On,
+            dark: BinaryColor::Off,
+            light: BinaryColor::On,
+        }
+    }
+
+    /// For displays where `BinaryColor::On` is a black pixel, like the e-paper displays
+    pub fn epaper() -> Self {
+        Self {
+            background: BinaryColor::Off,
+            text: BinaryColor::On,
+            dark: BinaryColor::On,
+            light: BinaryColor::Off,
+        }
+    }
+}
+
+pub struct QrView<C> {
+    palette: QrPalette<C>,
+    // The data and the caption currently on the display
+    drawn: Option<(Option<String>, String)>,
+}
+
+impl<C> QrView<C>
+where
+    C: PixelColor,
+{
+    pub fn new(palette: QrPalette<C>) -> Self {
+        Self {
+            palette,
+            drawn: None,
+        }
+    }
+
+    /// Renders the data as a QR code, along with the caption
+    ///
+    /// Without data (e.g. while waiting for an IP address), only the caption is rendered.
+    /// The display is only redrawn if the data or the caption changed since the previous call.
+    pub fn draw<D>(
+        &mut self,
+        display: &mut D,
+        data: Option<&str>,
+        caption: &str,
+    ) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = C>,
+    {
+        let current = (data.map(str::to_owned), caption.to_owned());
+
+        if self.drawn.as_ref() == Some(&current) {
+            return Ok(());
+        }
+
+        display.clear(self.palette.background)?;
+
+        let bbox = display.bounding_box();
+
+        // Data which does not fit in a QR code is not something the demo ever displays
+        let code = data.and_then(|data| QrCode::encode_text(data, QrCodeEcc::Medium).ok());
+
+        let caption_area = if let Some(code) = code {
+            let landscape = bbox.size.width >= bbox.size.height;
+            let side = bbox.size.width.min(bbox.size.height);
+
+            let code_area = Rectangle::new(bbox.top_left, Size::new(side, side));
+
+            self.draw_code(display, &code, code_area)?;
+
+            if landscape {
+                Rectangle::new(
+                    bbox.top_left + Point::new(side as i32, 0),
+                    Size::new(bbox.size.width - side, bbox.size.height),
+                )
+            } else {
+                Rectangle::new(
+                    bbox.top_left + Point::new(0, side as i32),
+                    Size::new(bbox.size.width, bbox.size.height - side),
+                )
+            }
+        } else {
+            bbox
+        };
+
+        self.draw_caption(display, caption, caption_area)?;
+
+        self.drawn = Some(current);
+
+        Ok(())
+    }
+
+    fn draw_code<D>(&self, display: &mut D, code: &QrCode, area: Rectangle) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = C>,
+    {
+        let modules = code.size() + QUIET_ZONE * 2;
+        let scale = (area.size.width as i32 / modules).max(1);
+
+        // Center the code, including its quiet zone, in the area
+        let size = Size::new((modules * scale) as u32, (modules * scale) as u32);
+        let origin = area.center() - Point::new(size.width as i32 / 2, size.height as i32 / 2);
+
+        Rectangle::new(origin, size)
+            .into_styled(PrimitiveStyle::with_fill(self.palette.light))
+            .draw(display)?;
+
+        let dark = PrimitiveStyle::with_fill(self.palette.dark);
+
+        for y in 0..code.size() {
+            for x in 0..code.size() {
+                if code.get_module(x, y) {
+                    Rectangle::new(
+                        origin + Point::new((x + QUIET_ZONE) * scale, (y + QUIET_ZONE) * scale),
+                        Size::new(scale as u32, scale as u32),
+                    )
+                    .into_styled(dark)
+                    .draw(display)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn draw_caption<D>(
+        &self,
+        display: &mut D,
+        caption: &str,
+        area: Rectangle,
+    ) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = C>,
+    {
+        let font = caption_font(area.size);
+        let char_size = font.character_size;
+
+        if area.size.width < char_size.width || area.size.height < char_size.height {
+            return Ok(());
+        }
+
+        let cols = (area.size.width / char_size.width) as usize;
+        let rows = (area.size.height / char_size.height) as usize;
+
+        let lines = console::wrap(caption, cols);
+        let lines = &lines[..lines.len().min(rows)];
+
+        // Center the caption vertically
+        let top =
+            area.top_left.y + (area.size.height - lines.len() as u32 * char_size.height) as i32 / 2;
+
+        let style = MonoTextStyle::new(font, self.palette.text);
+
+        for (index, line) in lines.iter().enumerate() {
+            Text::with_text_style(
+                line,
+                Point::new(
+                    area.center().x,
+                    top + index as i32 * char_size.height as i32,
+                ),
+                style,
+                TextStyleBuilder::new()
+                    .alignment(Alignment::Center)
+                    .baseline(Baseline::Top)
+                    .build(),
+            )
+            .draw(display)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn caption_font(size: Size) -> &'static MonoFont<'static> {
+    if size.width >= 200 {
+        &FONT_10X20
+    } else if size.width >= 120 {
+        &FONT_7X13
+    } else {
+        &FONT_6X10
+    }
+}
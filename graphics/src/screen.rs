@@ -0,0 +1,275 @@
+//! The pages shown on the board's display, of which one is visible at a time
+
+use core::fmt;
+use core::str::FromStr;
+
+use std::sync::Arc;
+
+use embedded_graphics::pixelcolor::*;
+use embedded_graphics::prelude::*;
+
+use crate::chart::{Chart, ChartPalette, History};
+use crate::console::{Console, ConsolePalette, Record};
+use crate::dashboard::{Dashboard, Palette, Snapshot};
+use crate::message::{Message, MessageStyle, MessageView};
+use crate::mirror::PackedColor;
+use crate::picture::{Picture, PictureStyle, PictureView};
+use crate::power::PowerState;
+use crate::qr::{self, QrPalette, QrView, WifiNetwork};
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Page {
+    #[default]
+    Dashboard,
+    Console,
+    /// A QR code of the device's HTTP URL
+    Url,
+    /// A QR code for joining the device's SoftAP
+    Wifi,
+    /// The most recently uploaded picture
+    Picture,
+    /// The recent history of the A2 ADC readings
+    Chart,
+}
+
+impl Page {
+    pub const ALL: &'static [Page] = &[
+        Page::Dashboard,
+        Page::Console,
+        Page::Url,
+        Page::Wifi,
+        Page::Picture,
+        Page::Chart,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Dashboard => "dashboard",
+            Self::Console => "console",
+            Self::Url => "url",
+            Self::Wifi => "wifi",
+            Self::Picture => "picture",
+            Self::Chart => "chart",
+        }
+    }
+
+    /// The page after this one in `ALL`, wrapping around
+    pub fn next(&self) -> Page {
+        let index = Self::ALL.iter().position(|page| page == self).unwrap_or(0);
+
+        Self::ALL[(index + 1) % Self::ALL.len()]
+    }
+
+    /// The page before this one in `ALL`, wrapping around
+    pub fn previous(&self) -> Page {
+        let index = Self::ALL.iter().position(|page| page == self).unwrap_or(0);
+
+        Self::ALL[(index + Self::ALL.len() - 1) % Self::ALL.len()]
+    }
+}
+
+impl fmt::Display for Page {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Page {
+    type Err = UnknownPage;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .iter()
+            .find(|page| page.name() == s)
+            .copied()
+            .ok_or(UnknownPage)
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct UnknownPage;
+
+impl fmt::Display for UnknownPage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Unknown display page")
+    }
+}
+
+impl std::error::Error for UnknownPage {}
+
+/// Everything any of the pages might need for rendering
+#[derive(Clone, Debug, Default)]
+pub struct Frame {
+    pub page: Page,
+    pub snapshot: Snapshot,
+    pub log: Vec<Record>,
+    pub access_point: Option<WifiNetwork>,
+    pub picture: Option<Arc<Picture>>,
+    pub adc_history: History,
+    /// Shown instead of the page, while there is one
+    pub message: Option<Arc<Message>>,
+    /// Not drawn by the screen, but applied to the panel by the renderer
+    pub power: PowerState,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Theme<C> {
+    pub dashboard: Palette<C>,
+    pub console: ConsolePalette<C>,
+    pub qr: QrPalette<C>,
+    pub picture: PictureStyle<C>,
+    pub message: MessageStyle<C>,
+    pub chart: ChartPalette<C>,
+}
+
+impl<C> Theme<C>
+where
+    C: RgbColor + From<Rgb888>,
+{
+    pub fn rgb() -> Self {
+        Self {
+            dashboard: Palette::rgb(),
+            console: ConsolePalette::rgb(),
+            qr: QrPalette::rgb(),
+            picture: PictureStyle::rgb(),
+            message: MessageStyle::rgb(),
+            chart: ChartPalette::rgb(),
+        }
+    }
+}
+
+impl Theme<BinaryColor> {
+    /// For displays where `BinaryColor::On` is a lit pixel, like the SSD1306 OLEDs
+    pub fn binary() -> Self {
+        Self {
+            dashboard: Palette::binary(),
+            console: ConsolePalette::binary(),
+            qr: QrPalette::binary(),
+            picture: PictureStyle::binary(),
+            message: MessageStyle::binary(),
+            chart: ChartPalette::binary(),
+        }
+    }
+
+    /// For displays where `BinaryColor::On` is a black pixel, like the e-paper displays
+    pub fn epaper() -> Self {
+        Self {
+            qr: QrPalette::epaper(),
+            picture: PictureStyle::epaper(),
+            ..Self::binary()
+        }
+    }
+}
+
+// What the screen currently shows
+#[derive(Clone)]
+enum Shown {
+    Page(Page),
+    Message(Arc<Message>),
+}
+
+impl PartialEq for Shown {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Page(a), Self::Page(b)) => a == b,
+            // The same text might arrive twice, and is then shown twice
+            (Self::Message(a), Self::Message(b)) => Arc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
+
+pub struct Screen<C> {
+    theme: Theme<C>,
+    shown: Option<Shown>,
+    dashboard: Dashboard<C>,
+    console: Console<C>,
+    qr: QrView<C>,
+    picture: PictureView<C>,
+    message: MessageView<C>,
+    chart: Chart<C>,
+}
+
+impl<C> Screen<C>
+where
+    C: PackedColor,
+{
+    pub fn new(theme: Theme<C>) -> Self {
+        Self {
+            theme,
+            shown: None,
+            dashboard: Dashboard::new(theme.dashboard),
+            console: Console::new(theme.console),
+            qr: QrView::new(theme.qr),
+            picture: PictureView::new(theme.picture),
+            message: MessageView::new(theme.message),
+            chart: adc_chart(theme.chart),
+        }
+    }
+
+    /// Forgets what is on the display, so that the next call to `draw()` clears it
+    ///
+    /// For when something else than the screen has drawn on the display in the meantime.
+    pub fn reset(&mut self) {
+        self.shown = None;
+    }
+
+    /// Renders the message of the frame if there is one, or else the page selected in the frame
+    ///
+    /// The display is cleared first whenever something else is shown than on the previous call.
+    pub fn draw<D>(&mut self, display: &mut D, frame: &Frame) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = C>,
+    {
+        let shown = match &frame.message {
+            Some(message) => Shown::Message(message.clone()),
+            None => Shown::Page(frame.page),
+        };
+
+        if self.shown.as_ref() != Some(&shown) {
+            // Start over, so that the new page clears the display
+            self.dashboard = Dashboard::new(self.theme.dashboard);
+            self.console = Console::new(self.theme.console);
+            self.qr = QrView::new(self.theme.qr);
+            self.picture = PictureView::new(self.theme.picture);
+            self.message = MessageView::new(self.theme.message);
+            self.chart = adc_chart(self.theme.chart);
+
+            self.shown = Some(shown);
+        }
+
+        if let Some(message) = &frame.message {
+            return self.message.draw(display, message);
+        }
+
+        match frame.page {
+            Page::Dashboard => self.dashboard.draw(display, &frame.snapshot),
+            Page::Console => self.console.draw(display, &frame.log),
+            Page::Url => match frame.snapshot.ip {
+                Some(ip) => {
+                    let url = qr::device_url(ip);
+
+                    self.qr.draw(display, Some(&url), &url)
+                }
+                None => self.qr.draw(display, None, "Waiting for an IP address"),
+            },
+            Page::Wifi => match &frame.access_point {
+                Some(network) => self.qr.draw(
+                    display,
+                    Some(&network.join_string()),
+                    &format!("Wifi: {}", network.ssid),
+                ),
+                None => self.qr.draw(display, None, "No Wifi access point"),
+            },
+            Page::Picture => self.picture.draw(display, frame.picture.as_ref()),
+            Page::Chart => self.chart.draw(display, &frame.adc_history),
+        }
+    }
+}
+
+fn adc_chart<C>(palette: ChartPalette<C>) -> Chart<C>
+where
+    C: PixelColor,
+{
+    Chart::new(palette, "A2", "mV")
+}
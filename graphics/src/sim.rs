@@ -0,0 +1,146 @@
+//! An in-memory display, for looking at the drawing code's output without a physical panel
+//!
+//! Monochrome frames are exported as binary PBM and color frames as PNG, and both as BMP. On the
+//! device, it is the mirror of the physical display (see the `mirror` module).
+
+use embedded_graphics::pixelcolor::*;
+use embedded_graphics::prelude::*;
+
+use crate::bmp::{self, BmpColor};
+
+pub struct Framebuffer<C> {
+    size: Size,
+    pixels: Vec<C>,
+}
+
+impl<C> Framebuffer<C>
+where
+    C: PixelColor,
+{
+    pub fn new(size: Size, background: C) -> Self {
+        Self {
+            size,
+            pixels: vec![background; (size.width * size.height) as usize],
+        }
+    }
+
+    pub fn pixel(&self, point: Point) -> Option<C> {
+        self.index(point).map(|index| self.pixels[index])
+    }
+
+    pub fn pixels(&self) -> &[C] {
+        &self.pixels
+    }
+
+    fn index(&self, point: Point) -> Option<usize> {
+        let (x, y) = (point.x as u32, point.y as u32);
+
+        (point.x >= 0 && point.y >= 0 && x < self.size.width && y < self.size.height)
+            .then(|| (y * self.size.width + x) as usize)
+    }
+}
+
+impl Framebuffer<BinaryColor> {
+    /// Exports the frame as a binary (P4) PBM image, where `BinaryColor::On` is black
+    pub fn to_pbm(&self) -> Vec<u8> {
+        let mut pbm = format!("P4\n{} {}\n", self.size.width, self.size.height).into_bytes();
+
+        for row in self.pixels.chunks(self.size.width as usize) {
+            // Rows are padded to whole bytes
+            for byte in row.chunks(8) {
+                pbm.push(
+                    byte.iter()
+                        .enumerate()
+                        .filter(|(_, color)| color.is_on())
+                        .fold(0, |acc, (bit, _)| acc | (0x80 >> bit)),
+                );
+            }
+        }
+
+        pbm
+    }
+}
+
+impl<C> Framebuffer<C>
+where
+    C: PixelColor + Into<Rgb888>,
+{
+    /// Returns the frame as 8-bit RGB triples, row by row
+    pub fn to_rgb888(&self) -> Vec<u8> {
+        self.pixels
+            .iter()
+            .flat_map(|color| {
+                let color: Rgb888 = (*color).into();
+
+                [color.r(), color.g(), color.b()]
+            })
+            .collect()
+    }
+
+    pub fn to_png(&self) -> Vec<u8> {
+        let mut png = Vec::new();
+
+        let mut encoder = png::Encoder::new(&mut png, self.size.width, self.size.height);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+
+        // Writing into a `Vec` cannot fail
+        encoder
+            .write_header()
+            .unwrap()
+            .write_image_data(&self.to_rgb888())
+            .unwrap();
+
+        png
+    }
+}
+
+impl<C> Framebuffer<C>
+where
+    C: BmpColor,
+{
+    pub fn to_bmp(&self) -> Vec<u8> {
+        let mut bmp = Vec::new();
+
+        bmp::encode(
+            self.size,
+            |point| self.pixel(point).unwrap(),
+            |bytes| {
+                bmp.extend_from_slice(bytes);
+
+                Ok::<_, core::convert::Infallible>(())
+            },
+        )
+        .unwrap();
+
+        bmp
+    }
+}
+
+impl<C> OriginDimensions for Framebuffer<C> {
+    fn size(&self) -> Size {
+        self.size
+    }
+}
+
+impl<C> DrawTarget for Framebuffer<C>
+where
+    C: PixelColor,
+{
+    type Color = C;
+    type Error = core::convert::Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        for Pixel(point, color) in pixels {
+            // Like a physical panel, silently clip whatever is outside
+            if let Some(index) = self.index(point) {
+                self.pixels[index] = color;
+            }
+        }
+
+        Ok(())
+    }
+}
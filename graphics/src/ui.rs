@@ -0,0 +1,207 @@
+//! The status dashboard of the color displays, declared in `ui/dashboard.slint` and rendered with
+//! Slint's software renderer
+//!
+//! The other pages and the messages are still drawn by the `Screen`, so `UiScreen` wraps one and
+//! only takes over the `Dashboard` page. Slint redraws just the parts of the window which changed,
+//! line by line, into any `DrawTarget`: the back buffer on the device, and a `sim::Framebuffer`
+//! in the snapshot tests.
+//!
+//! The software renderer only renders text with glyphs embedded at build time, so `build.rs`
+//! compiles the UI with `slint-build`, which embeds the glyphs of the font in `ui/fonts`.
+//!
+//! Slint is single-threaded: a `UiScreen` has to be created and used on the same thread.
+
+use core::ops::Range;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use embedded_graphics::pixelcolor::raw::RawU16;
+use embedded_graphics::pixelcolor::*;
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::Rectangle;
+
+use slint::platform::software_renderer::{
+    LineBufferProvider, MinimalSoftwareWindow, RepaintBufferType, Rgb565Pixel,
+};
+use slint::platform::{Platform, SetPlatformError, WindowAdapter};
+use slint::{ComponentHandle, PhysicalSize, PlatformError};
+
+use crate::dashboard::Snapshot;
+use crate::{Frame, Page, Screen, Theme};
+
+slint::include_modules!();
+
+/// The ADC readings shown as a full meter
+pub const ADC_FULL_SCALE_MV: u16 = 3300;
+
+pub struct UiScreen {
+    window: Rc<MinimalSoftwareWindow>,
+    dashboard: DashboardUi,
+    screen: Screen<Rgb565>,
+    // Whether the dashboard is on the display, as opposed to what the screen draws
+    shown: bool,
+}
+
+impl UiScreen {
+    pub fn new(size: Size) -> Result<Self, PlatformError> {
+        let window = MinimalSoftwareWindow::new(RepaintBufferType::ReusedBuffer);
+
+        // Slint creates the window of the component through the platform, which is set once for
+        // all threads
+        match slint::platform::set_platform(Box::new(UiPlatform)) {
+            Ok(()) | Err(SetPlatformError::AlreadySet) => (),
+            Err(err) => return Err(PlatformError::Other(err.to_string())),
+        }
+
+        NEXT_WINDOW.with(|next| next.replace(Some(window.clone())));
+
+        let dashboard = DashboardUi::new()?;
+
+        window.set_size(PhysicalSize::new(size.width, size.height));
+        dashboard.show()?;
+
+        Ok(Self {
+            window,
+            dashboard,
+            screen: Screen::new(Theme::rgb()),
+            shown: false,
+        })
+    }
+
+    /// Renders the dashboard with Slint if it is the page of the frame, or else defers to the
+    /// `Screen`
+    pub fn draw<D>(&mut self, display: &mut D, frame: &Frame) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = Rgb565>,
+    {
+        if frame.message.is_some() || frame.page != Page::Dashboard {
+            if self.shown {
+                self.screen.reset();
+                self.shown = false;
+            }
+
+            return self.screen.draw(display, frame);
+        }
+
+        self.update(&frame.snapshot);
+
+        // The screen drew over the previous frame of the dashboard, if any
+        let full = !self.shown;
+
+        if full {
+            self.window.request_redraw();
+            self.shown = true;
+        }
+
+        slint::platform::update_timers_and_animations();
+
+        let mut result = Ok(());
+
+        self.window.draw_if_needed(|renderer| {
+            // Slint has no public API for marking the whole window dirty, but changing the buffer
+            // type drops the renderer's cache of what is on the display, so all of it is redrawn
+            if full {
+                renderer.set_repaint_buffer_type(RepaintBufferType::NewBuffer);
+                renderer.set_repaint_buffer_type(RepaintBufferType::ReusedBuffer);
+            }
+
+            renderer.render_by_line(LineWriter {
+                display,
+                line: Vec::new(),
+                result: &mut result,
+            });
+        });
+
+        result
+    }
+
+    fn update(&self, snapshot: &Snapshot) {
+        let [ip, rssi, time, uptime, adc] = snapshot.lines().map(|(_, value)| value.into());
+
+        let dashboard = &self.dashboard;
+
+        dashboard.set_ip(ip);
+        dashboard.set_rssi(rssi);
+        dashboard.set_signal_bars(snapshot.rssi.map(signal_bars).unwrap_or(0));
+        dashboard.set_time(time);
+        dashboard.set_uptime(uptime);
+        dashboard.set_adc(adc);
+        dashboard.set_adc_level(
+            snapshot
+                .adc_mv
+                .map(|mv| mv as f32 / ADC_FULL_SCALE_MV as f32)
+                .unwrap_or(0.0),
+        );
+    }
+}
+
+/// 0 to 4 bars, with the usual Wifi thresholds
+pub fn signal_bars(rssi: i8) -> i32 {
+    match rssi {
+        -55.. => 4,
+        -67..=-56 => 3,
+        -75..=-68 => 2,
+        -85..=-76 => 1,
+        _ => 0,
+    }
+}
+
+thread_local! {
+    static NEXT_WINDOW: RefCell<Option<Rc<MinimalSoftwareWindow>>> = const { RefCell::new(None) };
+}
+
+struct UiPlatform;
+
+impl Platform for UiPlatform {
+    fn create_window_adapter(&self) -> Result<Rc<dyn WindowAdapter>, PlatformError> {
+        NEXT_WINDOW
+            .with(|next| next.take())
+            .map(|window| window as Rc<dyn WindowAdapter>)
+            .ok_or_else(|| PlatformError::Other("Only one window per UiScreen".into()))
+    }
+}
+
+/// Copies each line rendered by Slint to the display
+struct LineWriter<'a, D>
+where
+    D: DrawTarget<Color = Rgb565>,
+{
+    display: &'a mut D,
+    line: Vec<Rgb565Pixel>,
+    result: &'a mut Result<(), D::Error>,
+}
+
+impl<D> LineBufferProvider for LineWriter<'_, D>
+where
+    D: DrawTarget<Color = Rgb565>,
+{
+    type TargetPixel = Rgb565Pixel;
+
+    fn process_line(
+        &mut self,
+        line: usize,
+        range: Range<usize>,
+        render_fn: impl FnOnce(&mut [Self::TargetPixel]),
+    ) {
+        if self.result.is_err() {
+            return;
+        }
+
+        self.line.resize(range.len(), Rgb565Pixel::default());
+
+        render_fn(&mut self.line);
+
+        let area = Rectangle::new(
+            Point::new(range.start as i32, line as i32),
+            Size::new(range.len() as u32, 1),
+        );
+
+        *self.result = self.display.fill_contiguous(
+            &area,
+            self.line
+                .iter()
+                .map(|pixel| Rgb565::from(RawU16::new(pixel.0))),
+        );
+    }
+}
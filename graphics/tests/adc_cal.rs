@@ -0,0 +1,87 @@
+//! Tests of the ADC calibration tables
+
+use graphics::adc_cal::{CalibrationPoint, CalibrationTable, ChannelId, MAX_POINTS};
+
+fn point(raw: u16, mv: u16) -> CalibrationPoint {
+    CalibrationPoint { raw, mv }
+}
+
+#[test]
+fn new_sorts_and_dedups() {
+    let table =
+        CalibrationTable::new(&[point(3000, 2000), point(1000, 500), point(3000, 2000)]).unwrap();
+
+    assert_eq!(table.points(), &[point(1000, 500), point(3000, 2000)]);
+}
+
+#[test]
+fn new_rejects_invalid_points() {
+    assert!(CalibrationTable::new(&[]).is_err());
+    assert!(CalibrationTable::new(&[point(1000, 500), point(1000, 500)]).is_err());
+    assert!(CalibrationTable::new(&[point(1000, 500), point(1000, 600)]).is_err());
+
+    let points = (0..=MAX_POINTS as u16)
+        .map(|i| point(i * 100, i * 50))
+        .collect::<Vec<_>>();
+
+    assert!(CalibrationTable::new(&points).is_err());
+    assert!(CalibrationTable::new(&points[..MAX_POINTS]).is_ok());
+}
+
+#[test]
+fn bytes_round_trip() {
+    let table =
+        CalibrationTable::new(&[point(100, 80), point(2000, 1600), point(4000, 3100)]).unwrap();
+
+    let bytes = table.to_bytes();
+    assert_eq!(bytes.len(), 12);
+
+    assert_eq!(
+        CalibrationTable::from_bytes(&bytes).unwrap().points(),
+        table.points()
+    );
+}
+
+#[test]
+fn from_bytes_rejects_corrupted_data() {
+    assert!(CalibrationTable::from_bytes(&[1, 2, 3, 4, 5]).is_err());
+    assert!(CalibrationTable::from_bytes(&[1, 2, 3, 4]).is_err());
+}
+
+#[test]
+fn apply_interpolates_and_extrapolates() {
+    let table =
+        CalibrationTable::new(&[point(1000, 1000), point(2000, 1500), point(3000, 2500)]).unwrap();
+
+    assert_eq!(table.apply(1000), 1000);
+    assert_eq!(table.apply(1500), 1250);
+    assert_eq!(table.apply(2000), 1500);
+    assert_eq!(table.apply(2500), 2000);
+
+    // The first and the last segment continue beyond the table
+    assert_eq!(table.apply(500), 750);
+    assert_eq!(table.apply(3500), 3000);
+}
+
+#[test]
+fn apply_clamps() {
+    let table = CalibrationTable::new(&[point(1000, 100), point(2000, 5100)]).unwrap();
+
+    assert_eq!(table.apply(0), 0);
+    assert_eq!(table.apply(u16::MAX), u16::MAX);
+}
+
+#[test]
+fn channel_id_round_trip() {
+    let id = ChannelId {
+        unit: 0,
+        channel: 6,
+        atten: 3,
+    };
+
+    assert_eq!(id.to_string(), "adc1_ch6_a3");
+    assert_eq!("adc1_ch6_a3".parse::<ChannelId>().unwrap(), id);
+
+    assert!("adc0_ch6_a3".parse::<ChannelId>().is_err());
+    assert!("adc1_ch6".parse::<ChannelId>().is_err());
+}
@@ -0,0 +1,64 @@
+//! Tests of the e-paper refresh decisions, on frames in the 1 bpp format of `epd-waveshare`
+
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::Rectangle;
+
+use graphics::epaper::{self, Refresh, RefreshPolicy};
+
+const SIZE: Size = Size::new(40, 4);
+
+fn frame(set: &[(usize, usize)]) -> Vec<u8> {
+    let mut frame = vec![0; 5 * 4];
+
+    for (x, y) in set {
+        frame[y * 5 + x / 8] |= 0x80 >> (x % 8);
+    }
+
+    frame
+}
+
+#[test]
+fn dirty_window() {
+    assert_eq!(epaper::dirty_window(&frame(&[]), &frame(&[]), SIZE), None);
+
+    // Widened to the byte of the changed pixel
+    assert_eq!(
+        epaper::dirty_window(&frame(&[]), &frame(&[(10, 1)]), SIZE),
+        Some(Rectangle::new(Point::new(8, 1), Size::new(8, 1)))
+    );
+
+    assert_eq!(
+        epaper::dirty_window(&frame(&[(3, 0)]), &frame(&[(20, 2)]), SIZE),
+        Some(Rectangle::new(Point::new(0, 0), Size::new(24, 3)))
+    );
+}
+
+#[test]
+fn window() {
+    let frame = frame(&[(8, 1), (23, 2)]);
+    let window = Rectangle::new(Point::new(8, 1), Size::new(16, 2));
+
+    assert_eq!(
+        epaper::window(&frame, SIZE, &window),
+        [0x80, 0x00, 0x00, 0x01]
+    );
+}
+
+#[test]
+fn full_refresh_every_n_updates() {
+    let mut policy = RefreshPolicy::new(SIZE, 2);
+
+    assert_eq!(policy.next(&frame(&[])), Refresh::Full);
+    assert_eq!(policy.next(&frame(&[])), Refresh::None);
+
+    let partial = Refresh::Partial(Rectangle::new(Point::new(0, 0), Size::new(8, 1)));
+
+    assert_eq!(policy.next(&frame(&[(0, 0)])), partial);
+    assert_eq!(policy.next(&frame(&[])), partial);
+    assert_eq!(policy.next(&frame(&[(0, 0)])), Refresh::Full);
+    assert_eq!(policy.next(&frame(&[])), partial);
+
+    policy.reset();
+
+    assert_eq!(policy.next(&frame(&[])), Refresh::Full);
+}
@@ -0,0 +1,260 @@
+//! Tests of the display mirror, of the BMP screenshots taken from it, and of flushing it to the
+//! display as a back buffer
+
+use embedded_graphics::pixelcolor::*;
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::Rectangle;
+
+use std::sync::Mutex;
+use std::time::Duration;
+
+use graphics::dashboard::Snapshot;
+use graphics::mirror::{self, Mirror, Mirrored};
+use graphics::picture::{ColorMode, Picture, PictureStyle};
+use graphics::sim::Framebuffer;
+use graphics::{Frame, Page, Screen, Theme};
+
+fn frames() -> Vec<Frame> {
+    [Page::Dashboard, Page::Console, Page::Url]
+        .into_iter()
+        .map(|page| Frame {
+            page,
+            ..Default::default()
+        })
+        .collect()
+}
+
+#[test]
+fn mirror_rgb565() {
+    let size = Size::new(135, 240);
+
+    let mut display = Framebuffer::new(size, Rgb565::BLACK);
+    let mut mirror = Mirror::new(size, Rgb565::BLACK);
+
+    let mut screen = Screen::new(Theme::rgb());
+
+    for frame in frames() {
+        screen
+            .draw(&mut Mirrored::new(&mut display, &mut mirror), &frame)
+            .unwrap();
+
+        assert!(mirror.to_framebuffer().pixels() == display.pixels());
+    }
+
+    let screenshot = Picture::decode(
+        &mirror.to_framebuffer().to_bmp(),
+        size,
+        ColorMode::Convert(Rgb565::from),
+    )
+    .unwrap();
+
+    assert_eq!(screenshot.size(), size);
+    assert!(screenshot.pixels::<Rgb565>().unwrap().eq(mirror
+        .to_framebuffer()
+        .pixels()
+        .iter()
+        .copied()));
+}
+
+#[test]
+fn mirror_binary() {
+    let size = Size::new(128, 64);
+
+    let mut display = Framebuffer::new(size, BinaryColor::Off);
+    let mut mirror = Mirror::new(size, BinaryColor::Off);
+
+    let mut screen = Screen::new(Theme::binary());
+
+    for frame in frames() {
+        screen
+            .draw(&mut Mirrored::new(&mut display, &mut mirror), &frame)
+            .unwrap();
+
+        assert!(mirror.to_framebuffer().pixels() == display.pixels());
+    }
+
+    // Black and white pixels are not dithered
+    let screenshot = Picture::decode(
+        &mirror.to_framebuffer().to_bmp(),
+        size,
+        PictureStyle::binary().mode,
+    )
+    .unwrap();
+
+    assert_eq!(screenshot.size(), size);
+    assert!(screenshot.pixels::<BinaryColor>().unwrap().eq(mirror
+        .to_framebuffer()
+        .pixels()
+        .iter()
+        .copied()));
+}
+
+#[test]
+fn mirror_dirty() {
+    let size = Size::new(128, 64);
+
+    let mut display = Framebuffer::new(size, BinaryColor::Off);
+    let mut mirror = Mirror::new(size, BinaryColor::Off);
+
+    let mut screen = Screen::new(Theme::binary());
+
+    let mut frame = Frame::default();
+
+    screen
+        .draw(&mut Mirrored::new(&mut display, &mut mirror), &frame)
+        .unwrap();
+
+    assert!(mirror.take_dirty().is_some());
+
+    // Redrawing the same values does not change any pixels
+    screen
+        .draw(&mut Mirrored::new(&mut display, &mut mirror), &frame)
+        .unwrap();
+
+    assert_eq!(mirror.take_dirty(), None);
+
+    frame.snapshot = Snapshot {
+        uptime: Duration::from_secs(1),
+        ..Default::default()
+    };
+
+    let before = display.pixels().to_vec();
+
+    screen
+        .draw(&mut Mirrored::new(&mut display, &mut mirror), &frame)
+        .unwrap();
+
+    let dirty = mirror.take_dirty().unwrap();
+
+    // Only the uptime changed, and all changed pixels are within the dirty rectangle
+    assert!(dirty.size.height < size.height / 2);
+    assert!(Rectangle::new(Point::zero(), size)
+        .points()
+        .filter(|point| display.pixel(*point) != Some(before[(point.y * 128 + point.x) as usize]))
+        .all(|point| dirty.contains(point)));
+}
+
+#[test]
+fn mirror_dirty_rgb565() {
+    let size = Size::new(240, 135);
+
+    let mut display = Framebuffer::new(size, Rgb565::BLACK);
+    let mirror = Mutex::new(Mirror::new(size, Rgb565::BLACK));
+
+    // What a live view client paints, from the BMPs of the dirty areas
+    let mut client = Framebuffer::new(size, Rgb565::BLACK);
+
+    let mut screen = Screen::new(Theme::rgb());
+
+    let mut frame = Frame::default();
+
+    for uptime in 0..3 {
+        frame.snapshot.uptime = Duration::from_secs(uptime);
+
+        screen
+            .draw(
+                &mut Mirrored::new(&mut display, &mut mirror.lock().unwrap()),
+                &frame,
+            )
+            .unwrap();
+
+        let dirty = mirror.lock().unwrap().take_dirty().unwrap();
+
+        if uptime > 0 {
+            // Only the uptime changed
+            assert!(dirty.size.height < size.height / 2);
+        }
+
+        let mut bmp = Vec::new();
+
+        mirror::write_bmp(&mirror, &dirty, |bytes| {
+            bmp.extend_from_slice(bytes);
+
+            Ok::<_, ()>(())
+        })
+        .unwrap();
+
+        let area = Picture::decode(&bmp, dirty.size, ColorMode::Convert(Rgb565::from)).unwrap();
+
+        assert_eq!(area.size(), dirty.size);
+
+        client
+            .fill_contiguous(&dirty, area.pixels::<Rgb565>().unwrap())
+            .unwrap();
+
+        assert!(client.pixels() == display.pixels());
+    }
+
+    assert_eq!(mirror.lock().unwrap().take_dirty(), None);
+}
+
+#[test]
+fn mirror_flush() {
+    let size = Size::new(240, 135);
+
+    // Whatever the panel shows after its initialization
+    let mut display = Framebuffer::new(size, Rgb565::MAGENTA);
+    let mirror = Mutex::new(Mirror::new(size, Rgb565::BLACK));
+
+    let mut screen = Screen::new(Theme::rgb());
+
+    let mut frame = Frame::default();
+
+    screen.draw(&mut *mirror.lock().unwrap(), &frame).unwrap();
+
+    // The whole display, even where the page is of the initial color of the mirror
+    assert_eq!(
+        mirror::flush(&mirror, &mut display, 1000).unwrap(),
+        Some(Rectangle::new(Point::zero(), size))
+    );
+    assert!(mirror.lock().unwrap().to_framebuffer().pixels() == display.pixels());
+
+    assert_eq!(mirror::flush(&mirror, &mut display, 1000).unwrap(), None);
+
+    frame.snapshot.uptime = Duration::from_secs(1);
+
+    screen.draw(&mut *mirror.lock().unwrap(), &frame).unwrap();
+
+    let flushed = mirror::flush(&mirror, &mut display, 1000).unwrap().unwrap();
+
+    // Only the uptime changed
+    assert!(flushed.size.height < size.height / 2);
+    assert!(mirror.lock().unwrap().to_framebuffer().pixels() == display.pixels());
+}
+
+#[test]
+fn mirror_write_bmp() {
+    let size = Size::new(128, 64);
+
+    let mirror = Mutex::new(Mirror::new(size, BinaryColor::Off));
+
+    Screen::new(Theme::binary())
+        .draw(&mut *mirror.lock().unwrap(), &Frame::default())
+        .unwrap();
+
+    // One bit per pixel
+    assert_eq!(Mirror::<BinaryColor>::bytes(size), 1024);
+
+    let area = Rectangle::new(Point::new(8, 4), Size::new(50, 20));
+
+    let mut bmp = Vec::new();
+
+    mirror::write_bmp(&mirror, &area, |bytes| {
+        // Nothing waits for the client
+        assert!(mirror.try_lock().is_ok());
+
+        bmp.extend_from_slice(bytes);
+
+        Ok::<_, ()>(())
+    })
+    .unwrap();
+
+    let screenshot = Picture::decode(&bmp, area.size, PictureStyle::binary().mode).unwrap();
+    let frame = mirror.lock().unwrap().to_framebuffer();
+
+    assert_eq!(screenshot.size(), area.size);
+    assert!(area
+        .points()
+        .map(|point| frame.pixel(point).unwrap())
+        .eq(screenshot.pixels::<BinaryColor>().unwrap()));
+}
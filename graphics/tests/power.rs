@@ -0,0 +1,41 @@
+//! Tests of the display power policy
+
+use std::time::Duration;
+
+use graphics::power::{PowerPolicy, PowerState};
+
+fn secs(secs: u64) -> Duration {
+    Duration::from_secs(secs)
+}
+
+#[test]
+fn dim_then_blank() {
+    let mut policy = PowerPolicy::new(Some(secs(30)), Some(secs(120)), secs(5));
+
+    assert_eq!(policy.tick(secs(34)), None);
+    assert_eq!(policy.tick(secs(35)), Some(PowerState::Dimmed));
+    assert_eq!(policy.tick(secs(100)), None);
+    assert_eq!(policy.tick(secs(125)), Some(PowerState::Blanked));
+    assert_eq!(policy.state(), PowerState::Blanked);
+
+    assert_eq!(policy.wake(secs(200)), Some(PowerState::On));
+    assert_eq!(policy.wake(secs(210)), None);
+
+    // Idle time counts from the last activity
+    assert_eq!(policy.tick(secs(239)), None);
+    assert_eq!(policy.tick(secs(240)), Some(PowerState::Dimmed));
+}
+
+#[test]
+fn disabled() {
+    let mut never = PowerPolicy::new(None, None, secs(0));
+
+    assert_eq!(never.tick(secs(1_000_000)), None);
+    assert_eq!(never.state(), PowerState::On);
+
+    // Blanking without dimming first
+    let mut blank = PowerPolicy::new(None, Some(secs(60)), secs(0));
+
+    assert_eq!(blank.tick(secs(59)), None);
+    assert_eq!(blank.tick(secs(60)), Some(PowerState::Blanked));
+}
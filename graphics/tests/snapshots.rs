@@ -0,0 +1,855 @@
+//! Snapshot tests of the drawing code, for the resolution and color mode of each supported board
+//!
+//! The expected frames are in `tests/snapshots`. After an intended layout change, review the
+//! `*.actual.*` files written next to the failing snapshots, and then accept them all with
+//! `UPDATE_SNAPSHOTS=1 cargo test`.
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use embedded_graphics::pixelcolor::*;
+use embedded_graphics::prelude::*;
+
+use log::Level;
+
+use graphics::chart::{Chart, ChartPalette, History};
+use graphics::console::{self, Console, ConsolePalette, Record};
+use graphics::dashboard::{Dashboard, Palette, Snapshot};
+use graphics::message::{self, FontSize, Message, MessageError};
+use graphics::mirror::PackedColor;
+use graphics::picture::{ColorMode, DecodeError, Picture, PictureStyle};
+use graphics::qr::{self, WifiNetwork};
+use graphics::sim::Framebuffer;
+#[cfg(feature = "slint")]
+use graphics::ui::UiScreen;
+use graphics::{Frame, Page, Screen, Theme};
+
+#[derive(Copy, Clone)]
+struct Board<C> {
+    name: &'static str,
+    size: Size,
+    theme: fn() -> Theme<C>,
+}
+
+const RGB565_BOARDS: &[Board<Rgb565>] = &[
+    // Portrait, cropped from the 240x320 ST7789 frame
+    Board {
+        name: "ttgo",
+        size: Size::new(135, 240),
+        theme: Theme::rgb,
+    },
+    Board {
+        name: "kaluga",
+        size: Size::new(320, 240),
+        theme: Theme::rgb,
+    },
+    Board {
+        name: "esp32s3_usb_otg",
+        size: Size::new(240, 240),
+        theme: Theme::rgb,
+    },
+];
+
+const BINARY_BOARDS: &[Board<BinaryColor>] = &[
+    // Heltec and generic SSD1306 (I2C and SPI)
+    Board {
+        name: "ssd1306",
+        size: Size::new(128, 64),
+        theme: Theme::binary,
+    },
+    Board {
+        name: "waveshare_epd",
+        size: Size::new(400, 300),
+        theme: Theme::epaper,
+    },
+];
+
+fn snapshot() -> Snapshot {
+    Snapshot {
+        ip: Some([192, 168, 71, 1].into()),
+        rssi: Some(-58),
+        time: Some(Duration::from_secs(1_718_454_896)),
+        uptime: Duration::from_secs(93784),
+        adc_mv: Some(1234),
+    }
+}
+
+#[test]
+fn hello_rgb565() {
+    assert_boards("hello", RGB565_BOARDS, |display, _| {
+        graphics::led_draw(display).unwrap();
+    });
+}
+
+#[test]
+fn hello_binary() {
+    assert_boards("hello", BINARY_BOARDS, |display, _| {
+        graphics::led_draw_custom(
+            display,
+            BinaryColor::Off,
+            BinaryColor::On,
+            BinaryColor::On,
+            BinaryColor::On,
+        )
+        .unwrap();
+    });
+}
+
+#[test]
+fn dashboard() {
+    assert_boards("dashboard", RGB565_BOARDS, |display, theme| {
+        Dashboard::new(theme.dashboard)
+            .draw(display, &snapshot())
+            .unwrap();
+    });
+
+    assert_boards("dashboard", BINARY_BOARDS, |display, theme| {
+        Dashboard::new(theme.dashboard)
+            .draw(display, &snapshot())
+            .unwrap();
+    });
+}
+
+#[test]
+fn dashboard_redraw_erases_longer_values() {
+    let size = Size::new(128, 64);
+
+    let mut redrawn = Framebuffer::new(size, BinaryColor::Off);
+    let mut dashboard = Dashboard::new(Palette::binary());
+
+    dashboard.draw(&mut redrawn, &snapshot()).unwrap();
+    dashboard.draw(&mut redrawn, &Snapshot::default()).unwrap();
+
+    let mut fresh = Framebuffer::new(size, BinaryColor::Off);
+
+    Dashboard::new(Palette::binary())
+        .draw(&mut fresh, &Snapshot::default())
+        .unwrap();
+
+    assert!(redrawn.pixels() == fresh.pixels());
+}
+
+fn log() -> Vec<Record> {
+    [
+        (Level::Info, "Wifi DHCP info: IpInfo { ip: 192.168.71.1, subnet: Subnet { gateway: 192.168.71.254, mask: Mask(24) } }"),
+        (Level::Info, "SNTP initialized"),
+        (Level::Warn, "Setting PWM duty from MQTT topic rust-esp32-std-demo/pwm/40 failed: GPIO40 is not in the allowlist"),
+        (Level::Debug, "Tick from periodic timer"),
+        (Level::Error, "Rendering the dashboard failed: Display error: BusWriteError"),
+        (Level::Info, "A2 sensor reading: 1234mV"),
+    ]
+    .into_iter()
+    .map(|(level, message)| Record {
+        level,
+        message: message.into(),
+    })
+    .collect()
+}
+
+#[test]
+fn console() {
+    assert_boards("console", RGB565_BOARDS, |display, theme| {
+        Console::new(theme.console).draw(display, &log()).unwrap();
+    });
+
+    assert_boards("console", BINARY_BOARDS, |display, theme| {
+        Console::new(theme.console).draw(display, &log()).unwrap();
+    });
+}
+
+#[test]
+fn console_scrolls() {
+    let size = Size::new(128, 64);
+    let log = log();
+
+    let mut scrolled = Framebuffer::new(size, BinaryColor::Off);
+    let mut console = Console::new(ConsolePalette::binary());
+
+    for len in 1..=log.len() {
+        console.draw(&mut scrolled, &log[..len]).unwrap();
+    }
+
+    let mut fresh = Framebuffer::new(size, BinaryColor::Off);
+
+    Console::new(ConsolePalette::binary())
+        .draw(&mut fresh, &log)
+        .unwrap();
+
+    assert!(scrolled.pixels() == fresh.pixels());
+}
+
+#[test]
+fn console_wrap() {
+    assert_eq!(
+        console::wrap("A2 sensor reading: 1234mV", 10),
+        ["A2 sensor", "reading:", "1234mV"]
+    );
+    assert_eq!(
+        console::wrap("topic rust-esp32-std-demo/pwm/40", 8),
+        ["topic", "rust-esp", "32-std-d", "emo/pwm/", "40"]
+    );
+    assert_eq!(console::wrap("two\nlines", 10), ["two", "lines"]);
+    assert_eq!(console::wrap("", 10), [""]);
+}
+
+fn qr_frames() -> Vec<(Frame, String)> {
+    let snapshot = snapshot();
+    let access_point = WifiNetwork {
+        ssid: "aptest".into(),
+        password: None,
+    };
+
+    vec![
+        (
+            Frame {
+                page: Page::Url,
+                snapshot,
+                ..Default::default()
+            },
+            "http://192.168.71.1/".into(),
+        ),
+        (
+            Frame {
+                page: Page::Wifi,
+                access_point: Some(access_point),
+                ..Default::default()
+            },
+            "WIFI:T:nopass;S:aptest;;".into(),
+        ),
+    ]
+}
+
+#[test]
+fn qr() {
+    for (frame, data) in qr_frames() {
+        let name = format!("qr_{}", frame.page);
+
+        assert_boards(&name, RGB565_BOARDS, |display, theme| {
+            Screen::new(theme).draw(display, &frame).unwrap();
+
+            assert_eq!(decode_qr(display, theme.qr.dark), data);
+        });
+
+        assert_boards(&name, BINARY_BOARDS, |display, theme| {
+            Screen::new(theme).draw(display, &frame).unwrap();
+
+            assert_eq!(decode_qr(display, theme.qr.dark), data);
+        });
+    }
+}
+
+#[test]
+fn qr_without_data() {
+    let mut display = Framebuffer::new(Size::new(128, 64), BinaryColor::Off);
+
+    Screen::new(Theme::binary())
+        .draw(
+            &mut display,
+            &Frame {
+                page: Page::Url,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+    assert_pbm("qr_url_no_ip", &display);
+}
+
+#[test]
+fn qr_wifi_join() {
+    assert_eq!(qr::wifi_join("aptest", None), "WIFI:T:nopass;S:aptest;;");
+    assert_eq!(
+        qr::wifi_join("my;net", Some("p\\a:ss")),
+        "WIFI:T:WPA;S:my\\;net;P:p\\\\a\\:ss;;"
+    );
+}
+
+// A 160x100 color gradient with a white disc, of a different aspect ratio than all boards
+fn picture_pixels() -> (Size, Vec<Rgb888>) {
+    let size = Size::new(160, 100);
+
+    let pixels = (0..size.height)
+        .flat_map(|y| (0..size.width).map(move |x| (x, y)))
+        .map(|(x, y)| {
+            let (dx, dy) = (x as i32 - 80, y as i32 - 50);
+
+            if dx * dx + dy * dy < 30 * 30 {
+                Rgb888::WHITE
+            } else {
+                Rgb888::new((x * 255 / 159) as u8, (y * 255 / 99) as u8, 128)
+            }
+        })
+        .collect();
+
+    (size, pixels)
+}
+
+fn picture_png() -> Vec<u8> {
+    let (size, pixels) = picture_pixels();
+
+    let mut png = Vec::new();
+
+    let mut encoder = png::Encoder::new(&mut png, size.width, size.height);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .write_header()
+        .unwrap()
+        .write_image_data(
+            &pixels
+                .iter()
+                .flat_map(|c| [c.r(), c.g(), c.b()])
+                .collect::<Vec<_>>(),
+        )
+        .unwrap();
+
+    png
+}
+
+// A bottom-up 24 bpp BMP
+fn picture_bmp() -> Vec<u8> {
+    let (size, pixels) = picture_pixels();
+
+    let stride = (size.width as usize * 3).div_ceil(4) * 4;
+
+    let mut data = Vec::new();
+
+    for row in pixels.chunks(size.width as usize).rev() {
+        let start = data.len();
+
+        data.extend(row.iter().flat_map(|c| [c.b(), c.g(), c.r()]));
+        data.resize(start + stride, 0);
+    }
+
+    bmp(size.width as i32, size.height as i32, 24, &[], &data)
+}
+
+fn bmp(width: i32, height: i32, bpp: u16, palette: &[[u8; 4]], data: &[u8]) -> Vec<u8> {
+    let offset = 14 + 40 + palette.len() as u32 * 4;
+
+    let mut bmp = Vec::new();
+
+    bmp.extend(b"BM");
+    bmp.extend((offset + data.len() as u32).to_le_bytes());
+    bmp.extend([0; 4]);
+    bmp.extend(offset.to_le_bytes());
+
+    bmp.extend(40_u32.to_le_bytes());
+    bmp.extend(width.to_le_bytes());
+    bmp.extend(height.to_le_bytes());
+    bmp.extend(1_u16.to_le_bytes());
+    bmp.extend(bpp.to_le_bytes());
+    bmp.extend([0; 4 * 6]);
+
+    bmp.extend(palette.iter().flatten());
+    bmp.extend(data);
+
+    bmp
+}
+
+#[test]
+fn picture_decode() {
+    let (size, pixels) = picture_pixels();
+
+    let rgb565 = ColorMode::Convert(Rgb565::from);
+
+    for data in [picture_png(), picture_bmp()] {
+        let picture = Picture::decode(&data, size, rgb565).unwrap();
+
+        assert_eq!(picture.size(), size);
+        assert!(picture
+            .pixels::<Rgb565>()
+            .unwrap()
+            .eq(pixels.iter().map(|color| Rgb565::from(*color))));
+
+        // Scaled to fit, in the packed colors of the display
+        let picture =
+            Picture::decode(&data, Size::new(400, 300), PictureStyle::epaper().mode).unwrap();
+
+        assert_eq!(picture.size(), Size::new(400, 250));
+        assert!(picture.pixels::<Rgb565>().is_none());
+        assert_eq!(picture.pixels::<BinaryColor>().unwrap().count(), 400 * 250);
+    }
+
+    // A top-down 1 bpp BMP, 10 pixels wide so that its rows are padded
+    let palette = [[0, 0, 0, 0], [0, 0, 255, 0]];
+    let data = [
+        0b1010_1010,
+        0b1100_0000,
+        0,
+        0,
+        0b0101_0101,
+        0b0000_0000,
+        0,
+        0,
+    ];
+
+    let picture =
+        Picture::decode(&bmp(10, -2, 1, &palette, &data), Size::new(10, 2), rgb565).unwrap();
+
+    let (off, on) = (Rgb565::BLACK, Rgb565::RED);
+
+    assert_eq!(picture.size(), Size::new(10, 2));
+    assert_eq!(
+        picture.pixels::<Rgb565>().unwrap().collect::<Vec<_>>(),
+        [
+            [on, off, on, off, on, off, on, off, on, on],
+            [off, on, off, on, off, on, off, on, off, off]
+        ]
+        .concat()
+    );
+
+    // A 32 bpp `BI_BITFIELDS` BMP with all bits in the red channel
+    let mut bitfields = bmp(
+        1,
+        1,
+        32,
+        &[u32::MAX.to_le_bytes(), [0; 4], [0; 4]],
+        &u32::MAX.to_le_bytes(),
+    );
+    bitfields[30] = 3;
+
+    let picture = Picture::decode(&bitfields, Size::new(1, 1), rgb565).unwrap();
+
+    assert_eq!(
+        picture.pixels::<Rgb565>().unwrap().collect::<Vec<_>>(),
+        [Rgb565::RED]
+    );
+
+    // A header size which would overflow the offset of the palette
+    let mut header = bmp(10, -2, 1, &palette, &data);
+    header[14..18].copy_from_slice(&u32::MAX.to_le_bytes());
+
+    assert_eq!(
+        Picture::decode(&header, size, rgb565),
+        Err(DecodeError::Invalid)
+    );
+
+    assert_eq!(
+        Picture::decode(b"GIF89a", size, rgb565),
+        Err(DecodeError::UnknownFormat)
+    );
+    assert_eq!(
+        Picture::decode(&bmp(1000, 1000, 24, &[], &[]), size, rgb565),
+        Err(DecodeError::TooLarge)
+    );
+    assert_eq!(
+        Picture::decode(&picture_bmp()[..1000], size, rgb565),
+        Err(DecodeError::Invalid)
+    );
+}
+
+// The picture as decoded for the display
+fn picture_frame<C>(display: &Framebuffer<C>, theme: Theme<C>) -> Frame
+where
+    C: PackedColor,
+{
+    let size = display.bounding_box().size;
+
+    Frame {
+        page: Page::Picture,
+        picture: Some(Arc::new(
+            Picture::decode(&picture_png(), size, theme.picture.mode).unwrap(),
+        )),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn picture() {
+    assert_boards("picture", RGB565_BOARDS, |display, theme| {
+        let frame = picture_frame(display, theme);
+
+        Screen::new(theme).draw(display, &frame).unwrap();
+    });
+
+    assert_boards("picture", BINARY_BOARDS, |display, theme| {
+        let frame = picture_frame(display, theme);
+
+        Screen::new(theme).draw(display, &frame).unwrap();
+    });
+}
+
+#[test]
+fn message_parse() {
+    assert_eq!(
+        Message::parse(b"  Hello from MQTT!\n"),
+        Ok(Message::new("Hello from MQTT!"))
+    );
+
+    assert_eq!(
+        Message::parse(
+            br##"{"text": "Alarm", "size": "large", "color": "#ff8000", "duration": 2.5}"##
+        ),
+        Ok(Message {
+            text: "Alarm".into(),
+            size: FontSize::Large,
+            color: Some(Rgb888::new(0xff, 0x80, 0x00)),
+            duration: Duration::from_millis(2500),
+        })
+    );
+
+    assert_eq!(
+        Message::parse(br#"{"text": "Long", "duration": 1e9}"#).map(|m| m.duration),
+        Ok(message::MAX_DURATION)
+    );
+
+    assert_eq!(Message::parse(b" \n"), Err(MessageError::Empty));
+    assert_eq!(Message::parse(&[0xff, 0xfe]), Err(MessageError::NotUtf8));
+    assert_eq!(
+        Message::parse(&[b'x'; message::MAX_TEXT_LEN + 1]),
+        Err(MessageError::TooLong)
+    );
+
+    for invalid in [
+        r#"{"text": "Hi", "color": "orange"}"#,
+        r#"{"text": "Hi", "size": "huge"}"#,
+        r#"{"text": "Hi", "duration": -1}"#,
+        r#"{"text": "Hi", "blink": true}"#,
+        r#"{"size": "large"}"#,
+    ] {
+        assert!(
+            matches!(
+                Message::parse(invalid.as_bytes()),
+                Err(MessageError::Invalid(_))
+            ),
+            "{invalid}"
+        );
+    }
+}
+
+#[test]
+fn message_wrap() {
+    assert_eq!(
+        message::wrap("The quick brown fox\njumps", 10),
+        ["The quick", "brown fox", "jumps"]
+    );
+    assert_eq!(
+        message::wrap("Supercalifragilistic is long", 8),
+        ["Supercal", "ifragili", "stic is", "long"]
+    );
+}
+
+fn message_frame() -> Frame {
+    Frame {
+        page: Page::Dashboard,
+        snapshot: snapshot(),
+        message: Some(Arc::new(Message {
+            text: "Hello from MQTT! The washing machine is done.".into(),
+            size: FontSize::Large,
+            color: Some(Rgb888::new(0xff, 0x80, 0x00)),
+            duration: Duration::from_secs(10),
+        })),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn message() {
+    assert_boards("message", RGB565_BOARDS, |display, theme| {
+        Screen::new(theme).draw(display, &message_frame()).unwrap();
+    });
+
+    assert_boards("message", BINARY_BOARDS, |display, theme| {
+        Screen::new(theme).draw(display, &message_frame()).unwrap();
+    });
+}
+
+#[test]
+fn message_then_page() {
+    let size = Size::new(128, 64);
+
+    let mut screen = Screen::new(Theme::binary());
+    let mut display = Framebuffer::new(size, BinaryColor::Off);
+
+    let frame = message_frame();
+
+    screen.draw(&mut display, &frame).unwrap();
+
+    // Once the message expires, the page is back
+    screen
+        .draw(
+            &mut display,
+            &Frame {
+                message: None,
+                ..frame
+            },
+        )
+        .unwrap();
+
+    let mut fresh = Framebuffer::new(size, BinaryColor::Off);
+
+    Dashboard::new(Palette::binary())
+        .draw(&mut fresh, &snapshot())
+        .unwrap();
+
+    assert!(display.pixels() == fresh.pixels());
+}
+
+fn adc_history() -> History {
+    let mut history = History::default();
+
+    // A slow wave with some noise, like a potentiometer being turned back and forth
+    for index in 0..90 {
+        let wave = [0, 1, 2, 3, 4, 5, 6, 5, 4, 3, 2, 1][index / 2 % 12];
+        let noise = [0, 7, -5, 3, -8, 2][index % 6];
+
+        history.push(1100 + wave * 60 + noise);
+    }
+
+    history
+}
+
+#[test]
+fn chart_history() {
+    let mut history = History::new(3);
+
+    assert_eq!(history.latest(), None);
+    assert_eq!(history.min(), None);
+
+    for sample in [5, -2, 7, 4] {
+        history.push(sample);
+    }
+
+    assert_eq!(history.samples().collect::<Vec<_>>(), [-2, 7, 4]);
+    assert_eq!(history.latest(), Some(4));
+    assert_eq!(history.min(), Some(-2));
+    assert_eq!(history.max(), Some(7));
+}
+
+#[test]
+fn chart() {
+    // The TTGO panel in landscape, as well as the portrait crop of the other snapshots
+    let boards = [
+        RGB565_BOARDS,
+        &[Board {
+            name: "ttgo_landscape",
+            size: Size::new(240, 135),
+            theme: Theme::rgb,
+        }],
+    ]
+    .concat();
+
+    assert_boards("chart", &boards, |display, theme| {
+        Screen::new(theme)
+            .draw(
+                display,
+                &Frame {
+                    page: Page::Chart,
+                    adc_history: adc_history(),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+    });
+
+    assert_boards("chart", BINARY_BOARDS, |display, theme| {
+        Chart::new(theme.chart, "A2", "mV")
+            .draw(display, &adc_history())
+            .unwrap();
+    });
+}
+
+#[test]
+fn chart_sparse() {
+    let size = Size::new(128, 64);
+
+    let mut empty = Framebuffer::new(size, BinaryColor::Off);
+
+    Chart::new(ChartPalette::binary(), "A2", "mV")
+        .draw(&mut empty, &History::default())
+        .unwrap();
+
+    assert_pbm("chart_empty", &empty);
+
+    // A single sample, and a flat history are plotted in the middle
+    let mut flat = History::default();
+    flat.push(1234);
+
+    let mut single = Framebuffer::new(size, BinaryColor::Off);
+    let mut chart = Chart::new(ChartPalette::binary(), "A2", "mV");
+
+    chart.draw(&mut single, &flat).unwrap();
+
+    assert_pbm("chart_single", &single);
+
+    flat.push(1234);
+
+    chart.draw(&mut single, &flat).unwrap();
+
+    assert_pbm("chart_flat", &single);
+}
+
+#[cfg(feature = "slint")]
+#[test]
+fn ui() {
+    let frame = Frame {
+        snapshot: snapshot(),
+        ..Default::default()
+    };
+
+    assert_boards("ui", RGB565_BOARDS, |display, _| {
+        UiScreen::new(display.size())
+            .unwrap()
+            .draw(display, &frame)
+            .unwrap();
+    });
+}
+
+#[cfg(feature = "slint")]
+#[test]
+fn ui_updates() {
+    let size = Size::new(320, 240);
+
+    let frame = |adc_mv, page| Frame {
+        page,
+        snapshot: Snapshot {
+            adc_mv: Some(adc_mv),
+            ..snapshot()
+        },
+        log: log(),
+        ..Default::default()
+    };
+
+    let mut display = Framebuffer::new(size, Rgb565::BLACK);
+    let mut ui = UiScreen::new(size).unwrap();
+
+    // Only the changed parts are redrawn, and the dashboard is redrawn in full after another page
+    for (adc_mv, page) in [
+        (1234, Page::Dashboard),
+        (2500, Page::Dashboard),
+        (2500, Page::Console),
+        (3000, Page::Dashboard),
+    ] {
+        let frame = frame(adc_mv, page);
+
+        ui.draw(&mut display, &frame).unwrap();
+
+        let mut fresh = Framebuffer::new(size, Rgb565::BLACK);
+        UiScreen::new(size)
+            .unwrap()
+            .draw(&mut fresh, &frame)
+            .unwrap();
+
+        assert!(display.pixels() == fresh.pixels(), "{adc_mv} mV on {page}");
+    }
+}
+
+/// Draws the frame of each board with `draw`, and compares it with the `<name>_<board>` snapshot
+fn assert_boards<C: SnapshotColor>(
+    name: &str,
+    boards: &[Board<C>],
+    draw: impl Fn(&mut Framebuffer<C>, Theme<C>),
+) {
+    for board in boards {
+        let mut display = Framebuffer::new(board.size, C::BACKGROUND);
+
+        draw(&mut display, (board.theme)());
+
+        C::assert_snapshot(&format!("{name}_{}", board.name), &display);
+    }
+}
+
+trait SnapshotColor: PixelColor {
+    const BACKGROUND: Self;
+
+    fn assert_snapshot(name: &str, display: &Framebuffer<Self>);
+}
+
+impl SnapshotColor for Rgb565 {
+    const BACKGROUND: Self = Rgb565::BLACK;
+
+    fn assert_snapshot(name: &str, display: &Framebuffer<Self>) {
+        assert_png(name, display);
+    }
+}
+
+impl SnapshotColor for BinaryColor {
+    const BACKGROUND: Self = BinaryColor::Off;
+
+    fn assert_snapshot(name: &str, display: &Framebuffer<Self>) {
+        assert_pbm(name, display);
+    }
+}
+
+// Decodes the single QR code on the display, as a phone camera would
+fn decode_qr<C: PixelColor>(display: &Framebuffer<C>, dark: C) -> String {
+    let size = display.size();
+
+    let mut image =
+        rqrr::PreparedImage::prepare_from_greyscale(size.width as _, size.height as _, |x, y| {
+            if display.pixel(Point::new(x as _, y as _)) == Some(dark) {
+                0
+            } else {
+                255
+            }
+        });
+
+    let grids = image.detect_grids();
+    assert_eq!(grids.len(), 1);
+
+    grids[0].decode().unwrap().1
+}
+
+fn assert_pbm(name: &str, display: &Framebuffer<BinaryColor>) {
+    let pbm = display.to_pbm();
+
+    assert_snapshot(name, "pbm", &pbm, |expected| expected == pbm);
+}
+
+fn assert_png(name: &str, display: &Framebuffer<Rgb565>) {
+    let rgb = display.to_rgb888();
+
+    // Compare the decoded pixels rather than the encoded bytes, which may vary between `png` versions
+    assert_snapshot(name, "png", &display.to_png(), |expected| {
+        let mut decoder = png::Decoder::new(expected).read_info().unwrap();
+        let mut pixels = vec![0; decoder.output_buffer_size()];
+
+        let info = decoder.next_frame(&mut pixels).unwrap();
+
+        pixels[..info.buffer_size()] == rgb[..]
+    });
+}
+
+fn assert_snapshot(name: &str, ext: &str, actual: &[u8], matches: impl Fn(&[u8]) -> bool) {
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("snapshots");
+
+    let path = dir.join(format!("{name}.{ext}"));
+    let actual_path = dir.join(format!("{name}.actual.{ext}"));
+
+    if env::var_os("UPDATE_SNAPSHOTS").is_some() {
+        fs::write(&path, actual).unwrap();
+        let _ = fs::remove_file(&actual_path);
+
+        return;
+    }
+
+    match fs::read(&path) {
+        Ok(expected) if matches(&expected) => {
+            let _ = fs::remove_file(&actual_path);
+        }
+        Ok(_) => {
+            fs::write(&actual_path, actual).unwrap();
+
+            panic!(
+                "Snapshot {} does not match, see {}",
+                path.display(),
+                actual_path.display()
+            );
+        }
+        Err(_) => {
+            fs::write(&actual_path, actual).unwrap();
+
+            panic!(
+                "Snapshot {} is missing, see {}",
+                path.display(),
+                actual_path.display()
+            );
+        }
+    }
+}
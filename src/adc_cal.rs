@@ -0,0 +1,451 @@
+//! Multi-point ADC calibration
+//!
+//! The eFuse calibration done by `adc::config::Config::calibration(true)` is only accurate to a
+//! few percent. This module allows recording (raw reading, reference voltage) pairs per ADC channel
+//! via a small HTTP wizard, turns them into a piecewise-linear correction table which is persisted
+//! in NVS, and uses that table for all subsequent readings of the channel.
+//!
+//! The readings of a channel depend on its attenuation, so each attenuation of a channel has a
+//! table of its own.
+//!
+//! The tables themselves are in the `graphics` crate, so that they can be tested on the host. Only
+//! reading the ADC and persisting the tables in NVS is left here.
+
+use core::fmt::{self, Write as _};
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, bail, Result};
+
+use log::*;
+
+use embedded_hal::adc::Channel;
+
+use esp_idf_svc::hal::adc::{self, attenuation::adc_atten_t};
+use esp_idf_svc::hal::gpio::ADCPin;
+use esp_idf_svc::http::server::{EspHttpServer, Method};
+use esp_idf_svc::io::Write;
+use esp_idf_svc::nvs::{EspDefaultNvs, EspDefaultNvsPartition};
+use esp_idf_svc::sys::EspError;
+#[cfg(all(
+    any(esp32, esp32s2, esp32s3, esp32c3),
+    any(esp_idf_comp_esp_adc_cal_enabled, esp_idf_comp_esp_adc_enabled)
+))]
+use esp_idf_svc::sys::{
+    esp_adc_cal_characteristics_t, esp_adc_cal_characterize, esp_adc_cal_raw_to_voltage,
+};
+
+pub use graphics::adc_cal::{CalibrationPoint, CalibrationTable, ChannelId, MAX_POINTS};
+
+const NAMESPACE: &str = "adc_cal";
+
+// Each reading is averaged over that many raw samples, so as to reduce the noise
+// both during calibration and afterwards
+const SAMPLES: u32 = 16;
+
+// Without the tables, how readings are converted
+#[cfg(all(
+    any(esp32, esp32s2, esp32s3, esp32c3),
+    any(esp_idf_comp_esp_adc_cal_enabled, esp_idf_comp_esp_adc_enabled)
+))]
+const FALLBACK: &str = "eFuse calibration";
+#[cfg(not(all(
+    any(esp32, esp32s2, esp32s3, esp32c3),
+    any(esp_idf_comp_esp_adc_cal_enabled, esp_idf_comp_esp_adc_enabled)
+)))]
+const FALLBACK: &str = "ADC driver";
+
+/// The ADC unit and channel of the driver's pin, with the attenuation the driver uses
+pub fn channel_id<const A: adc_atten_t, T: ADCPin>(
+    _channel: &adc::AdcChannelDriver<'_, A, T>,
+) -> ChannelId {
+    let (channel, atten) = <adc::AdcChannelDriver<'_, A, T> as Channel<T::Adc>>::channel();
+
+    ChannelId {
+        unit: <T::Adc as adc::Adc>::unit(),
+        channel,
+        atten,
+    }
+}
+
+#[derive(Default)]
+struct ChannelState {
+    table: Option<CalibrationTable>,
+    #[cfg(all(
+        any(esp32, esp32s2, esp32s3, esp32c3),
+        any(esp_idf_comp_esp_adc_cal_enabled, esp_idf_comp_esp_adc_enabled)
+    ))]
+    efuse: Option<esp_adc_cal_characteristics_t>,
+    pending: Vec<CalibrationPoint>,
+    last_raw: Option<u16>,
+    last_mv: Option<u16>,
+}
+
+pub struct AdcCalibration {
+    nvs: Mutex<EspDefaultNvs>,
+    channels: Mutex<BTreeMap<ChannelId, ChannelState>>,
+}
+
+impl AdcCalibration {
+    pub fn new(partition: EspDefaultNvsPartition) -> Result<Self, EspError> {
+        Ok(Self {
+            nvs: Mutex::new(EspDefaultNvs::new(partition, NAMESPACE, true)?),
+            channels: Mutex::new(BTreeMap::new()),
+        })
+    }
+
+    /// Reads the channel in millivolts, using the channel's calibration table if one was
+    /// recorded, or the eFuse calibration otherwise (the conversion of the ADC driver, on the chips
+    /// which `esp_adc_cal` does not support)
+    ///
+    /// The table and the eFuse calibration convert the average of the raw samples, which is also
+    /// what gets recorded as a calibration point.
+    pub fn read<ADC, const A: adc_atten_t, T>(
+        &self,
+        adc: &mut adc::AdcDriver<'_, ADC>,
+        channel: &mut adc::AdcChannelDriver<'_, A, T>,
+    ) -> Result<u16>
+    where
+        ADC: adc::Adc,
+        T: ADCPin<Adc = ADC>,
+    {
+        let id = channel_id(channel);
+
+        let mut raw = 0;
+        for _ in 0..SAMPLES {
+            raw += adc.read_raw(channel)? as u32;
+        }
+
+        let raw = (raw / SAMPLES) as u16;
+
+        let mut channels = self.channels.lock().unwrap();
+
+        if !channels.contains_key(&id) {
+            let table = self.load(id)?;
+
+            if table.is_some() {
+                info!("Using the NVS calibration table for ADC channel {}", id);
+            }
+
+            channels.insert(
+                id,
+                ChannelState {
+                    table,
+                    ..Default::default()
+                },
+            );
+        }
+
+        let state = channels.get_mut(&id).unwrap();
+
+        let mv = if let Some(table) = &state.table {
+            table.apply(raw)
+        } else {
+            #[cfg(all(
+                any(esp32, esp32s2, esp32s3, esp32c3),
+                any(esp_idf_comp_esp_adc_cal_enabled, esp_idf_comp_esp_adc_enabled)
+            ))]
+            let mv = {
+                let efuse = state.efuse.get_or_insert_with(|| efuse_characteristics(id));
+
+                unsafe { esp_adc_cal_raw_to_voltage(raw as u32, efuse) as u16 }
+            };
+
+            // Without `esp_adc_cal`, the conversion of the ADC driver, averaged as well
+            #[cfg(not(all(
+                any(esp32, esp32s2, esp32s3, esp32c3),
+                any(esp_idf_comp_esp_adc_cal_enabled, esp_idf_comp_esp_adc_enabled)
+            )))]
+            let mv = {
+                let mut mv = 0;
+                for _ in 0..SAMPLES {
+                    mv += adc.read(channel)? as u32;
+                }
+
+                (mv / SAMPLES) as u16
+            };
+
+            mv
+        };
+
+        state.last_raw = Some(raw);
+        state.last_mv = Some(mv);
+
+        Ok(mv)
+    }
+
+    /// Records the latest raw reading of the channel as corresponding to the supplied reference voltage
+    pub fn record(&self, id: ChannelId, mv: u16) -> Result<CalibrationPoint> {
+        let mut channels = self.channels.lock().unwrap();
+        let state = channels
+            .get_mut(&id)
+            .ok_or_else(|| anyhow!("ADC channel {} was not read yet", id))?;
+
+        let raw = state
+            .last_raw
+            .ok_or_else(|| anyhow!("No reading for ADC channel {} yet", id))?;
+
+        if state.pending.len() >= MAX_POINTS {
+            bail!("At most {} calibration points are supported", MAX_POINTS);
+        }
+
+        let point = CalibrationPoint { raw, mv };
+        state.pending.push(point);
+
+        Ok(point)
+    }
+
+    /// Turns the recorded points into a calibration table, and persists it in NVS
+    pub fn save(&self, id: ChannelId) -> Result<()> {
+        let mut channels = self.channels.lock().unwrap();
+        let state = channels
+            .get_mut(&id)
+            .ok_or_else(|| anyhow!("ADC channel {} was not read yet", id))?;
+
+        let table = CalibrationTable::new(&state.pending)?;
+
+        self.nvs
+            .lock()
+            .unwrap()
+            .set_blob(&id.to_string(), &table.to_bytes())?;
+
+        info!("Saved calibration table {:?} for ADC channel {}", table, id);
+
+        state.table = Some(table);
+        state.pending.clear();
+
+        Ok(())
+    }
+
+    /// Drops the recorded points as well as the persisted calibration table, if any,
+    /// thus reverting the channel to the eFuse calibration
+    pub fn reset(&self, id: ChannelId) -> Result<()> {
+        let mut channels = self.channels.lock().unwrap();
+
+        self.nvs.lock().unwrap().remove(&id.to_string())?;
+
+        if let Some(state) = channels.get_mut(&id) {
+            state.table = None;
+            state.pending.clear();
+        }
+
+        info!("Removed the calibration table for ADC channel {}", id);
+
+        Ok(())
+    }
+
+    fn load(&self, id: ChannelId) -> Result<Option<CalibrationTable>> {
+        let mut buf = [0_u8; MAX_POINTS * 4];
+
+        let nvs = self.nvs.lock().unwrap();
+
+        match nvs.get_blob(&id.to_string(), &mut buf)? {
+            Some(data) => match CalibrationTable::from_bytes(data) {
+                Ok(table) => Ok(Some(table)),
+                Err(err) => {
+                    warn!(
+                        "Ignoring the NVS calibration table for ADC channel {}: {}",
+                        id, err
+                    );
+                    Ok(None)
+                }
+            },
+            None => Ok(None),
+        }
+    }
+
+    fn render(&self) -> Result<String, fmt::Error> {
+        let channels = self.channels.lock().unwrap();
+
+        let mut html = String::new();
+
+        write!(
+            &mut html,
+            r#"
+            <doctype html5>
+            <html>
+                <body>
+                    <h1>ADC calibration</h1>
+                    <p>
+                        For each calibration point, apply a known reference voltage to the channel,
+                        wait for the reading to settle and then record it. Record at least two points
+                        covering the range you are interested in, then save the table.
+                    </p>
+            "#
+        )?;
+
+        for (id, state) in channels.iter() {
+            write!(
+                &mut html,
+                r#"
+                    <h2>{id}</h2>
+                    <p>Last raw reading: {raw}, last voltage: {mv}mV ({source})</p>
+                "#,
+                raw = state
+                    .last_raw
+                    .map(|raw| raw.to_string())
+                    .unwrap_or_else(|| "-".into()),
+                mv = state
+                    .last_mv
+                    .map(|mv| mv.to_string())
+                    .unwrap_or_else(|| "-".into()),
+                source = if state.table.is_some() {
+                    "calibration table"
+                } else {
+                    FALLBACK
+                },
+            )?;
+
+            if let Some(table) = &state.table {
+                write!(&mut html, "<p>Calibration table:")?;
+
+                for point in table.points() {
+                    write!(&mut html, " {}&rarr;{}mV", point.raw, point.mv)?;
+                }
+
+                write!(&mut html, "</p>")?;
+            }
+
+            write!(&mut html, "<p>Recorded points:")?;
+
+            for point in &state.pending {
+                write!(&mut html, " {}&rarr;{}mV", point.raw, point.mv)?;
+            }
+
+            write!(
+                &mut html,
+                r#"
+                    </p>
+                    <form method = "post" action = "/adc_cal/record" enctype="application/x-www-form-urlencoded">
+                        <input name = "channel" type = "hidden" value = "{id}">
+                        Reference voltage <input name = "mv" type = "text" value = "1000"> mV
+                        <input type = "submit" value = "Record">
+                    </form>
+                    <form method = "post" action = "/adc_cal/save" enctype="application/x-www-form-urlencoded">
+                        <input name = "channel" type = "hidden" value = "{id}">
+                        <input type = "submit" value = "Save table">
+                    </form>
+                    <form method = "post" action = "/adc_cal/reset" enctype="application/x-www-form-urlencoded">
+                        <input name = "channel" type = "hidden" value = "{id}">
+                        <input type = "submit" value = "Revert to {FALLBACK}">
+                    </form>
+                "#
+            )?;
+        }
+
+        write!(
+            &mut html,
+            r#"
+                </body>
+            </html>
+            "#
+        )?;
+
+        Ok(html)
+    }
+}
+
+/// The eFuse calibration of the channel at the default resolution, which is the one the ADC
+/// driver of the demo uses
+#[cfg(all(
+    any(esp32, esp32s2, esp32s3, esp32c3),
+    any(esp_idf_comp_esp_adc_cal_enabled, esp_idf_comp_esp_adc_enabled)
+))]
+fn efuse_characteristics(id: ChannelId) -> esp_adc_cal_characteristics_t {
+    let mut characteristics = Default::default();
+
+    unsafe {
+        esp_adc_cal_characterize(
+            id.unit,
+            id.atten,
+            adc::config::Resolution::default().into(),
+            0,
+            &mut characteristics,
+        )
+    };
+
+    characteristics
+}
+
+pub fn httpd_endpoints(
+    server: &mut EspHttpServer<'static>,
+    calibration: Arc<AdcCalibration>,
+) -> Result<()> {
+    fn channel(form: &[(String, String)]) -> Result<ChannelId> {
+        crate::form_param(form, "channel")?.parse()
+    }
+
+    let page = calibration.clone();
+    let record = calibration.clone();
+    let save = calibration.clone();
+    let reset = calibration;
+
+    server
+        .fn_handler("/adc_cal", Method::Get, move |req| {
+            let html = page.render()?;
+
+            req.into_ok_response()?.write_all(html.as_bytes())?;
+
+            Result::<_, anyhow::Error>::Ok(())
+        })?
+        .fn_handler("/adc_cal/record", Method::Post, move |mut req| {
+            let Some(form) = crate::read_form(&mut req)? else {
+                return Ok(());
+            };
+
+            let recorded = channel(&form).and_then(|id| {
+                let mv = crate::form_param(&form, "mv")?.parse::<u16>()?;
+
+                Ok((id, record.record(id, mv)?))
+            });
+
+            let (id, point) = match recorded {
+                Ok(recorded) => recorded,
+                Err(err) => return crate::bad_request(req, err),
+            };
+
+            info!(
+                "Recorded calibration point {:?} for ADC channel {}",
+                point, id
+            );
+
+            req.into_response(303, Some("See Other"), &[("Location", "/adc_cal")])?;
+
+            Result::<_, anyhow::Error>::Ok(())
+        })?
+        .fn_handler("/adc_cal/save", Method::Post, move |mut req| {
+            let Some(form) = crate::read_form(&mut req)? else {
+                return Ok(());
+            };
+
+            let id = match channel(&form) {
+                Ok(id) => id,
+                Err(err) => return crate::bad_request(req, err),
+            };
+
+            save.save(id)?;
+
+            req.into_response(303, Some("See Other"), &[("Location", "/adc_cal")])?;
+
+            Result::<_, anyhow::Error>::Ok(())
+        })?
+        .fn_handler("/adc_cal/reset", Method::Post, move |mut req| {
+            let Some(form) = crate::read_form(&mut req)? else {
+                return Ok(());
+            };
+
+            let id = match channel(&form) {
+                Ok(id) => id,
+                Err(err) => return crate::bad_request(req, err),
+            };
+
+            reset.reset(id)?;
+
+            req.into_response(303, Some("See Other"), &[("Location", "/adc_cal")])?;
+
+            Result::<_, anyhow::Error>::Ok(())
+        })?;
+
+    Ok(())
+}
@@ -0,0 +1,290 @@
+//! Click, double-click and long-press events of the board buttons, posted to the event loop
+//!
+//! The buttons are inputs with interrupts on both edges. The interrupts only wake up the button
+//! thread, which debounces the levels and turns the presses and releases into `ButtonEvent`s.
+//! What the events do is up to the subscribers of the event loop; `subscribe_default_actions`
+//! cycles the display pages on clicks and resets to the factory settings on a long press of the
+//! BOOT button.
+
+use core::ffi::CStr;
+use core::num::NonZeroU32;
+
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+
+use log::*;
+
+use esp_idf_svc::eventloop::*;
+use esp_idf_svc::hal::delay::{self, TickType};
+use esp_idf_svc::hal::gpio::{AnyIOPin, Input, InterruptType, PinDriver, Pull};
+use esp_idf_svc::hal::task::notification::Notification;
+
+use crate::display::DisplayService;
+use crate::gpio_api::GpioService;
+
+// The BOOT button of the devkits, which pulls the strapping pin low
+#[cfg(not(esp32c3))]
+const BUTTONS: &[(&str, i32)] = &[("boot", 0)];
+#[cfg(esp32c3)]
+const BUTTONS: &[(&str, i32)] = &[("boot", 9)];
+
+// How long the level has to be stable to count as a press or a release
+const DEBOUNCE: Duration = Duration::from_millis(30);
+// How long after the release of a click the second click of a double-click may start
+const DOUBLE_CLICK: Duration = Duration::from_millis(350);
+const LONG_PRESS: Duration = Duration::from_secs(3);
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Gesture {
+    Click,
+    DoubleClick,
+    LongPress,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct ButtonEvent {
+    pub button: &'static str,
+    pub gesture: Gesture,
+}
+
+unsafe impl EspEventSource for ButtonEvent {
+    fn source() -> Option<&'static CStr> {
+        // String should be unique across the whole project and ESP IDF
+        Some(CStr::from_bytes_with_nul(b"DEMO-BUTTONS\0").unwrap())
+    }
+}
+
+impl EspEventSerializer for ButtonEvent {
+    type Data<'a> = ButtonEvent;
+
+    fn serialize<F, R>(event: &Self::Data<'_>, f: F) -> R
+    where
+        F: FnOnce(&EspEventPostData) -> R,
+    {
+        // The payload is `Copy` and only refers to static strings
+        f(&unsafe { EspEventPostData::new(Self::source().unwrap(), Self::event_id(), event) })
+    }
+}
+
+impl EspEventDeserializer for ButtonEvent {
+    type Data<'a> = ButtonEvent;
+
+    fn deserialize<'a>(data: &EspEvent<'a>) -> Self::Data<'a> {
+        *unsafe { data.as_payload::<ButtonEvent>() }
+    }
+}
+
+/// Claims the pins of the board buttons and spawns the thread which posts their events
+///
+/// Buttons whose pins are in use by something else - like the RMII clock of the IP101 - are
+/// skipped.
+pub fn spawn(gpios: &GpioService, eventloop: EspBackgroundEventLoop) -> Result<()> {
+    let mut buttons = Vec::new();
+
+    for &(name, pin) in BUTTONS {
+        match gpios.claim(pin, "button") {
+            Ok(pin) => buttons.push(Button::new(name, pin)?),
+            Err(err) => warn!("Not using the {} button: {}", name, err),
+        }
+    }
+
+    if buttons.is_empty() {
+        return Ok(());
+    }
+
+    thread::Builder::new()
+        .name("buttons".into())
+        .stack_size(4096)
+        .spawn(move || {
+            if let Err(err) = run(&mut buttons, &eventloop) {
+                warn!("Handling the buttons failed: {}", err);
+            }
+        })?;
+
+    info!("Buttons started: {:?}", BUTTONS);
+
+    Ok(())
+}
+
+/// Page through the display on clicks, and reset to the factory settings on a long press of
+/// the BOOT button
+pub fn subscribe_default_actions(
+    eventloop: &EspBackgroundEventLoop,
+    display_service: Arc<DisplayService>,
+) -> Result<EspBackgroundSubscription<'static>> {
+    let subscription = eventloop.subscribe::<ButtonEvent, _>(move |event| {
+        info!("Button {}: {:?}", event.button, event.gesture);
+
+        match (event.button, event.gesture) {
+            (_, Gesture::Click) => display_service.set_page(display_service.page().next()),
+            ("boot", Gesture::LongPress) => {
+                if let Err(err) = factory_reset() {
+                    warn!("Factory reset failed: {}", err);
+                }
+            }
+            _ => display_service.wake(),
+        }
+    })?;
+
+    Ok(subscription)
+}
+
+/// Erases the configuration stored in NVS - like the ADC calibration - and restarts
+fn factory_reset() -> Result<()> {
+    warn!("Factory reset: erasing NVS and restarting");
+
+    esp_idf_svc::sys::esp!(unsafe { esp_idf_svc::sys::nvs_flash_erase() })?;
+
+    unsafe { esp_idf_svc::sys::esp_restart() }
+}
+
+fn run(buttons: &mut [Button], eventloop: &EspBackgroundEventLoop) -> Result<()> {
+    // The notification has to be created on the thread which waits for it
+    let notification = Notification::new();
+
+    for (index, button) in buttons.iter_mut().enumerate() {
+        let notifier = notification.notifier();
+        let bit = NonZeroU32::new(1 << index).unwrap();
+
+        // Only notifies the thread, which is safe in an ISR
+        unsafe {
+            button.driver.subscribe(move || {
+                notifier.notify_and_yield(bit);
+            })?;
+        }
+
+        button.driver.enable_interrupt()?;
+    }
+
+    loop {
+        let now = Instant::now();
+
+        let timeout = buttons
+            .iter()
+            .filter_map(|button| button.deadline())
+            .min()
+            .map(|deadline| TickType::from(deadline.saturating_duration_since(now)).ticks())
+            .unwrap_or(delay::BLOCK);
+
+        notification.wait(timeout);
+
+        let now = Instant::now();
+
+        for button in buttons.iter_mut() {
+            // The driver disables the interrupt whenever it fires
+            button.driver.enable_interrupt()?;
+
+            if let Some(gesture) = button.update(now) {
+                eventloop.post::<ButtonEvent>(
+                    &ButtonEvent {
+                        button: button.name,
+                        gesture,
+                    },
+                    delay::NON_BLOCK,
+                )?;
+            }
+        }
+    }
+}
+
+struct Button {
+    name: &'static str,
+    driver: PinDriver<'static, AnyIOPin, Input>,
+    // The debounced state
+    pressed: bool,
+    // Since when the level differs from the debounced state
+    changed_at: Option<Instant>,
+    pressed_at: Instant,
+    long_press: bool,
+    // A click which may still become a double-click
+    clicked_at: Option<Instant>,
+}
+
+impl Button {
+    fn new(name: &'static str, pin: AnyIOPin) -> Result<Self> {
+        let mut driver = PinDriver::input(pin)?;
+
+        driver.set_pull(Pull::Up)?;
+        driver.set_interrupt_type(InterruptType::AnyEdge)?;
+
+        Ok(Self {
+            name,
+            driver,
+            pressed: false,
+            changed_at: None,
+            pressed_at: Instant::now(),
+            long_press: false,
+            clicked_at: None,
+        })
+    }
+
+    /// When `update` has to be called again, even if the level does not change
+    fn deadline(&self) -> Option<Instant> {
+        [
+            self.changed_at.map(|at| at + DEBOUNCE),
+            (self.pressed && !self.long_press).then(|| self.pressed_at + LONG_PRESS),
+            self.clicked_at.map(|at| at + DOUBLE_CLICK),
+        ]
+        .into_iter()
+        .flatten()
+        .min()
+    }
+
+    fn update(&mut self, now: Instant) -> Option<Gesture> {
+        // The buttons pull their pins low
+        let level = self.driver.is_low();
+
+        if level == self.pressed {
+            self.changed_at = None;
+        } else {
+            let changed_at = *self.changed_at.get_or_insert(now);
+
+            if now - changed_at >= DEBOUNCE {
+                self.pressed = level;
+                self.changed_at = None;
+
+                if let Some(gesture) = self.debounced(now) {
+                    return Some(gesture);
+                }
+            }
+        }
+
+        if self.pressed && !self.long_press && now - self.pressed_at >= LONG_PRESS {
+            self.long_press = true;
+            self.clicked_at = None;
+
+            return Some(Gesture::LongPress);
+        }
+
+        if !self.pressed && self.clicked_at.is_some_and(|at| now - at >= DOUBLE_CLICK) {
+            self.clicked_at = None;
+
+            return Some(Gesture::Click);
+        }
+
+        None
+    }
+
+    // Called on each debounced press and release
+    fn debounced(&mut self, now: Instant) -> Option<Gesture> {
+        if self.pressed {
+            self.pressed_at = now;
+            self.long_press = false;
+
+            return None;
+        }
+
+        if self.long_press {
+            None
+        } else if self.clicked_at.take().is_some() {
+            Some(Gesture::DoubleClick)
+        } else {
+            self.clicked_at = Some(now);
+
+            None
+        }
+    }
+}
@@ -0,0 +1,116 @@
+//! The data source of the live status dashboard page of the display
+//!
+//! The dashboard shows the IP address, Wifi RSSI, SNTP time, uptime and the latest ADC reading.
+//! The recent ADC readings are also kept, for the chart page. The IP address follows the DHCP
+//! leases, see `subscribe_ip`.
+//! The drawing itself lives in the `graphics` crate, so that it can be snapshot-tested on the host.
+
+use core::time::Duration;
+
+use std::net::Ipv4Addr;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use anyhow::Result;
+
+use esp_idf_svc::eventloop::{EspSubscription, EspSystemEventLoop, System};
+use esp_idf_svc::netif::IpEvent;
+
+pub use graphics::chart::History;
+pub use graphics::dashboard::Snapshot;
+
+// Any wall clock time before that means that SNTP has not synced yet
+const MIN_SYNCED_TIME: Duration = Duration::from_secs(1_700_000_000);
+
+/// The values shown on the dashboard which are not directly queryable from the renderer thread
+///
+/// Also the latest temperature reading, which is not on the dashboard but published to Home
+/// Assistant along the other values.
+pub struct Status {
+    ip: Mutex<Option<Ipv4Addr>>,
+    adc_mv: Mutex<Option<u16>>,
+    temperature: Mutex<Option<f32>>,
+    // The recent ADC readings, for the chart page
+    history: Mutex<History>,
+}
+
+impl Status {
+    pub fn new() -> Self {
+        Self {
+            ip: Mutex::new(None),
+            adc_mv: Mutex::new(None),
+            temperature: Mutex::new(None),
+            history: Mutex::new(History::default()),
+        }
+    }
+
+    pub fn set_ip(&self, ip: Ipv4Addr) {
+        *self.ip.lock().unwrap() = Some(ip);
+    }
+
+    pub fn clear_ip(&self) {
+        *self.ip.lock().unwrap() = None;
+    }
+
+    pub fn set_adc_mv(&self, mv: u16) {
+        *self.adc_mv.lock().unwrap() = Some(mv);
+        self.history.lock().unwrap().push(mv as _);
+    }
+
+    pub fn set_temperature(&self, celsius: f32) {
+        *self.temperature.lock().unwrap() = Some(celsius);
+    }
+
+    pub fn temperature(&self) -> Option<f32> {
+        *self.temperature.lock().unwrap()
+    }
+
+    pub fn adc_history(&self) -> History {
+        self.history.lock().unwrap().clone()
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        let time = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .ok()
+            .filter(|time| *time >= MIN_SYNCED_TIME);
+
+        Snapshot {
+            ip: *self.ip.lock().unwrap(),
+            rssi: rssi(),
+            time,
+            uptime: Duration::from_micros(unsafe { esp_idf_svc::sys::esp_timer_get_time() } as _),
+            adc_mv: *self.adc_mv.lock().unwrap(),
+        }
+    }
+}
+
+/// Keeps the IP address of the status up to date with the DHCP leases, which change when the
+/// Wifi reconnects or the lease is renewed
+pub fn subscribe_ip(
+    sysloop: &EspSystemEventLoop,
+    status: Arc<Status>,
+) -> Result<EspSubscription<'static, System>> {
+    let subscription = sysloop.subscribe::<IpEvent, _>(move |event| match event {
+        IpEvent::DhcpIpAssigned(assignment) => status.set_ip(assignment.ip()),
+        IpEvent::DhcpIpDeassigned(_) => status.clear_ip(),
+        _ => (),
+    })?;
+
+    Ok(subscription)
+}
+
+#[cfg(not(feature = "qemu"))]
+fn rssi() -> Option<i8> {
+    let mut ap_info = esp_idf_svc::sys::wifi_ap_record_t::default();
+
+    // Fails if the station is not connected
+    esp_idf_svc::sys::esp!(unsafe { esp_idf_svc::sys::esp_wifi_sta_get_ap_info(&mut ap_info) })
+        .ok()
+        .map(|_| ap_info.rssi)
+}
+
+#[cfg(feature = "qemu")]
+fn rssi() -> Option<i8> {
+    None
+}
@@ -0,0 +1,805 @@
+//! The board's display, driven by a renderer thread
+//!
+//! The board functions in `main()` hand over their display to the renderer thread, which
+//! periodically draws the currently selected page - the status dashboard, the log console, a
+//! QR code for the device URL or for joining its SoftAP, an uploaded picture, or a chart of the
+//! recent ADC readings. The page can be switched, pictures uploaded, and screenshots taken over
+//! HTTP.
+//!
+//! Text messages - e.g. received over MQTT - are queued, and shown one after the other instead of
+//! the page, each for its own duration.
+//!
+//! Without any activity - a page switch, an uploaded picture, a message, or later on a button press
+//! or a touch - the display is dimmed and then blanked, until the next activity wakes it up.
+//!
+//! What the display shows is kept in a mirror, for the screenshots and the live view. For the SPI
+//! panels, the mirror is also the back buffer which the renderer draws into; a thread of its own
+//! then flushes the changed areas to the panel, so that nobody waits for the slow SPI transfers.
+//! Without the back buffer, the renderer draws to the panel and the mirror alike; only if the heap
+//! has no room for the 2 bytes per pixel of a color panel, the display has no mirror.
+
+use core::fmt::Debug;
+use core::time::Duration;
+
+use std::collections::VecDeque;
+use std::sync::mpsc::{self, SyncSender};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::Instant;
+
+use anyhow::Result;
+
+use log::*;
+
+use esp_idf_svc::http::server::{EspHttpConnection, EspHttpServer, Method, Request};
+use esp_idf_svc::io::Write;
+use esp_idf_svc::sys::{
+    heap_caps_get_free_size, heap_caps_get_largest_free_block, MALLOC_CAP_8BIT,
+};
+
+pub use graphics::message::Message;
+pub use graphics::mirror::{Mirror, Mirrored};
+pub use graphics::picture::{ColorMode, Picture};
+pub use graphics::power::PowerState;
+pub use graphics::qr::WifiNetwork;
+#[cfg(feature = "slint")]
+pub use graphics::ui::UiScreen;
+pub use graphics::{Frame, Page, Screen, Theme};
+
+use embedded_graphics::draw_target::Cropped;
+use embedded_graphics::pixelcolor::BinaryColor;
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::Rectangle;
+
+use graphics::bmp::BmpColor;
+use graphics::mirror::{self, PackedColor};
+use graphics::picture::DecodeError;
+use graphics::power::PowerPolicy;
+
+use crate::dashboard::Status;
+use crate::log_console;
+use crate::pwm::PwmService;
+
+// Enough for a 1 bpp BMP or a PNG of any of the supported displays, and for a 24 bpp BMP of most
+const MAX_UPLOAD_SIZE: usize = 192 * 1024;
+
+// The stack of the PNG decoder's inflate, which does not fit in the HTTP server's 6KB
+const DECODE_STACK_SIZE: usize = 16 * 1024;
+
+// What must remain of the heap for the WiFi, the HTTP server and the rest, after the display's
+// buffers are allocated
+const MIN_FREE_HEAP: usize = 48 * 1024;
+
+const MAX_QUEUED_MESSAGES: usize = 8;
+
+// 8KB strips at 16 bpp, so that flushing a full page does not need another frame buffer
+const FLUSH_STRIP_PIXELS: usize = 4096;
+
+/// A copy of what the display shows
+pub trait DisplayMirror: Send + Sync {
+    fn size(&self) -> Size;
+
+    /// The area which changed since the previous call
+    fn take_dirty(&self) -> Option<Rectangle>;
+
+    /// Writes the area as a BMP file, piece by piece
+    fn write_bmp(&self, area: &Rectangle, out: &mut dyn FnMut(&[u8]) -> Result<()>) -> Result<()>;
+}
+
+impl<C> DisplayMirror for Mutex<Mirror<C>>
+where
+    C: BmpColor + PackedColor + Send,
+{
+    fn size(&self) -> Size {
+        self.lock().unwrap().size()
+    }
+
+    fn take_dirty(&self) -> Option<Rectangle> {
+        self.lock().unwrap().take_dirty()
+    }
+
+    fn write_bmp(&self, area: &Rectangle, out: &mut dyn FnMut(&[u8]) -> Result<()>) -> Result<()> {
+        mirror::write_bmp(self, area, out)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct PowerConfig {
+    /// After how long without any activity the display is dimmed; `None` to never dim it
+    pub dim_after: Option<Duration>,
+    /// After how long without any activity the display is blanked; `None` to never blank it
+    pub blank_after: Option<Duration>,
+    /// The backlight brightness while dimmed, in percent
+    pub dimmed_brightness: f32,
+}
+
+impl Default for PowerConfig {
+    /// Overridable at build time with the `RUST_ESP32_STD_DEMO_DISPLAY_DIM_SECS` and
+    /// `RUST_ESP32_STD_DEMO_DISPLAY_BLANK_SECS` environment variables, where 0 disables either
+    fn default() -> Self {
+        let secs = |secs: Option<&str>, default| {
+            Some(secs.and_then(|secs| secs.parse().ok()).unwrap_or(default))
+                .filter(|secs| *secs > 0)
+                .map(Duration::from_secs)
+        };
+
+        Self {
+            dim_after: secs(option_env!("RUST_ESP32_STD_DEMO_DISPLAY_DIM_SECS"), 60),
+            blank_after: secs(option_env!("RUST_ESP32_STD_DEMO_DISPLAY_BLANK_SECS"), 300),
+            dimmed_brightness: 10.0,
+        }
+    }
+}
+
+/// The monochrome OLEDs, which keep a frame buffer in the driver and have no backlight
+pub trait Oled: DrawTarget<Color = BinaryColor> {
+    /// Sends what was drawn since the previous flush to the panel
+    fn flush(&mut self) -> Result<()>;
+
+    /// Dims and blanks the panel itself
+    fn set_power(&mut self, power: PowerState) -> Result<()>;
+}
+
+impl<DI, SIZE> Oled for ssd1306::Ssd1306<DI, SIZE, ssd1306::mode::BufferedGraphicsMode<SIZE>>
+where
+    DI: display_interface::WriteOnlyDataCommand,
+    SIZE: ssd1306::size::DisplaySize,
+{
+    fn flush(&mut self) -> Result<()> {
+        ssd1306::Ssd1306::flush(self).map_err(oled_error)
+    }
+
+    fn set_power(&mut self, power: PowerState) -> Result<()> {
+        let brightness = match power {
+            PowerState::On => ssd1306::prelude::Brightness::NORMAL,
+            PowerState::Dimmed => ssd1306::prelude::Brightness::DIMMEST,
+            PowerState::Blanked => return self.set_display_on(false).map_err(oled_error),
+        };
+
+        self.set_display_on(true)
+            .and_then(|_| self.set_brightness(brightness))
+            .map_err(oled_error)
+    }
+}
+
+fn oled_error(err: display_interface::DisplayError) -> anyhow::Error {
+    anyhow::anyhow!("Display error: {:?}", err)
+}
+
+/// The back buffer of a display, see `DisplayService::back_buffer()`
+pub struct BackBuffer<D>
+where
+    D: DrawTarget,
+{
+    buffering: Buffering<D>,
+}
+
+enum Buffering<D>
+where
+    D: DrawTarget,
+{
+    /// Flushed to the display by a thread of its own
+    Buffered {
+        buffer: Arc<Mutex<Mirror<D::Color>>>,
+        flush: SyncSender<()>,
+    },
+    /// Without a back buffer, drawn to the display itself, and through `Mirrored` to its mirror
+    /// if there is one
+    Direct {
+        display: D,
+        area: Rectangle,
+        mirror: Option<Arc<Mutex<Mirror<D::Color>>>>,
+    },
+}
+
+impl<D> BackBuffer<D>
+where
+    D: DrawTarget,
+    D::Color: PackedColor,
+{
+    /// Draws into the back buffer, and has the changes flushed to the display without waiting
+    ///
+    /// Without a back buffer, draws to the display itself and waits for it.
+    pub fn draw<R>(&mut self, draw: impl FnOnce(&mut BackBufferTarget<'_, D>) -> R) -> R {
+        match &mut self.buffering {
+            Buffering::Buffered { buffer, flush } => {
+                let result = draw(&mut BackBufferTarget::Buffer(buffer.lock().unwrap()));
+
+                // If the channel is full, a pending flush picks up these changes as well
+                let _ = flush.try_send(());
+
+                result
+            }
+            Buffering::Direct {
+                display,
+                area,
+                mirror: Some(mirror),
+            } => draw(&mut BackBufferTarget::Mirrored(
+                display.cropped(area),
+                mirror.lock().unwrap(),
+            )),
+            Buffering::Direct {
+                display,
+                area,
+                mirror: None,
+            } => draw(&mut BackBufferTarget::Display(display.cropped(area))),
+        }
+    }
+}
+
+/// What `BackBuffer::draw()` draws into
+pub enum BackBufferTarget<'a, D>
+where
+    D: DrawTarget,
+{
+    Buffer(MutexGuard<'a, Mirror<D::Color>>),
+    /// Drawn through `Mirrored`
+    Mirrored(Cropped<'a, D>, MutexGuard<'a, Mirror<D::Color>>),
+    Display(Cropped<'a, D>),
+}
+
+impl<D> DrawTarget for BackBufferTarget<'_, D>
+where
+    D: DrawTarget,
+    D::Color: PackedColor,
+{
+    type Color = D::Color;
+    type Error = D::Error;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        match self {
+            Self::Buffer(buffer) => buffer.draw_iter(pixels).map_err(|err| match err {}),
+            Self::Mirrored(display, mirror) => Mirrored::new(display, mirror).draw_iter(pixels),
+            Self::Display(display) => display.draw_iter(pixels),
+        }
+    }
+
+    fn fill_contiguous<I>(&mut self, area: &Rectangle, colors: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Self::Color>,
+    {
+        match self {
+            Self::Buffer(buffer) => buffer
+                .fill_contiguous(area, colors)
+                .map_err(|err| match err {}),
+            Self::Mirrored(display, mirror) => {
+                Mirrored::new(display, mirror).fill_contiguous(area, colors)
+            }
+            Self::Display(display) => display.fill_contiguous(area, colors),
+        }
+    }
+
+    fn fill_solid(&mut self, area: &Rectangle, color: Self::Color) -> Result<(), Self::Error> {
+        match self {
+            Self::Buffer(buffer) => buffer.fill_solid(area, color).map_err(|err| match err {}),
+            Self::Mirrored(display, mirror) => {
+                Mirrored::new(display, mirror).fill_solid(area, color)
+            }
+            Self::Display(display) => display.fill_solid(area, color),
+        }
+    }
+}
+
+impl<D> Dimensions for BackBufferTarget<'_, D>
+where
+    D: DrawTarget,
+    D::Color: PackedColor,
+{
+    fn bounding_box(&self) -> Rectangle {
+        match self {
+            Self::Buffer(buffer) => buffer.bounding_box(),
+            Self::Mirrored(display, _) => display.bounding_box(),
+            Self::Display(display) => display.bounding_box(),
+        }
+    }
+}
+
+pub struct DisplayService {
+    status: Arc<Status>,
+    pwm: Arc<PwmService>,
+    started: Instant,
+    dimmed_brightness: f32,
+    power: Mutex<PowerPolicy>,
+    page: Mutex<Page>,
+    access_point: Mutex<Option<WifiNetwork>>,
+    picture: Mutex<Option<Arc<Picture>>>,
+    picture_decoder: Mutex<Option<PictureDecoder>>,
+    mirror: Mutex<Option<Arc<dyn DisplayMirror>>>,
+    messages: Mutex<Messages>,
+}
+
+// Decodes uploaded pictures for the display
+type PictureDecoder = Arc<dyn Fn(&[u8]) -> Result<Picture, DecodeError> + Send + Sync>;
+
+#[derive(Default)]
+struct Messages {
+    // The message on the display, and when it expires
+    current: Option<(Arc<Message>, Instant)>,
+    queue: VecDeque<Arc<Message>>,
+}
+
+impl DisplayService {
+    pub fn new(status: Arc<Status>, pwm: Arc<PwmService>, power: &PowerConfig) -> Self {
+        Self {
+            status,
+            pwm,
+            started: Instant::now(),
+            dimmed_brightness: power.dimmed_brightness,
+            power: Mutex::new(PowerPolicy::new(
+                power.dim_after,
+                power.blank_after,
+                Duration::ZERO,
+            )),
+            page: Mutex::new(Page::default()),
+            access_point: Mutex::new(None),
+            picture: Mutex::new(None),
+            picture_decoder: Mutex::new(None),
+            mirror: Mutex::new(None),
+            messages: Mutex::new(Messages::default()),
+        }
+    }
+
+    pub fn page(&self) -> Page {
+        *self.page.lock().unwrap()
+    }
+
+    pub fn set_page(&self, page: Page) {
+        *self.page.lock().unwrap() = page;
+
+        info!("Display page set to {}", page);
+
+        self.wake();
+    }
+
+    /// Records activity, which turns the display back on if it was dimmed or blanked
+    pub fn wake(&self) {
+        let state = self.power.lock().unwrap().wake(self.started.elapsed());
+
+        if let Some(state) = state {
+            self.apply_power(state);
+        }
+    }
+
+    pub fn power(&self) -> PowerState {
+        self.power.lock().unwrap().state()
+    }
+
+    // Dims or blanks the display once it has been idle for long enough
+    fn tick_power(&self) -> PowerState {
+        let mut power = self.power.lock().unwrap();
+
+        if let Some(state) = power.tick(self.started.elapsed()) {
+            self.apply_power(state);
+        }
+
+        power.state()
+    }
+
+    // The backlight is handled here; the panels themselves are handled by their renderers
+    fn apply_power(&self, state: PowerState) {
+        info!("Display power: {}", state);
+
+        let brightness = match state {
+            PowerState::On => 100.0,
+            PowerState::Dimmed => self.dimmed_brightness,
+            PowerState::Blanked => 0.0,
+        };
+
+        if let Err(err) = self.pwm.set_backlight(brightness) {
+            warn!("Setting the backlight brightness failed: {}", err);
+        }
+    }
+
+    /// The SoftAP of the device, rendered as a QR code on the `wifi` page
+    pub fn set_access_point(&self, network: WifiNetwork) {
+        *self.access_point.lock().unwrap() = Some(network);
+    }
+
+    /// Has uploaded pictures decoded for a display of `size`, in the colors of `mode`
+    ///
+    /// Without it, uploading pictures fails, as there is no display to show them on.
+    pub fn set_picture_format<C>(&self, size: Size, mode: ColorMode<C>)
+    where
+        C: PackedColor + Send + Sync + 'static,
+    {
+        *self.picture_decoder.lock().unwrap() = Some(Arc::new(move |data: &[u8]| {
+            Picture::decode(data, size, mode)
+        }));
+    }
+
+    /// Replaces the picture of the `picture` page, and switches to that page
+    pub fn set_picture(&self, picture: Picture) {
+        let size = picture.size();
+
+        *self.picture.lock().unwrap() = Some(Arc::new(picture));
+
+        info!(
+            "Picture uploaded, scaled to {}x{} pixels",
+            size.width, size.height
+        );
+
+        self.set_page(Page::Picture);
+    }
+
+    /// Queues the message, to be shown instead of the page once the earlier ones expired
+    pub fn show_message(&self, message: Message) -> Result<()> {
+        let mut messages = self.messages.lock().unwrap();
+
+        if messages.queue.len() >= MAX_QUEUED_MESSAGES {
+            anyhow::bail!("Too many display messages queued");
+        }
+
+        info!(
+            "Display message queued for {:?}: {}",
+            message.duration, message.text
+        );
+
+        messages.queue.push_back(Arc::new(message));
+
+        drop(messages);
+
+        self.wake();
+
+        Ok(())
+    }
+
+    /// The message to show, if any
+    ///
+    /// A message expires only after its duration since it was first rendered, so even with long
+    /// render intervals every message is shown.
+    fn message(&self) -> Option<Arc<Message>> {
+        let mut messages = self.messages.lock().unwrap();
+
+        let now = Instant::now();
+
+        if matches!(&messages.current, Some((_, expires)) if *expires <= now) {
+            messages.current = None;
+        }
+
+        if messages.current.is_none() {
+            messages.current = messages.queue.pop_front().map(|message| {
+                let expires = now + message.duration;
+
+                (message, expires)
+            });
+        }
+
+        messages
+            .current
+            .as_ref()
+            .map(|(message, _)| message.clone())
+    }
+
+    /// Creates the mirror of the display
+    ///
+    /// The renderer is expected to draw through `Mirrored`, with the returned mirror.
+    pub fn mirror<C>(&self, size: Size, background: C) -> Arc<Mutex<Mirror<C>>>
+    where
+        C: BmpColor + PackedColor + Send + 'static,
+    {
+        let mirror = Arc::new(Mutex::new(Mirror::new(size, background)));
+
+        self.set_mirror(mirror.clone());
+
+        mirror
+    }
+
+    /// Like `mirror()`, but `None` if the heap has no room for the mirror
+    pub fn try_mirror<C>(&self, size: Size, background: C) -> Option<Arc<Mutex<Mirror<C>>>>
+    where
+        C: BmpColor + PackedColor + Send + 'static,
+    {
+        if !heap_room(Mirror::<C>::bytes(size)) {
+            return None;
+        }
+
+        let mirror = Arc::new(Mutex::new(Mirror::try_new(size, background).ok()?));
+
+        self.set_mirror(mirror.clone());
+
+        Some(mirror)
+    }
+
+    /// Hands the display over to a thread of its own, which flushes the returned back buffer to it
+    ///
+    /// The back buffer is also the mirror of the display; `area` is the part of the display's
+    /// frame which the panel shows.
+    ///
+    /// The back buffer can be disabled with `RUST_ESP32_STD_DEMO_DISPLAY_BACK_BUFFER=false` at build
+    /// time, and is not used either if the heap has no room for it. The renderer then draws to the
+    /// display itself - through a mirror of the same size, if there is room for that.
+    pub fn back_buffer<D>(
+        &self,
+        mut display: D,
+        area: Rectangle,
+        background: D::Color,
+    ) -> Result<BackBuffer<D>>
+    where
+        D: DrawTarget + Send + 'static,
+        D::Color: BmpColor + PackedColor + Send + 'static,
+        D::Error: Debug,
+    {
+        let enabled = option_env!("RUST_ESP32_STD_DEMO_DISPLAY_BACK_BUFFER")
+            .map(str::parse)
+            .transpose()?
+            .unwrap_or(true);
+
+        let mirror = self.try_mirror(area.size, background);
+
+        let buffer = match mirror {
+            Some(buffer) if enabled => buffer,
+            mirror => {
+                if mirror.is_none() {
+                    warn!(
+                        "No room for the {}KB mirror of the display, drawing to it directly",
+                        Mirror::<D::Color>::bytes(area.size) / 1024
+                    );
+                }
+
+                return Ok(BackBuffer {
+                    buffering: Buffering::Direct {
+                        display,
+                        area,
+                        mirror,
+                    },
+                });
+            }
+        };
+
+        let (flush, pending) = mpsc::sync_channel(1);
+
+        let flushed = buffer.clone();
+
+        thread::Builder::new()
+            .name("display_flush".into())
+            .stack_size(4096)
+            .spawn(move || {
+                while pending.recv().is_ok() {
+                    let result =
+                        mirror::flush(&flushed, &mut display.cropped(&area), FLUSH_STRIP_PIXELS);
+
+                    if let Err(err) = result {
+                        warn!("Flushing the display failed: {:?}", err);
+                    }
+                }
+            })?;
+
+        Ok(BackBuffer {
+            buffering: Buffering::Buffered { buffer, flush },
+        })
+    }
+
+    /// For displays which keep a copy of their content anyway, instead of `mirror()`
+    pub fn set_mirror(&self, mirror: Arc<dyn DisplayMirror>) {
+        *self.mirror.lock().unwrap() = Some(mirror);
+    }
+
+    pub fn display_mirror(&self) -> Option<Arc<dyn DisplayMirror>> {
+        self.mirror.lock().unwrap().clone()
+    }
+
+    pub fn frame(&self) -> Frame {
+        let page = self.page();
+
+        Frame {
+            page,
+            snapshot: self.status.snapshot(),
+            log: if page == Page::Console {
+                log_console::records()
+            } else {
+                Vec::new()
+            },
+            access_point: self.access_point.lock().unwrap().clone(),
+            picture: self.picture.lock().unwrap().clone(),
+            adc_history: if page == Page::Chart {
+                self.status.adc_history()
+            } else {
+                Default::default()
+            },
+            message: self.message(),
+            power: self.tick_power(),
+        }
+    }
+
+    /// Spawns the renderer thread, which calls `render` with a fresh frame every `interval`
+    ///
+    /// The first render happens after `interval`, so that whatever the display shows at boot time
+    /// remains visible for a while. `render` is also called while the display is blanked, so that
+    /// it can put the panel to sleep, but it does not need to draw anything then.
+    pub fn spawn<R>(self: &Arc<Self>, interval: Duration, render: R) -> Result<()>
+    where
+        R: FnMut(&Frame) -> Result<()> + Send + 'static,
+    {
+        self.spawn_with(interval, 8192, move || Ok(render))
+    }
+
+    /// Like `spawn`, but with the renderer created by `init` on the renderer thread itself
+    ///
+    /// For renderers which cannot be moved across threads - like the Slint UI - or which need a
+    /// larger stack than the default one.
+    pub fn spawn_with<I, R>(
+        self: &Arc<Self>,
+        interval: Duration,
+        stack_size: usize,
+        init: I,
+    ) -> Result<()>
+    where
+        I: FnOnce() -> Result<R> + Send + 'static,
+        R: FnMut(&Frame) -> Result<()>,
+    {
+        let service = self.clone();
+
+        thread::Builder::new()
+            .name("display".into())
+            .stack_size(stack_size)
+            .spawn(move || {
+                let mut render = match init() {
+                    Ok(render) => render,
+                    Err(err) => {
+                        warn!("Creating the display renderer failed: {}", err);
+                        return;
+                    }
+                };
+
+                loop {
+                    thread::sleep(interval);
+
+                    if let Err(err) = render(&service.frame()) {
+                        warn!("Rendering the display failed: {}", err);
+                    }
+                }
+            })?;
+
+        info!("Display renderer started");
+
+        Ok(())
+    }
+}
+
+pub fn httpd_endpoints(
+    server: &mut EspHttpServer<'static>,
+    display: Arc<DisplayService>,
+) -> Result<()> {
+    let get = display.clone();
+    let set = display.clone();
+    let upload = display.clone();
+    let screenshot = display;
+
+    server
+        .fn_handler("/display", Method::Get, move |req| {
+            let json = serde_json::json!({
+                "page": get.page().name(),
+                "power": get.power().to_string(),
+                "pages": Page::ALL.iter().map(Page::name).collect::<Vec<_>>(),
+            })
+            .to_string();
+
+            req.into_response(200, Some("OK"), &[("Content-Type", "application/json")])?
+                .write_all(json.as_bytes())?;
+
+            Result::<_, anyhow::Error>::Ok(())
+        })?
+        .fn_handler("/display/page", Method::Post, move |mut req| {
+            let Some(form) = crate::read_form(&mut req)? else {
+                return Ok(());
+            };
+
+            let page = match crate::form_param(&form, "page").and_then(|page| Ok(page.parse()?)) {
+                Ok(page) => page,
+                Err(err) => return crate::bad_request(req, err),
+            };
+
+            set.set_page(page);
+
+            req.into_ok_response()?;
+
+            Result::<_, anyhow::Error>::Ok(())
+        })?
+        .fn_handler("/display/picture", Method::Post, move |mut req| {
+            let Some(decode) = upload.picture_decoder.lock().unwrap().clone() else {
+                req.into_response(503, Some("Service Unavailable"), &[])?
+                    .write_all(b"No display to show the picture on")?;
+
+                return Ok(());
+            };
+
+            let len = req
+                .header("Content-Length")
+                .and_then(|len| len.parse().ok())
+                .unwrap_or(0);
+
+            // Allocated up front, so that an upload which does not fit is refused before reading it
+            let mut body = Vec::new();
+
+            if len > MAX_UPLOAD_SIZE || !heap_room(len) || body.try_reserve_exact(len).is_err() {
+                req.into_response(413, Some("Payload Too Large"), &[])?
+                    .write_all(
+                        format!(
+                            "Uploads are limited to {} bytes and to the free memory",
+                            MAX_UPLOAD_SIZE
+                        )
+                        .as_bytes(),
+                    )?;
+
+                return Ok(());
+            }
+
+            if let Err(err) = read_body(&mut req, &mut body, len) {
+                return crate::bad_request(req, err);
+            }
+
+            // Decoded on a thread of its own, as decoding a PNG takes more stack than the HTTP
+            // server's; the upload is freed there as well, before the display renders the picture
+            let decoded = thread::Builder::new()
+                .name("picture_decode".into())
+                .stack_size(DECODE_STACK_SIZE)
+                .spawn(move || decode(&body))?
+                .join()
+                .map_err(|_| anyhow::anyhow!("Decoding the picture panicked"))?;
+
+            let picture = match decoded {
+                Ok(picture) => picture,
+                Err(err) => return crate::bad_request(req, err),
+            };
+
+            upload.set_picture(picture);
+
+            req.into_ok_response()?;
+
+            Result::<_, anyhow::Error>::Ok(())
+        })?
+        .fn_handler("/display/screenshot", Method::Get, move |req| {
+            let Some(mirror) = screenshot.display_mirror() else {
+                req.into_response(503, Some("Service Unavailable"), &[])?
+                    .write_all(b"No room for a mirror of the display")?;
+
+                return Ok(());
+            };
+
+            let mut resp = req.into_response(200, Some("OK"), &[("Content-Type", "image/bmp")])?;
+
+            mirror.write_bmp(
+                &Rectangle::new(Point::zero(), mirror.size()),
+                &mut |bytes| Ok(resp.write_all(bytes)?),
+            )?;
+
+            Result::<_, anyhow::Error>::Ok(())
+        })?;
+
+    Ok(())
+}
+
+/// Whether the heap has room for a block of `bytes`, leaving enough of it for everything else
+fn heap_room(bytes: usize) -> bool {
+    let (free, largest) = unsafe {
+        (
+            heap_caps_get_free_size(MALLOC_CAP_8BIT),
+            heap_caps_get_largest_free_block(MALLOC_CAP_8BIT),
+        )
+    };
+
+    bytes <= largest && bytes + MIN_FREE_HEAP <= free
+}
+
+/// Reads the `len` bytes of the request body into `body`
+fn read_body(
+    req: &mut Request<&mut EspHttpConnection>,
+    body: &mut Vec<u8>,
+    len: usize,
+) -> Result<()> {
+    let mut buf = [0_u8; 1024];
+
+    while body.len() < len {
+        let read = req.read(&mut buf[..(len - body.len()).min(1024)])?;
+        if read == 0 {
+            anyhow::bail!("The upload ended after {} of {} bytes", body.len(), len);
+        }
+
+        body.extend_from_slice(&buf[..read]);
+    }
+
+    Ok(())
+}
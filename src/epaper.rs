@@ -0,0 +1,250 @@
+//! The Waveshare 4.2" e-paper display, refreshed partially where possible and asleep in between
+//!
+//! Small changes - like the ticking values of the dashboard - are refreshed partially with the
+//! quick LUT, which avoids the flashing of a full refresh. Every `full_refresh_every` updates, a
+//! full refresh clears the ghosting accumulated by the partial ones.
+
+use core::time::Duration;
+
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+
+use log::*;
+
+use esp_idf_svc::hal::delay;
+use esp_idf_svc::hal::gpio::{AnyInputPin, AnyOutputPin, Input, Output, PinDriver};
+use esp_idf_svc::hal::spi::{SpiDeviceDriver, SpiDriver};
+
+use epd_waveshare::{epd4in2::*, graphics::VarDisplay, prelude::*};
+
+use embedded_graphics::pixelcolor::BinaryColor;
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::Rectangle;
+
+use graphics::bmp::{self, BmpColor};
+use graphics::epaper::{self, Refresh, RefreshPolicy};
+use graphics::mirror;
+
+use crate::display::DisplayMirror;
+
+type Spi = SpiDeviceDriver<'static, SpiDriver<'static>>;
+type OutputPinDriver = PinDriver<'static, AnyOutputPin, Output>;
+
+type Epd = Epd4in2<
+    Spi,
+    OutputPinDriver,
+    PinDriver<'static, AnyInputPin, Input>,
+    OutputPinDriver,
+    OutputPinDriver,
+    delay::Ets,
+>;
+
+#[derive(Clone, Debug)]
+pub struct EpaperConfig {
+    /// How often the content is redrawn; the panel is only refreshed if the content changed
+    pub interval: Duration,
+    pub full_refresh_every: u32,
+    /// Whether to put the panel into deep sleep between the updates
+    pub sleep: bool,
+}
+
+impl Default for EpaperConfig {
+    /// Overridable at build time with the `RUST_ESP32_STD_DEMO_EPD_INTERVAL_SECS` and
+    /// `RUST_ESP32_STD_DEMO_EPD_FULL_REFRESH_EVERY` environment variables
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(
+                option_env!("RUST_ESP32_STD_DEMO_EPD_INTERVAL_SECS")
+                    .and_then(|secs| secs.parse().ok())
+                    .unwrap_or(60),
+            ),
+            full_refresh_every: option_env!("RUST_ESP32_STD_DEMO_EPD_FULL_REFRESH_EVERY")
+                .and_then(|updates| updates.parse().ok())
+                .unwrap_or(10),
+            sleep: true,
+        }
+    }
+}
+
+pub struct Epaper {
+    epd: Epd,
+    spi: Spi,
+    buffer: Vec<u8>,
+    mirror: Arc<EpaperMirror>,
+    policy: RefreshPolicy,
+    sleep: bool,
+    asleep: bool,
+}
+
+impl Epaper {
+    pub fn new(
+        mut spi: Spi,
+        cs: AnyOutputPin,
+        busy_in: AnyInputPin,
+        dc: AnyOutputPin,
+        rst: AnyOutputPin,
+        config: &EpaperConfig,
+    ) -> Result<Self> {
+        let epd = Epd4in2::new(
+            &mut spi,
+            PinDriver::output(cs)?,
+            PinDriver::input(busy_in)?,
+            PinDriver::output(dc)?,
+            PinDriver::output(rst)?,
+            &mut delay::Ets,
+        )?;
+
+        let buffer =
+            vec![DEFAULT_BACKGROUND_COLOR.get_byte_value(); WIDTH as usize / 8 * HEIGHT as usize];
+
+        Ok(Self {
+            epd,
+            spi,
+            mirror: Arc::new(EpaperMirror {
+                shown: Mutex::new((buffer.clone(), None)),
+            }),
+            buffer,
+            policy: RefreshPolicy::new(Size::new(WIDTH, HEIGHT), config.full_refresh_every),
+            sleep: config.sleep,
+            asleep: false,
+        })
+    }
+
+    /// The frame to draw into; only shown on the panel by `update()`
+    pub fn display(&mut self) -> VarDisplay<'_> {
+        VarDisplay::new(WIDTH, HEIGHT, &mut self.buffer)
+    }
+
+    /// The frame on the panel, which may lag behind the one drawn into
+    pub fn mirror(&self) -> Arc<EpaperMirror> {
+        self.mirror.clone()
+    }
+
+    /// Refreshes the panel with the frame, if it changed since the previous update
+    pub fn update(&mut self) -> Result<()> {
+        let refresh = self.policy.next(&self.buffer);
+
+        if refresh == Refresh::None {
+            return Ok(());
+        }
+
+        let woken = self.asleep;
+
+        if self.asleep {
+            self.epd.wake_up(&mut self.spi, &mut delay::Ets)?;
+            self.asleep = false;
+        }
+
+        let size = Size::new(WIDTH, HEIGHT);
+
+        let dirty = match &refresh {
+            Refresh::Partial(window) => *window,
+            _ => Rectangle::new(Point::zero(), size),
+        };
+
+        match refresh {
+            Refresh::Full => {
+                debug!("Full e-paper refresh");
+
+                self.epd.set_lut(&mut self.spi, Some(RefreshLut::Full))?;
+                self.epd
+                    .update_frame(&mut self.spi, &self.buffer, &mut delay::Ets)?;
+            }
+            Refresh::Partial(window) => {
+                debug!("Partial e-paper refresh of {:?}", window);
+
+                self.epd.set_lut(&mut self.spi, Some(RefreshLut::Quick))?;
+
+                if woken {
+                    // The controller's memory does not survive deep sleep, so rewrite both of its
+                    // frames - the quick LUT only drives the pixels which differ between them
+                    let previous = self.mirror.frame();
+
+                    self.epd
+                        .update_old_frame(&mut self.spi, &previous, &mut delay::Ets)?;
+                    self.epd
+                        .update_new_frame(&mut self.spi, &self.buffer, &mut delay::Ets)?;
+                } else {
+                    self.epd.update_partial_frame(
+                        &mut self.spi,
+                        &epaper::window(&self.buffer, size, &window),
+                        window.top_left.x as u32,
+                        window.top_left.y as u32,
+                        window.size.width,
+                        window.size.height,
+                    )?;
+                }
+            }
+            Refresh::None => unreachable!(),
+        }
+
+        self.epd.display_frame(&mut self.spi, &mut delay::Ets)?;
+
+        self.mirror.update(&self.buffer, dirty);
+
+        if self.sleep {
+            self.epd.sleep(&mut self.spi, &mut delay::Ets)?;
+            self.asleep = true;
+        }
+
+        Ok(())
+    }
+}
+
+pub struct EpaperMirror {
+    // The frame, and the area changed since the last `take_dirty()`
+    shown: Mutex<(Vec<u8>, Option<Rectangle>)>,
+}
+
+impl EpaperMirror {
+    /// A copy of the frame on the panel
+    fn frame(&self) -> Vec<u8> {
+        self.shown.lock().unwrap().0.clone()
+    }
+
+    fn update(&self, frame: &[u8], changed: Rectangle) {
+        let mut shown = self.shown.lock().unwrap();
+
+        shown.0.copy_from_slice(frame);
+
+        shown.1 = Some(match shown.1 {
+            Some(dirty) => mirror::union(&dirty, &changed),
+            None => changed,
+        });
+    }
+}
+
+impl DisplayMirror for EpaperMirror {
+    fn size(&self) -> Size {
+        Size::new(WIDTH, HEIGHT)
+    }
+
+    fn take_dirty(&self) -> Option<Rectangle> {
+        self.shown.lock().unwrap().1.take()
+    }
+
+    fn write_bmp(&self, area: &Rectangle, out: &mut dyn FnMut(&[u8]) -> Result<()>) -> Result<()> {
+        // Only locked while copying a row, so that the updates do not wait for a slow client
+        bmp::encode_rows::<BinaryColor, _>(
+            area.size,
+            |y, row| {
+                let shown = self.shown.lock().unwrap();
+                let y = (area.top_left.y + y) as usize;
+
+                BinaryColor::encode_row(
+                    (0..area.size.width as i32).map(|x| {
+                        let x = (area.top_left.x + x) as usize;
+                        let index = y * WIDTH as usize / 8 + x / 8;
+
+                        // A set bit is a white pixel, which is `BinaryColor::On` for the BMP, even
+                        // though drawing with `BinaryColor::On` clears the bit
+                        (shown.0[index] & (0x80 >> (x % 8)) != 0).into()
+                    }),
+                    row,
+                )
+            },
+            out,
+        )
+    }
+}
@@ -0,0 +1,445 @@
+//! Remote control of the board GPIOs over HTTP
+//!
+//! Only the pins in the board's allowlist can be configured, read or written. The allowlist is
+//! all general-purpose pins of the chip, minus the pins claimed in `main()` by the enabled
+//! display, Ethernet and ADC features. The input-only pins of the chip cannot be configured as
+//! outputs.
+
+use core::str::FromStr;
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, bail, Result};
+
+use log::*;
+
+use serde::Serialize;
+
+use esp_idf_svc::hal::gpio::{self, AnyIOPin, Input, Output, PinDriver, Pull};
+use esp_idf_svc::http::server::{EspHttpServer, Method};
+use esp_idf_svc::io::Write;
+
+// General purpose pins, i.e. all pins minus those connected to the flash/PSRAM chips, UART0 and USB,
+// which can be both inputs and outputs
+#[cfg(esp32)]
+const IO_PINS: &[i32] = &[
+    0, 2, 4, 5, 12, 13, 14, 15, 16, 17, 18, 19, 21, 22, 23, 25, 26, 27, 32, 33,
+];
+#[cfg(esp32s2)]
+const IO_PINS: &[i32] = &[
+    0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 21, 33, 34, 35, 36, 37, 38,
+    39, 40, 41, 42, 45,
+];
+#[cfg(esp32s3)]
+const IO_PINS: &[i32] = &[
+    0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 21, 38, 39, 40, 41, 42, 45,
+    46, 47, 48,
+];
+#[cfg(esp32c3)]
+const IO_PINS: &[i32] = &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+#[cfg(not(any(esp32, esp32s2, esp32s3, esp32c3)))]
+const IO_PINS: &[i32] = &[];
+
+// General purpose pins which have no output driver
+#[cfg(esp32)]
+const INPUT_ONLY_PINS: &[i32] = &[34, 35, 36, 39];
+#[cfg(esp32s2)]
+const INPUT_ONLY_PINS: &[i32] = &[46];
+#[cfg(not(any(esp32, esp32s2)))]
+const INPUT_ONLY_PINS: &[i32] = &[];
+
+// Pins claimed in `main()`, per feature
+const RESERVED_PINS: &[(&str, &[i32])] = &[
+    #[cfg(esp32)]
+    ("adc", &[34]),
+    #[cfg(not(esp32))]
+    ("adc", &[2]),
+    #[cfg(esp32s2)]
+    ("ulp", &[4]),
+    #[cfg(feature = "ttgo")]
+    ("ttgo", &[4, 5, 16, 18, 19, 23]),
+    #[cfg(feature = "waveshare_epd")]
+    ("waveshare_epd", &[13, 14, 15, 25, 26, 27]),
+    #[cfg(feature = "kaluga")]
+    ("kaluga", &[6, 9, 11, 13, 15, 16]),
+    #[cfg(feature = "heltec")]
+    ("heltec", &[4, 15, 16]),
+    #[cfg(feature = "ssd1306g_spi")]
+    ("ssd1306g_spi", &[4, 5, 16, 18, 23]),
+    #[cfg(feature = "ssd1306g")]
+    ("ssd1306g", &[14, 21, 22]),
+    #[cfg(feature = "esp32s3_usb_otg")]
+    ("esp32s3_usb_otg", &[4, 5, 6, 7, 8, 9]),
+    #[cfg(feature = "ip101")]
+    ("ip101", &[0, 5, 18, 19, 21, 22, 23, 25, 26, 27]),
+    #[cfg(feature = "w5500")]
+    ("w5500", &[12, 13, 14, 25, 26, 27]),
+];
+
+pub fn is_allowed(pin: i32) -> bool {
+    (IO_PINS.contains(&pin) || INPUT_ONLY_PINS.contains(&pin))
+        && !RESERVED_PINS
+            .iter()
+            .any(|(_, reserved)| reserved.contains(&pin))
+}
+
+pub fn is_output_capable(pin: i32) -> bool {
+    IO_PINS.contains(&pin)
+}
+
+pub fn allowlist() -> impl Iterator<Item = i32> {
+    let mut pins = IO_PINS
+        .iter()
+        .chain(INPUT_ONLY_PINS)
+        .copied()
+        .filter(|pin| is_allowed(*pin))
+        .collect::<Vec<_>>();
+
+    pins.sort();
+
+    pins.into_iter()
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Mode {
+    Input,
+    Output,
+}
+
+impl FromStr for Mode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "input" => Ok(Self::Input),
+            "output" => Ok(Self::Output),
+            _ => bail!("Invalid GPIO mode: {}", s),
+        }
+    }
+}
+
+fn parse_pull(s: &str) -> Result<Pull> {
+    match s {
+        "none" => Ok(Pull::Floating),
+        "up" => Ok(Pull::Up),
+        "down" => Ok(Pull::Down),
+        "updown" => Ok(Pull::UpDown),
+        _ => bail!("Invalid GPIO pull: {}", s),
+    }
+}
+
+fn pull_name(pull: Pull) -> &'static str {
+    match pull {
+        Pull::Floating => "none",
+        Pull::Up => "up",
+        Pull::Down => "down",
+        Pull::UpDown => "updown",
+    }
+}
+
+enum RemotePin {
+    Input(PinDriver<'static, AnyIOPin, Input>, Pull),
+    Output(PinDriver<'static, AnyIOPin, Output>),
+    Claimed(&'static str),
+}
+
+#[derive(Serialize)]
+pub struct PinStatus {
+    pin: i32,
+    input_only: bool,
+    mode: Option<&'static str>,
+    pull: Option<&'static str>,
+    level: Option<u8>,
+}
+
+pub struct GpioService {
+    pins: Mutex<BTreeMap<i32, RemotePin>>,
+}
+
+impl GpioService {
+    pub fn new() -> Self {
+        info!(
+            "GPIOs available for remote control: {:?}",
+            allowlist().collect::<Vec<_>>()
+        );
+
+        Self {
+            pins: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn status(&self) -> Vec<PinStatus> {
+        let pins = self.pins.lock().unwrap();
+
+        allowlist()
+            .map(|pin| match pins.get(&pin) {
+                Some(RemotePin::Input(driver, pull)) => PinStatus {
+                    pin,
+                    input_only: !is_output_capable(pin),
+                    mode: Some("input"),
+                    pull: Some(pull_name(*pull)),
+                    level: Some(driver.is_high() as u8),
+                },
+                Some(RemotePin::Output(driver)) => PinStatus {
+                    pin,
+                    input_only: !is_output_capable(pin),
+                    mode: Some("output"),
+                    pull: None,
+                    level: Some(driver.is_set_high() as u8),
+                },
+                Some(RemotePin::Claimed(owner)) => PinStatus {
+                    pin,
+                    input_only: !is_output_capable(pin),
+                    mode: Some(*owner),
+                    pull: None,
+                    level: None,
+                },
+                None => PinStatus {
+                    pin,
+                    input_only: !is_output_capable(pin),
+                    mode: None,
+                    pull: None,
+                    level: None,
+                },
+            })
+            .collect()
+    }
+
+    pub fn configure(&self, pin: i32, mode: Mode, pull: Pull) -> Result<()> {
+        Self::check(pin)?;
+
+        let mut pins = self.pins.lock().unwrap();
+
+        if let Some(RemotePin::Claimed(owner)) = pins.get(&pin) {
+            bail!("GPIO{} is in use by {}", pin, owner);
+        }
+
+        // Dropping the previous driver (if any) resets the pin
+        pins.remove(&pin);
+
+        // Safe, because the pin is in the allowlist and is thus not used anywhere else
+        let gpio = unsafe { AnyIOPin::new(pin) };
+
+        let remote = match mode {
+            Mode::Input => {
+                let mut driver = PinDriver::input(gpio)?;
+                driver.set_pull(pull)?;
+
+                RemotePin::Input(driver, pull)
+            }
+            Mode::Output => {
+                if !is_output_capable(pin) {
+                    bail!("GPIO{} is an input-only pin", pin);
+                }
+
+                if pull != Pull::Floating {
+                    bail!("Pull-up/down is only supported in input mode");
+                }
+
+                RemotePin::Output(PinDriver::output(gpio)?)
+            }
+        };
+
+        pins.insert(pin, remote);
+
+        info!("Configured GPIO{} as {:?} (pull: {:?})", pin, mode, pull);
+
+        Ok(())
+    }
+
+    pub fn read(&self, pin: i32) -> Result<bool> {
+        Self::check(pin)?;
+
+        match self.pins.lock().unwrap().get(&pin) {
+            Some(RemotePin::Input(driver, _)) => Ok(driver.is_high()),
+            Some(RemotePin::Output(driver)) => Ok(driver.is_set_high()),
+            Some(RemotePin::Claimed(owner)) => bail!("GPIO{} is in use by {}", pin, owner),
+            None => bail!("GPIO{} is not configured", pin),
+        }
+    }
+
+    pub fn write(&self, pin: i32, high: bool) -> Result<()> {
+        Self::check(pin)?;
+
+        match self.pins.lock().unwrap().get_mut(&pin) {
+            Some(RemotePin::Output(driver)) => {
+                driver.set_level(gpio::Level::from(high))?;
+
+                Ok(())
+            }
+            Some(RemotePin::Input(..)) => bail!("GPIO{} is configured as an input", pin),
+            Some(RemotePin::Claimed(owner)) => bail!("GPIO{} is in use by {}", pin, owner),
+            None => bail!("GPIO{} is not configured", pin),
+        }
+    }
+
+    /// The pins configured as outputs, and whether they are driven high
+    pub fn outputs(&self) -> Vec<(i32, bool)> {
+        self.pins
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|(pin, remote)| match remote {
+                RemotePin::Output(driver) => Some((*pin, driver.is_set_high())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Hands over the pin to another service (e.g. PWM), so that it can no longer be
+    /// configured via the GPIO API until released
+    pub fn claim(&self, pin: i32, owner: &'static str) -> Result<AnyIOPin> {
+        Self::check(pin)?;
+
+        let mut pins = self.pins.lock().unwrap();
+
+        if let Some(RemotePin::Claimed(other)) = pins.get(&pin) {
+            bail!("GPIO{} is in use by {}", pin, other);
+        }
+
+        // Dropping the previous driver (if any) resets the pin
+        pins.insert(pin, RemotePin::Claimed(owner));
+
+        // Safe, because the pin is in the allowlist and the GPIO API will no longer touch it
+        Ok(unsafe { AnyIOPin::new(pin) })
+    }
+
+    /// Like `claim`, for services which drive the pin
+    pub fn claim_output(&self, pin: i32, owner: &'static str) -> Result<AnyIOPin> {
+        Self::check(pin)?;
+
+        if !is_output_capable(pin) {
+            bail!("GPIO{} is an input-only pin", pin);
+        }
+
+        self.claim(pin, owner)
+    }
+
+    pub fn release(&self, pin: i32) {
+        let mut pins = self.pins.lock().unwrap();
+
+        if let Some(RemotePin::Claimed(_)) = pins.get(&pin) {
+            pins.remove(&pin);
+        }
+    }
+
+    fn check(pin: i32) -> Result<()> {
+        if !is_allowed(pin) {
+            bail!("GPIO{} is not in the allowlist", pin);
+        }
+
+        Ok(())
+    }
+}
+
+pub fn httpd_endpoints(server: &mut EspHttpServer<'static>, gpios: Arc<GpioService>) -> Result<()> {
+    fn pin(params: &[(String, String)]) -> Result<i32> {
+        Ok(crate::form_param(params, "pin")?.parse()?)
+    }
+
+    fn not_allowed(pin: i32) -> anyhow::Error {
+        anyhow!("GPIO{} is not in the allowlist", pin)
+    }
+
+    let list = gpios.clone();
+    let config = gpios.clone();
+    let read = gpios.clone();
+    let write = gpios;
+
+    server
+        .fn_handler("/gpio", Method::Get, move |req| {
+            let json = serde_json::to_string(&list.status())?;
+
+            req.into_response(200, Some("OK"), &[("Content-Type", "application/json")])?
+                .write_all(json.as_bytes())?;
+
+            Result::<_, anyhow::Error>::Ok(())
+        })?
+        .fn_handler("/gpio/config", Method::Post, move |mut req| {
+            let Some(form) = crate::read_form(&mut req)? else {
+                return Ok(());
+            };
+
+            let params = pin(&form).and_then(|pin| {
+                let mode: Mode = crate::form_param(&form, "mode")?.parse()?;
+                let pull = crate::form_param(&form, "pull")
+                    .map(parse_pull)
+                    .unwrap_or(Ok(Pull::Floating))?;
+
+                Ok((pin, mode, pull))
+            });
+
+            let (pin, mode, pull) = match params {
+                Ok(params) => params,
+                Err(err) => return crate::bad_request(req, err),
+            };
+
+            if !is_allowed(pin) {
+                return crate::forbidden(req, not_allowed(pin));
+            }
+
+            config.configure(pin, mode, pull)?;
+
+            req.into_ok_response()?;
+
+            Result::<_, anyhow::Error>::Ok(())
+        })?
+        .fn_handler("/gpio/read", Method::Get, move |req| {
+            let query = crate::read_query(&req);
+
+            let pin = match pin(&query) {
+                Ok(pin) => pin,
+                Err(err) => return crate::bad_request(req, err),
+            };
+
+            if !is_allowed(pin) {
+                return crate::forbidden(req, not_allowed(pin));
+            }
+
+            let json = serde_json::to_string(&PinStatus {
+                pin,
+                input_only: !is_output_capable(pin),
+                mode: None,
+                pull: None,
+                level: Some(read.read(pin)? as u8),
+            })?;
+
+            req.into_response(200, Some("OK"), &[("Content-Type", "application/json")])?
+                .write_all(json.as_bytes())?;
+
+            Result::<_, anyhow::Error>::Ok(())
+        })?
+        .fn_handler("/gpio/write", Method::Post, move |mut req| {
+            let Some(form) = crate::read_form(&mut req)? else {
+                return Ok(());
+            };
+
+            let params = pin(&form).and_then(|pin| {
+                let high = match crate::form_param(&form, "level")? {
+                    "0" | "low" => false,
+                    "1" | "high" => true,
+                    level => bail!("Invalid GPIO level: {}", level),
+                };
+
+                Ok((pin, high))
+            });
+
+            let (pin, high) = match params {
+                Ok(params) => params,
+                Err(err) => return crate::bad_request(req, err),
+            };
+
+            if !is_allowed(pin) {
+                return crate::forbidden(req, not_allowed(pin));
+            }
+
+            write.write(pin, high)?;
+
+            req.into_ok_response()?;
+
+            Result::<_, anyhow::Error>::Ok(())
+        })?;
+
+    Ok(())
+}
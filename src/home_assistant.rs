@@ -0,0 +1,286 @@
+//! The board as a Home Assistant device, via MQTT discovery
+//!
+//! The retained discovery configs on `homeassistant/<component>/<device-id>/<entity>/config`
+//! describe sensors for the ADC, the temperature, the Wifi RSSI and the uptime, a switch for each
+//! GPIO output and a number for each PWM output. The values of all of them are published together
+//! as JSON on `<prefix>/<device-id>/state` every 30 seconds - and right after each MQTT command or
+//! PWM duty change, see `StateTrigger` - and the entities are available while the status topic of
+//! the board is `online`.
+//!
+//! The GPIO and PWM outputs come and go, so the configs are republished whenever the entities
+//! change, and the configs of the removed entities are cleared, which removes them from Home
+//! Assistant. The switches are controlled with the `gpio` command, and the numbers with the PWM
+//! topics of the device, `<prefix>/<device-id>/pwm/<pin>`.
+
+use core::time::Duration;
+
+use std::collections::BTreeMap;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use anyhow::Result;
+
+use log::*;
+
+use serde_json::{json, Value};
+
+use esp_idf_svc::mqtt::client::{EspMqttClient, QoS};
+
+use crate::dashboard::Status;
+use crate::gpio_api::GpioService;
+use crate::mqtt_config::MqttConfig;
+use crate::mqtt_presence::{OFFLINE, ONLINE};
+use crate::pwm::PwmService;
+
+const DISCOVERY_PREFIX: &str = "homeassistant";
+
+const STATE_INTERVAL: Duration = Duration::from_secs(30);
+
+const TEMPERATURE: bool = cfg!(all(
+    not(esp_idf_version_major = "4"),
+    esp_idf_soc_temp_sensor_supported
+));
+
+struct Topics {
+    device_id: String,
+    status: String,
+    state: String,
+    gpio_command: String,
+    pwm_command: String,
+}
+
+/// Has the state published right away, rather than with the next interval
+#[derive(Clone)]
+pub struct StateTrigger(SyncSender<()>);
+
+impl StateTrigger {
+    pub fn new() -> (Self, Receiver<()>) {
+        let (sender, receiver) = mpsc::sync_channel(1);
+
+        (Self(sender), receiver)
+    }
+
+    pub fn trigger(&self) {
+        // If the channel is full, the pending publish picks up the change as well
+        let _ = self.0.try_send(());
+    }
+}
+
+/// Spawns the thread which publishes the discovery configs and the state of the entities
+pub fn spawn(
+    client: Arc<Mutex<EspMqttClient<'static>>>,
+    config: &MqttConfig,
+    status: Arc<Status>,
+    gpios: Arc<GpioService>,
+    pwm: Arc<PwmService>,
+    triggered: Receiver<()>,
+) -> Result<()> {
+    let topics = Topics {
+        device_id: config.device_id(),
+        status: config.device_topic("status"),
+        state: config.device_topic("state"),
+        gpio_command: config.device_topic("cmd/gpio"),
+        pwm_command: config.device_topic("pwm"),
+    };
+
+    thread::Builder::new()
+        .name("home-assistant".into())
+        .stack_size(8192)
+        .spawn(move || {
+            // Discovery topic -> config, as last published
+            let mut published = BTreeMap::new();
+
+            loop {
+                let configs = configs(&topics, &gpios, &pwm);
+
+                if configs != published {
+                    match publish_configs(&mut client.lock().unwrap(), &published, &configs) {
+                        Ok(()) => {
+                            info!("Published {} Home Assistant entities", configs.len());
+
+                            published = configs;
+                        }
+                        // Retried with the next state
+                        Err(err) => warn!("Publishing the Home Assistant entities failed: {}", err),
+                    }
+                }
+
+                let state = state(&status, &gpios, &pwm).to_string();
+
+                if let Err(err) = client.lock().unwrap().publish(
+                    &topics.state,
+                    QoS::AtMostOnce,
+                    false,
+                    state.as_bytes(),
+                ) {
+                    warn!("Publishing the Home Assistant state failed: {}", err);
+                }
+
+                if let Err(RecvTimeoutError::Disconnected) = triggered.recv_timeout(STATE_INTERVAL)
+                {
+                    thread::sleep(STATE_INTERVAL);
+                }
+            }
+        })?;
+
+    Ok(())
+}
+
+fn publish_configs(
+    client: &mut EspMqttClient<'static>,
+    published: &BTreeMap<String, String>,
+    configs: &BTreeMap<String, String>,
+) -> Result<()> {
+    for (topic, config) in configs {
+        if published.get(topic) != Some(config) {
+            client.publish(topic, QoS::AtLeastOnce, true, config.as_bytes())?;
+        }
+    }
+
+    // An empty config removes the entity
+    for topic in published.keys() {
+        if !configs.contains_key(topic) {
+            client.publish(topic, QoS::AtLeastOnce, true, &[])?;
+        }
+    }
+
+    Ok(())
+}
+
+/// The discovery configs of the current entities, by discovery topic
+fn configs(topics: &Topics, gpios: &GpioService, pwm: &PwmService) -> BTreeMap<String, String> {
+    let mut entities = vec![
+        (
+            "sensor",
+            "adc".to_string(),
+            json!({
+                "name": "ADC",
+                "device_class": "voltage",
+                "unit_of_measurement": "mV",
+                "state_class": "measurement",
+                "value_template": "{{ value_json.adc_mv }}",
+            }),
+        ),
+        (
+            "sensor",
+            "rssi".into(),
+            json!({
+                "name": "Wifi RSSI",
+                "device_class": "signal_strength",
+                "unit_of_measurement": "dBm",
+                "state_class": "measurement",
+                "entity_category": "diagnostic",
+                "value_template": "{{ value_json.rssi }}",
+            }),
+        ),
+        (
+            "sensor",
+            "uptime".into(),
+            json!({
+                "name": "Uptime",
+                "device_class": "duration",
+                "unit_of_measurement": "s",
+                "entity_category": "diagnostic",
+                "value_template": "{{ value_json.uptime }}",
+            }),
+        ),
+    ];
+
+    if TEMPERATURE {
+        entities.push((
+            "sensor",
+            "temperature".into(),
+            json!({
+                "name": "Chip temperature",
+                "device_class": "temperature",
+                "unit_of_measurement": "°C",
+                "state_class": "measurement",
+                "value_template": "{{ value_json.temperature }}",
+            }),
+        ));
+    }
+
+    for (pin, _) in gpios.outputs() {
+        entities.push((
+            "switch",
+            format!("gpio{pin}"),
+            json!({
+                "name": format!("GPIO{pin}"),
+                "command_topic": topics.gpio_command,
+                "payload_on": json!({ "pin": pin, "level": 1 }).to_string(),
+                "payload_off": json!({ "pin": pin, "level": 0 }).to_string(),
+                "state_on": "1",
+                "state_off": "0",
+                "value_template": format!("{{{{ value_json.gpio['{pin}'] }}}}"),
+            }),
+        ));
+    }
+
+    for output in pwm.status() {
+        let pin = output.pin;
+
+        entities.push((
+            "number",
+            format!("pwm{pin}"),
+            json!({
+                "name": format!("PWM {} (GPIO{pin})", output.name),
+                "command_topic": format!("{}/{pin}", topics.pwm_command),
+                "min": 0,
+                "max": 100,
+                "step": 1,
+                "unit_of_measurement": "%",
+                "value_template": format!("{{{{ value_json.pwm['{pin}'] }}}}"),
+            }),
+        ));
+    }
+
+    let device = json!({
+        "identifiers": [topics.device_id],
+        "name": topics.device_id,
+        "model": "rust-esp32-std-demo",
+        "manufacturer": "Espressif",
+        "sw_version": env!("CARGO_PKG_VERSION"),
+    });
+
+    entities
+        .into_iter()
+        .map(|(component, object_id, mut config)| {
+            config["unique_id"] = format!("{}_{}", topics.device_id, object_id).into();
+            config["state_topic"] = topics.state.as_str().into();
+            config["availability_topic"] = topics.status.as_str().into();
+            config["payload_available"] = ONLINE.into();
+            config["payload_not_available"] = OFFLINE.into();
+            config["device"] = device.clone();
+
+            (
+                format!(
+                    "{}/{}/{}/{}/config",
+                    DISCOVERY_PREFIX, component, topics.device_id, object_id
+                ),
+                config.to_string(),
+            )
+        })
+        .collect()
+}
+
+fn state(status: &Status, gpios: &GpioService, pwm: &PwmService) -> Value {
+    let snapshot = status.snapshot();
+
+    json!({
+        "adc_mv": snapshot.adc_mv,
+        "rssi": snapshot.rssi,
+        "uptime": snapshot.uptime.as_secs(),
+        "temperature": status.temperature(),
+        "gpio": gpios
+            .outputs()
+            .into_iter()
+            .map(|(pin, high)| (pin.to_string(), Value::from(high as u8)))
+            .collect::<serde_json::Map<_, _>>(),
+        "pwm": pwm
+            .status()
+            .into_iter()
+            .map(|output| (output.pin.to_string(), Value::from(output.duty)))
+            .collect::<serde_json::Map<_, _>>(),
+    })
+}
@@ -0,0 +1,262 @@
+//! I2C bus diagnostics
+//!
+//! Scans all 7-bit addresses of a bus and identifies the devices which respond, either by their address
+//! alone or - for devices which have one - by their chip ID / WHO_AM_I register. Useful for verifying
+//! the wiring of add-on sensors.
+//!
+//! The buses are only scanned at boot, before their drivers are handed over to the display, so
+//! devices attached later show up only after a restart.
+
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+
+use log::*;
+
+use serde::Serialize;
+
+use esp_idf_svc::hal::delay::TickType;
+use esp_idf_svc::hal::i2c::I2cDriver;
+use esp_idf_svc::http::server::{EspHttpServer, Method};
+use esp_idf_svc::io::Write;
+
+// 0x00 - 0x07 and 0x78 - 0x7f are reserved by the I2C spec
+const ADDRESSES: core::ops::RangeInclusive<u8> = 0x08..=0x77;
+
+const TIMEOUT: TickType = TickType::new_millis(10);
+
+struct KnownDevice {
+    name: &'static str,
+    addresses: &'static [u8],
+    // The register holding the chip ID, and the expected ID
+    id: Option<(u8, u8)>,
+}
+
+const KNOWN_DEVICES: &[KnownDevice] = &[
+    KnownDevice {
+        name: "SSD1306/SH1106 OLED display",
+        addresses: &[0x3c, 0x3d],
+        id: None,
+    },
+    KnownDevice {
+        name: "MPU6050 IMU",
+        addresses: &[0x68, 0x69],
+        id: Some((0x75, 0x68)),
+    },
+    KnownDevice {
+        name: "MPU6500 IMU",
+        addresses: &[0x68, 0x69],
+        id: Some((0x75, 0x70)),
+    },
+    KnownDevice {
+        name: "MPU9250 IMU",
+        addresses: &[0x68, 0x69],
+        id: Some((0x75, 0x71)),
+    },
+    KnownDevice {
+        name: "DS3231/DS1307 RTC",
+        addresses: &[0x68],
+        id: None,
+    },
+    KnownDevice {
+        name: "BMP280 pressure sensor",
+        addresses: &[0x76, 0x77],
+        id: Some((0xd0, 0x58)),
+    },
+    KnownDevice {
+        name: "BME280 environmental sensor",
+        addresses: &[0x76, 0x77],
+        id: Some((0xd0, 0x60)),
+    },
+    KnownDevice {
+        name: "BME680 environmental sensor",
+        addresses: &[0x76, 0x77],
+        id: Some((0xd0, 0x61)),
+    },
+    KnownDevice {
+        name: "ADXL345 accelerometer",
+        addresses: &[0x1d, 0x53],
+        id: Some((0x00, 0xe5)),
+    },
+    KnownDevice {
+        name: "LIS3DH accelerometer",
+        addresses: &[0x18, 0x19],
+        id: Some((0x0f, 0x33)),
+    },
+    KnownDevice {
+        name: "LSM6DS3 IMU",
+        addresses: &[0x6a, 0x6b],
+        id: Some((0x0f, 0x69)),
+    },
+    KnownDevice {
+        name: "VL53L0X time-of-flight sensor",
+        addresses: &[0x29],
+        id: Some((0xc0, 0xee)),
+    },
+    KnownDevice {
+        name: "APDS9960 gesture sensor",
+        addresses: &[0x39],
+        id: Some((0x92, 0xab)),
+    },
+    KnownDevice {
+        name: "HMC5883L magnetometer",
+        addresses: &[0x1e],
+        id: Some((0x0a, 0x48)),
+    },
+    KnownDevice {
+        name: "QMC5883L magnetometer",
+        addresses: &[0x0d],
+        id: None,
+    },
+    KnownDevice {
+        name: "BH1750 light sensor",
+        addresses: &[0x23, 0x5c],
+        id: None,
+    },
+    KnownDevice {
+        name: "AHT10/AHT20 humidity sensor",
+        addresses: &[0x38],
+        id: None,
+    },
+    KnownDevice {
+        name: "SHT3x humidity sensor",
+        addresses: &[0x44, 0x45],
+        id: None,
+    },
+    KnownDevice {
+        name: "INA219 current sensor / HDC1080 / Si7021 humidity sensor",
+        addresses: &[0x40],
+        id: None,
+    },
+    KnownDevice {
+        name: "ADS1115 ADC / TMP102 temperature sensor / PCF8591 ADC",
+        addresses: &[0x48, 0x49, 0x4a, 0x4b],
+        id: None,
+    },
+    KnownDevice {
+        name: "AT24Cxx EEPROM",
+        addresses: &[0x50, 0x51, 0x52, 0x54, 0x55, 0x56, 0x57],
+        id: None,
+    },
+    KnownDevice {
+        name: "PCF8574/MCP23017 GPIO expander",
+        addresses: &[0x20, 0x21, 0x22, 0x24, 0x25, 0x26, 0x27],
+        id: None,
+    },
+    KnownDevice {
+        name: "PCF8574A GPIO expander (LCD backpack)",
+        addresses: &[0x3f],
+        id: None,
+    },
+    KnownDevice {
+        name: "TCA9548A I2C multiplexer",
+        addresses: &[0x70, 0x71, 0x72, 0x73, 0x74, 0x75, 0x77],
+        id: None,
+    },
+];
+
+#[derive(Clone, Debug, Serialize)]
+pub struct I2cDevice {
+    pub address: u8,
+    pub name: Option<&'static str>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct I2cBus {
+    pub bus: &'static str,
+    pub devices: Vec<I2cDevice>,
+}
+
+/// The devices found on all scanned buses
+pub struct I2cInventory(Mutex<Vec<I2cBus>>);
+
+impl I2cInventory {
+    pub fn new() -> Self {
+        Self(Mutex::new(Vec::new()))
+    }
+
+    /// Scans the bus and records the devices found on it in the inventory
+    pub fn scan(&self, bus: &'static str, i2c: &mut I2cDriver) -> Result<()> {
+        info!("About to scan I2C bus {}", bus);
+
+        let mut devices = Vec::new();
+
+        for address in ADDRESSES {
+            if i2c.write(address, &[], TIMEOUT.ticks()).is_ok() {
+                let device = I2cDevice {
+                    address,
+                    name: identify(i2c, address),
+                };
+
+                info!(
+                    "Found I2C device at address 0x{:02x}: {}",
+                    device.address,
+                    device.name.unwrap_or("unknown")
+                );
+
+                devices.push(device);
+            }
+        }
+
+        info!("Found {} device(s) on I2C bus {}", devices.len(), bus);
+
+        let mut buses = self.0.lock().unwrap();
+
+        buses.retain(|other| other.bus != bus);
+        buses.push(I2cBus { bus, devices });
+
+        Ok(())
+    }
+
+    pub fn buses(&self) -> Vec<I2cBus> {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+fn identify(i2c: &mut I2cDriver, address: u8) -> Option<&'static str> {
+    let candidates = || {
+        KNOWN_DEVICES
+            .iter()
+            .filter(move |device| device.addresses.contains(&address))
+    };
+
+    // Devices with an ID register can be identified with certainty, so try these first
+    for device in candidates() {
+        if let Some((register, id)) = device.id {
+            let mut value = [0_u8; 1];
+
+            if i2c
+                .write_read(address, &[register], &mut value, TIMEOUT.ticks())
+                .is_ok()
+                && value[0] == id
+            {
+                return Some(device.name);
+            }
+        }
+    }
+
+    candidates()
+        .find(|device| device.id.is_none())
+        .map(|device| device.name)
+}
+
+pub fn httpd_endpoints(
+    server: &mut EspHttpServer<'static>,
+    inventory: Arc<I2cInventory>,
+) -> Result<()> {
+    server.fn_handler("/i2c", Method::Get, move |req| {
+        let json = serde_json::json!({
+            "note": "The buses are only scanned at boot, and only the I2C display bus of the \
+                Heltec and SSD1306 boards is scanned",
+            "buses": inventory.buses(),
+        })
+        .to_string();
+
+        req.into_response(200, Some("OK"), &[("Content-Type", "application/json")])?
+            .write_all(json.as_bytes())?;
+
+        Result::<_, anyhow::Error>::Ok(())
+    })?;
+
+    Ok(())
+}
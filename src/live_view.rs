@@ -0,0 +1,225 @@
+//! Streams what the display shows to browsers, which paint it onto a canvas
+//!
+//! `GET /display/live` serves the page, which connects to the `/display/live/ws` WebSocket. Each
+//! binary message is a changed area of the display: its `x` and `y` as little-endian `u16`s,
+//! followed by a BMP of the area, which browsers decode natively. Newly connected browsers first
+//! get the size of the display as a `{"width":..,"height":..}` text message, and then all of it.
+
+use core::time::Duration;
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use anyhow::Result;
+
+use log::*;
+
+use esp_idf_svc::http::server::ws::EspHttpWsDetachedSender;
+use esp_idf_svc::http::server::{EspHttpServer, Method};
+use esp_idf_svc::io::Write;
+use esp_idf_svc::ws::FrameType;
+
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::Rectangle;
+
+use crate::display::{DisplayMirror, DisplayService};
+
+const INTERVAL: Duration = Duration::from_millis(250);
+
+// Large changes are sent in strips of at most this many bytes, assuming 16 bpp
+const MAX_MESSAGE_SIZE: usize = 8 * 1024;
+
+const PAGE: &str = r#"<!DOCTYPE html>
+<html>
+    <head>
+        <title>rust-esp32-std-demo display</title>
+        <style>
+            body { background: #333; }
+            canvas { image-rendering: pixelated; width: 100%; max-width: 800px; border: 1px solid #888; }
+        </style>
+    </head>
+    <body>
+        <canvas id="display"></canvas>
+        <script>
+            const canvas = document.getElementById("display");
+            const ctx = canvas.getContext("2d");
+
+            function connect() {
+                const ws = new WebSocket(`ws://${location.host}/display/live/ws`);
+                ws.binaryType = "arraybuffer";
+
+                // Paint in the order of arrival, even though decoding is asynchronous
+                let painted = Promise.resolve();
+
+                ws.onmessage = (event) => {
+                    if (typeof event.data === "string") {
+                        const size = JSON.parse(event.data);
+
+                        painted = painted.then(() => {
+                            canvas.width = size.width;
+                            canvas.height = size.height;
+                        });
+
+                        return;
+                    }
+
+                    const view = new DataView(event.data);
+                    const x = view.getUint16(0, true);
+                    const y = view.getUint16(2, true);
+
+                    const bitmap = createImageBitmap(new Blob([event.data.slice(4)], { type: "image/bmp" }));
+
+                    painted = painted.then(() => bitmap).then((bitmap) => ctx.drawImage(bitmap, x, y));
+                };
+
+                ws.onclose = () => setTimeout(connect, 1000);
+            }
+
+            connect();
+        </script>
+    </body>
+</html>
+"#;
+
+struct LiveView {
+    display: Arc<DisplayService>,
+    clients: Mutex<Vec<EspHttpWsDetachedSender>>,
+    // Whether a newly connected client still needs the whole display
+    full: AtomicBool,
+}
+
+impl LiveView {
+    fn spawn(self: &Arc<Self>) -> Result<()> {
+        let live = self.clone();
+
+        thread::Builder::new()
+            .name("live_view".into())
+            .stack_size(6144)
+            .spawn(move || loop {
+                thread::sleep(INTERVAL);
+
+                if let Err(err) = live.publish() {
+                    warn!("Streaming the display failed: {}", err);
+                }
+            })?;
+
+        Ok(())
+    }
+
+    fn publish(&self) -> Result<()> {
+        let Some(mirror) = self.display.display_mirror() else {
+            return Ok(());
+        };
+
+        let mut clients = self.clients.lock().unwrap();
+
+        clients.retain(|client| !client.is_closed());
+
+        if clients.is_empty() {
+            return Ok(());
+        }
+
+        let dirty = mirror.take_dirty();
+
+        let area = if self.full.swap(false, Ordering::SeqCst) {
+            let size = mirror.size();
+
+            // Sent to all clients, which is harmless for those which already know the size
+            let json =
+                serde_json::json!({ "width": size.width, "height": size.height }).to_string();
+
+            send(&mut clients, FrameType::Text(false), json.as_bytes());
+
+            Rectangle::new(Point::zero(), size)
+        } else if let Some(dirty) = dirty {
+            dirty
+        } else {
+            return Ok(());
+        };
+
+        let rows = (MAX_MESSAGE_SIZE / (area.size.width as usize * 2)).max(1) as u32;
+
+        for top in (0..area.size.height).step_by(rows as usize) {
+            let strip = Rectangle::new(
+                area.top_left + Point::new(0, top as i32),
+                Size::new(area.size.width, rows.min(area.size.height - top)),
+            );
+
+            send(
+                &mut clients,
+                FrameType::Binary(false),
+                &message(mirror.as_ref(), &strip)?,
+            );
+        }
+
+        Ok(())
+    }
+}
+
+// Sends the frame to all clients, dropping those which disconnected
+fn send(clients: &mut Vec<EspHttpWsDetachedSender>, frame_type: FrameType, data: &[u8]) {
+    clients.retain_mut(|client| {
+        client
+            .send(frame_type, data)
+            .map_err(|err| info!("Live view client disconnected: {}", err))
+            .is_ok()
+    });
+}
+
+fn message(mirror: &dyn DisplayMirror, area: &Rectangle) -> Result<Vec<u8>> {
+    let mut message = Vec::new();
+
+    message.extend((area.top_left.x as u16).to_le_bytes());
+    message.extend((area.top_left.y as u16).to_le_bytes());
+
+    mirror.write_bmp(area, &mut |bytes| {
+        message.extend_from_slice(bytes);
+
+        Ok(())
+    })?;
+
+    Ok(message)
+}
+
+pub fn httpd_endpoints(
+    server: &mut EspHttpServer<'static>,
+    display: Arc<DisplayService>,
+) -> Result<()> {
+    let live = Arc::new(LiveView {
+        display,
+        clients: Mutex::new(Vec::new()),
+        full: AtomicBool::new(false),
+    });
+
+    live.spawn()?;
+
+    server
+        .fn_handler("/display/live", Method::Get, |req| {
+            req.into_response(200, Some("OK"), &[("Content-Type", "text/html")])?
+                .write_all(PAGE.as_bytes())?;
+
+            Result::<_, anyhow::Error>::Ok(())
+        })?
+        .ws_handler("/display/live/ws", move |ws| {
+            if ws.is_new() {
+                info!("Live view client connected");
+
+                live.clients
+                    .lock()
+                    .unwrap()
+                    .push(ws.create_detached_sender()?);
+
+                live.full.store(true, Ordering::SeqCst);
+            } else if !ws.is_closed() {
+                // The page does not send anything; just drain whatever arrives
+                let mut buf = [0_u8; 64];
+
+                ws.recv(&mut buf)?;
+            }
+
+            Result::<_, anyhow::Error>::Ok(())
+        })?;
+
+    Ok(())
+}
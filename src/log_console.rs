@@ -0,0 +1,109 @@
+//! Keeps the most recent `log` records in memory, for the console page of the display
+//!
+//! Installed in place of `EspLogger::initialize_default()`; all records are still forwarded to
+//! the ESP-IDF logging facilities (i.e. the serial console). The console keeps the same records as
+//! the serial console, i.e. those allowed by the runtime level of their target (see `set_level`).
+
+use std::collections::{BTreeMap, VecDeque};
+use std::ffi::CString;
+use std::sync::Mutex;
+
+use log::{Level, Log, Metadata, Record};
+
+use esp_idf_svc::log::EspLogger;
+use esp_idf_svc::sys::*;
+
+use graphics::console;
+
+// More than any of the supported displays can show at once
+const CAPACITY: usize = 32;
+
+static LOGGER: ConsoleLogger = ConsoleLogger {
+    esp: EspLogger,
+    records: Mutex::new(VecDeque::new()),
+    targets: Mutex::new(BTreeMap::new()),
+};
+
+struct ConsoleLogger {
+    esp: EspLogger,
+    records: Mutex<VecDeque<console::Record>>,
+    // `esp_log_level_get` caches the levels by the address of the target, so each target needs
+    // a C string which stays put
+    targets: Mutex<BTreeMap<String, CString>>,
+}
+
+impl ConsoleLogger {
+    /// Whether the runtime level of the record's target allows it, as checked by `EspLogger`
+    #[cfg(not(esp_idf_version = "4.3"))]
+    fn target_enabled(&self, record: &Record) -> bool {
+        let level = match record.level() {
+            Level::Error => esp_log_level_t_ESP_LOG_ERROR,
+            Level::Warn => esp_log_level_t_ESP_LOG_WARN,
+            Level::Info => esp_log_level_t_ESP_LOG_INFO,
+            Level::Debug => esp_log_level_t_ESP_LOG_DEBUG,
+            Level::Trace => esp_log_level_t_ESP_LOG_VERBOSE,
+        };
+
+        let mut targets = self.targets.lock().unwrap();
+
+        if !targets.contains_key(record.target()) {
+            match CString::new(record.target()) {
+                Ok(target) => targets.insert(record.target().into(), target),
+                Err(_) => return true,
+            };
+        }
+
+        level <= unsafe { esp_log_level_get(targets[record.target()].as_ptr()) }
+    }
+
+    // No `esp_log_level_get` on ESP-IDF V4.3
+    #[cfg(esp_idf_version = "4.3")]
+    fn target_enabled(&self, _record: &Record) -> bool {
+        true
+    }
+}
+
+impl Log for ConsoleLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.esp.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        self.esp.log(record);
+
+        if self.enabled(record.metadata()) && self.target_enabled(record) {
+            // Format outside of the lock, in case formatting the arguments logs as well
+            let record = console::Record {
+                level: record.level(),
+                message: record.args().to_string(),
+            };
+
+            let mut records = self.records.lock().unwrap();
+
+            if records.len() == CAPACITY {
+                records.pop_front();
+            }
+
+            records.push_back(record);
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+pub fn initialize() {
+    log::set_logger(&LOGGER)
+        .map(|()| LOGGER.esp.initialize())
+        .unwrap();
+}
+
+/// The most recent records, oldest first
+pub fn records() -> Vec<console::Record> {
+    LOGGER.records.lock().unwrap().iter().cloned().collect()
+}
+
+/// Changes the level of a target - i.e. a module or an ESP-IDF component - or of all of them with
+/// `*`, up to the maximum level the firmware was built with
+pub fn set_level(target: &str, level: log::LevelFilter) -> Result<(), EspError> {
+    LOGGER.esp.set_target_level(target, level)
+}
@@ -24,6 +24,22 @@ compile_error!(
     "The `esp32s3_usb_otg` feature can only be built for the `xtensa-esp32s3-espidf` target."
 );
 
+#[cfg(all(
+    feature = "spi_display",
+    any(
+        feature = "ttgo",
+        feature = "kaluga",
+        feature = "heltec",
+        feature = "ssd1306g_spi",
+        feature = "ssd1306g",
+        feature = "esp32s3_usb_otg",
+        feature = "waveshare_epd"
+    )
+))]
+compile_error!(
+    "The `spi_display` feature cannot be combined with the features of the other displays."
+);
+
 use core::cell::RefCell;
 use core::ffi::{self, CStr};
 use core::fmt::{self, Debug};
@@ -57,6 +73,7 @@ use esp_idf_svc::hal::spi;
 use esp_idf_svc::eventloop::*;
 use esp_idf_svc::ipv4;
 use esp_idf_svc::mqtt::client::*;
+use esp_idf_svc::nvs::*;
 use esp_idf_svc::ping;
 use esp_idf_svc::sntp;
 use esp_idf_svc::systime::EspSystemTime;
@@ -75,7 +92,32 @@ use mipidsi;
 use ssd1306;
 use ssd1306::mode::DisplayConfig;
 
-use epd_waveshare::{epd4in2::*, graphics::VarDisplay, prelude::*};
+use graphics::{led_draw, led_draw_custom};
+
+mod adc_cal;
+mod buttons;
+mod dashboard;
+mod display;
+#[cfg(feature = "waveshare_epd")]
+mod epaper;
+mod gpio_api;
+mod home_assistant;
+mod i2c_scan;
+#[cfg(esp_idf_httpd_ws_support)]
+mod live_view;
+mod log_console;
+mod mqtt_commands;
+mod mqtt_config;
+mod mqtt_presence;
+#[cfg(feature = "spi_display")]
+mod panel;
+mod pwm;
+#[cfg(feature = "spi_display")]
+mod sh1106;
+#[cfg(all(not(esp_idf_version_major = "4"), esp_idf_soc_temp_sensor_supported))]
+mod temp_sensor;
+#[cfg(any(esp32s2, esp32s3))]
+mod touch;
 
 #[allow(dead_code)]
 #[cfg(not(feature = "qemu"))]
@@ -84,6 +126,17 @@ const SSID: &str = env!("RUST_ESP32_STD_DEMO_WIFI_SSID");
 #[cfg(not(feature = "qemu"))]
 const PASS: &str = env!("RUST_ESP32_STD_DEMO_WIFI_PASS");
 
+#[cfg(not(feature = "qemu"))]
+const AP_SSID: &str = "aptest";
+
+// The narrower the range, the more accurate the on-die temperature sensor readings; overridden
+// with `RUST_ESP32_STD_DEMO_TEMP_SENSOR_RANGE=<min>,<max>`
+#[allow(dead_code)]
+const TEMP_SENSOR_RANGE: core::ops::RangeInclusive<i32> = -10..=80;
+
+// Enough for the MQTT settings with their certificates, URL-encoded
+const MAX_FORM_SIZE: usize = 16 * 1024;
+
 #[cfg(esp32s2)]
 include!(env!("EMBUILD_GENERATED_SYMBOLS_FILE"));
 
@@ -109,8 +162,8 @@ fn main() -> Result<()> {
     #[cfg(not(esp_idf_version = "4.3"))]
     test_fs()?;
 
-    // Bind the log crate to the ESP Logging facilities
-    esp_idf_svc::log::EspLogger::initialize_default();
+    // Bind the log crate to the ESP Logging facilities, also keeping the recent records for the display
+    log_console::initialize();
 
     // Get backtraces from anyhow; only works for Xtensa arch currently
     // TODO: No longer working with ESP-IDF 4.3.1+
@@ -167,8 +220,30 @@ fn main() -> Result<()> {
     #[allow(unused)]
     let sysloop = EspSystemEventLoop::take()?;
 
+    let nvs = EspDefaultNvsPartition::take()?;
+
+    let adc_cal = Arc::new(adc_cal::AdcCalibration::new(nvs.clone())?);
+
+    let mqtt_config = Arc::new(mqtt_config::MqttConfigStore::new(nvs.clone())?);
+
+    let gpios = Arc::new(gpio_api::GpioService::new());
+
+    let pwm = Arc::new(pwm::PwmService::new(peripherals.ledc, gpios.clone()));
+
+    let i2c_inventory = Arc::new(i2c_scan::I2cInventory::new());
+
+    let status = Arc::new(dashboard::Status::new());
+
+    let display_service = Arc::new(display::DisplayService::new(
+        status.clone(),
+        pwm.clone(),
+        &display::PowerConfig::default(),
+    ));
+
     #[cfg(feature = "ttgo")]
     ttgo_hello_world(
+        &pwm,
+        display_service.clone(),
         pins.gpio4,
         pins.gpio16,
         pins.gpio23,
@@ -180,6 +255,7 @@ fn main() -> Result<()> {
 
     #[cfg(feature = "waveshare_epd")]
     waveshare_epd_hello_world(
+        display_service.clone(),
         peripherals.spi2,
         pins.gpio13.into(),
         pins.gpio14.into(),
@@ -191,6 +267,8 @@ fn main() -> Result<()> {
 
     #[cfg(feature = "kaluga")]
     kaluga_hello_world(
+        &pwm,
+        display_service.clone(),
         pins.gpio6,
         pins.gpio13,
         pins.gpio16,
@@ -201,10 +279,18 @@ fn main() -> Result<()> {
     )?;
 
     #[cfg(feature = "heltec")]
-    heltec_hello_world(pins.gpio16, peripherals.i2c0, pins.gpio4, pins.gpio15)?;
+    heltec_hello_world(
+        &i2c_inventory,
+        display_service.clone(),
+        pins.gpio16,
+        peripherals.i2c0,
+        pins.gpio4,
+        pins.gpio15,
+    )?;
 
     #[cfg(feature = "ssd1306g_spi")]
     ssd1306g_hello_world_spi(
+        display_service.clone(),
         pins.gpio4.into(),
         pins.gpio16.into(),
         peripherals.spi3,
@@ -214,7 +300,9 @@ fn main() -> Result<()> {
     )?;
 
     #[cfg(feature = "ssd1306g")]
-    let mut led_power = ssd1306g_hello_world(
+    let _led_power = ssd1306g_hello_world(
+        &i2c_inventory,
+        display_service.clone(),
         peripherals.i2c0,
         pins.gpio14.into(),
         pins.gpio22.into(),
@@ -223,6 +311,8 @@ fn main() -> Result<()> {
 
     #[cfg(feature = "esp32s3_usb_otg")]
     esp32s3_usb_otg_hello_world(
+        &pwm,
+        display_service.clone(),
         pins.gpio9,
         pins.gpio4,
         pins.gpio8,
@@ -232,6 +322,15 @@ fn main() -> Result<()> {
         pins.gpio5,
     )?;
 
+    #[cfg(feature = "spi_display")]
+    let _panel_reset = panel::hello_world(
+        &panel::PanelConfig::from_env()?,
+        &gpios,
+        &pwm,
+        display_service.clone(),
+        peripherals.spi2,
+    )?;
+
     #[allow(clippy::redundant_clone)]
     #[cfg(not(feature = "qemu"))]
     #[allow(unused_mut)]
@@ -303,6 +402,21 @@ fn main() -> Result<()> {
         eth
     };
 
+    #[cfg(not(feature = "qemu"))]
+    {
+        status.set_ip(wifi.sta_netif().get_ip_info()?.ip);
+
+        display_service.set_access_point(display::WifiNetwork {
+            ssid: AP_SSID.into(),
+            password: None,
+        });
+    }
+
+    #[cfg(any(feature = "qemu", feature = "w5500", feature = "ip101"))]
+    status.set_ip(eth.netif().get_ip_info()?.ip);
+
+    let _ip_subscription = dashboard::subscribe_ip(&sysloop, status.clone())?;
+
     test_tcp()?;
 
     test_tcp_bind()?;
@@ -312,9 +426,45 @@ fn main() -> Result<()> {
 
     let (eventloop, _subscription) = test_eventloop()?;
 
-    let mqtt_client = test_mqtt_client()?;
+    buttons::spawn(&gpios, eventloop.clone())?;
+
+    let _button_actions = buttons::subscribe_default_actions(&eventloop, display_service.clone())?;
+
+    #[cfg(any(esp32s2, esp32s3))]
+    touch::spawn(&touch::pads_from_env()?, &gpios, eventloop.clone())?;
 
-    let _timer = test_timer(eventloop, mqtt_client)?;
+    #[cfg(any(esp32s2, esp32s3))]
+    let _touch_actions = touch::subscribe_default_actions(&eventloop, display_service.clone())?;
+
+    let (home_assistant_state, home_assistant_triggered) = home_assistant::StateTrigger::new();
+
+    let mqtt_commands = Arc::new(mqtt_commands::CommandService::new(
+        gpios.clone(),
+        home_assistant_state.clone(),
+    ));
+
+    let mqtt_settings = mqtt_config.load()?;
+
+    let mqtt_client = test_mqtt_client(
+        &mqtt_settings,
+        status.clone(),
+        pwm.clone(),
+        display_service.clone(),
+        mqtt_commands.clone(),
+        home_assistant_state,
+    )?;
+
+    home_assistant::spawn(
+        mqtt_client.clone(),
+        &mqtt_settings,
+        status.clone(),
+        gpios.clone(),
+        pwm.clone(),
+        home_assistant_triggered,
+    )?;
+
+    // The `telemetry` MQTT command changes the interval of the timer
+    mqtt_commands.set_telemetry_timer(test_timer(eventloop, mqtt_client)?);
 
     #[allow(clippy::needless_update)]
     {
@@ -339,17 +489,15 @@ fn main() -> Result<()> {
 
     let mutex = Arc::new((Mutex::new(None), Condvar::new()));
 
-    let httpd = httpd(mutex.clone())?;
-
-    #[cfg(feature = "ssd1306g")]
-    {
-        for s in 0..3 {
-            info!("Powering off the display in {} secs", 3 - s);
-            thread::sleep(Duration::from_secs(1));
-        }
-
-        led_power.set_low()?;
-    }
+    let httpd = httpd(
+        mutex.clone(),
+        adc_cal.clone(),
+        gpios.clone(),
+        pwm.clone(),
+        i2c_inventory.clone(),
+        display_service.clone(),
+        mqtt_config.clone(),
+    )?;
 
     let mut wait = mutex.0.lock().unwrap();
 
@@ -368,6 +516,9 @@ fn main() -> Result<()> {
         &adc::config::Config::new().calibration(true),
     )?;
 
+    #[cfg(all(not(esp_idf_version_major = "4"), esp_idf_soc_temp_sensor_supported))]
+    let mut temp_sensor = temp_sensor::TempSensor::new(temp_sensor_range()?)?;
+
     #[allow(unused)]
     let cycles = loop {
         if let Some(cycles) = *wait {
@@ -384,10 +535,20 @@ fn main() -> Result<()> {
                 "Hall sensor reading: {}mV",
                 powered_adc1.read_hall(&mut hall_sensor).unwrap()
             );
-            log::info!(
-                "A2 sensor reading: {}mV",
-                powered_adc1.read(&mut a2).unwrap()
-            );
+            let a2_mv = adc_cal.read(&mut powered_adc1, &mut a2).unwrap();
+
+            log::info!("A2 sensor reading: {}mV", a2_mv);
+
+            status.set_adc_mv(a2_mv);
+
+            #[cfg(all(not(esp_idf_version_major = "4"), esp_idf_soc_temp_sensor_supported))]
+            {
+                let celsius = temp_sensor.read_celsius().unwrap();
+
+                log::info!("Temperature sensor reading: {:.1}C", celsius);
+
+                status.set_temperature(celsius);
+            }
         }
     };
 
@@ -588,8 +749,8 @@ fn test_tcp_bind() -> Result<()> {
 
 fn test_timer(
     eventloop: EspBackgroundEventLoop,
-    mut client: EspMqttClient<'static>,
-) -> Result<EspTimer> {
+    client: Arc<Mutex<EspMqttClient<'static>>>,
+) -> Result<EspTimer<'static>> {
     info!("About to schedule a one-shot timer for after 2 seconds");
     let once_timer = EspTaskTimerService::new()?.timer(|| {
         info!("One-shot timer triggered");
@@ -611,6 +772,8 @@ fn test_timer(
                 .unwrap();
 
             client
+                .lock()
+                .unwrap()
                 .publish(
                     "rust-esp32-std-demo",
                     QoS::AtMostOnce,
@@ -675,42 +838,130 @@ fn test_eventloop() -> Result<(EspBackgroundEventLoop, EspBackgroundSubscription
     Ok((eventloop, subscription))
 }
 
-fn test_mqtt_client() -> Result<EspMqttClient<'static>> {
-    info!("About to start MQTT client");
+fn test_mqtt_client(
+    config: &mqtt_config::MqttConfig,
+    status: Arc<dashboard::Status>,
+    pwm: Arc<pwm::PwmService>,
+    display_service: Arc<display::DisplayService>,
+    commands: Arc<mqtt_commands::CommandService>,
+    home_assistant_state: home_assistant::StateTrigger,
+) -> Result<Arc<Mutex<EspMqttClient<'static>>>> {
+    info!("About to start MQTT client for broker {}", config.url);
 
-    let conf = MqttClientConfiguration {
-        client_id: Some("rust-esp32-std-demo"),
-        crt_bundle_attach: Some(esp_idf_svc::sys::esp_crt_bundle_attach),
+    let status_topic = config.device_topic("status");
 
-        ..Default::default()
-    };
+    let conf = config.client_configuration(&status_topic)?;
 
-    let (mut client, mut connection) = EspMqttClient::new("mqtts://broker.emqx.io:8883", &conf)?;
+    let (client, mut connection) = EspMqttClient::new(&config.url, &conf)?;
+
+    let client = Arc::new(Mutex::new(client));
 
     info!("MQTT client started");
 
+    let (connected, on_connected) = std::sync::mpsc::channel();
+
+    mqtt_presence::spawn(client.clone(), config, status, on_connected)?;
+
+    let (command_sender, received_commands) = std::sync::mpsc::channel();
+
+    mqtt_commands::spawn(client.clone(), config, commands, received_commands)?;
+
+    let command_prefix = config.device_topic("cmd/");
+    let pwm_prefix = config.device_topic("pwm/");
+    let display_topic = config.device_topic("display");
+
     // Need to immediately start pumping the connection for messages, or else subscribe() and publish() below will not work
     // Note that when using the alternative constructor - `EspMqttClient::new` - you don't need to
     // spawn a new thread, as the messages will be pumped with a backpressure into the callback you provide.
     // Yet, you still need to efficiently process each message in the callback without blocking for too long.
     //
-    // Note also that if you go to http://tools.emqx.io/ and then connect and send a message to topic
-    // "rust-esp32-std-demo", the client configured here should receive it.
+    // Note also that with the default settings, if you go to http://tools.emqx.io/ and then connect
+    // and send a message to topic "rust-esp32-std-demo", the client configured here should receive it.
     thread::spawn(move || {
         info!("MQTT Listening for messages");
 
         while let Ok(event) = connection.next() {
             info!("MQTT Event: {}", event.payload());
+
+            if let EventPayload::Connected(_) = event.payload() {
+                // The presence thread publishes the birth messages
+                let _ = connected.send(());
+            }
+
+            if let EventPayload::Received {
+                topic: Some(topic),
+                data,
+                ..
+            } = event.payload()
+            {
+                if let Some(pin) = topic.strip_prefix(pwm_prefix.as_str()) {
+                    let result = pin
+                        .parse::<i32>()
+                        .map_err(anyhow::Error::from)
+                        .and_then(|pin| {
+                            let duty = std::str::from_utf8(data)?.trim().parse::<f32>()?;
+
+                            pwm.set_duty(pin, duty)
+                        });
+
+                    match result {
+                        Ok(()) => home_assistant_state.trigger(),
+                        Err(err) => {
+                            warn!("Setting PWM duty from MQTT topic {} failed: {}", topic, err)
+                        }
+                    }
+                } else if topic == display_topic {
+                    let result = display::Message::parse(data)
+                        .map_err(anyhow::Error::from)
+                        .and_then(|message| display_service.show_message(message));
+
+                    if let Err(err) = result {
+                        warn!(
+                            "Showing the message from MQTT topic {} failed: {}",
+                            topic, err
+                        );
+                    }
+                } else if let Some(name) = topic.strip_prefix(command_prefix.as_str()) {
+                    // Executed and replied to by the command thread
+                    let _ = command_sender.send(mqtt_commands::Command {
+                        name: name.into(),
+                        payload: data.to_vec(),
+                    });
+                }
+            }
         }
 
         info!("MQTT connection loop exit");
     });
 
-    client.subscribe("rust-esp32-std-demo", QoS::AtMostOnce)?;
+    let mut client_guard = client.lock().unwrap();
+
+    client_guard.subscribe("rust-esp32-std-demo", QoS::AtMostOnce)?;
 
     info!("Subscribed to all topics (rust-esp32-std-demo)");
 
-    client.publish(
+    let pwm_topic = config.device_topic("pwm/+");
+
+    client_guard.subscribe(&pwm_topic, QoS::AtMostOnce)?;
+
+    info!("Subscribed to the device's PWM duty topics ({})", pwm_topic);
+
+    let display_topic = config.device_topic("display");
+
+    client_guard.subscribe(&display_topic, QoS::AtMostOnce)?;
+
+    info!(
+        "Subscribed to the display message topic ({})",
+        display_topic
+    );
+
+    let command_topic = config.device_topic("cmd/#");
+
+    client_guard.subscribe(&command_topic, QoS::AtLeastOnce)?;
+
+    info!("Subscribed to the command topics ({})", command_topic);
+
+    client_guard.publish(
         "rust-esp32-std-demo",
         QoS::AtMostOnce,
         false,
@@ -719,6 +970,8 @@ fn test_mqtt_client() -> Result<EspMqttClient<'static>> {
 
     info!("Published a hello message to topic \"rust-esp32-std-demo\"");
 
+    drop(client_guard);
+
     Ok(client)
 }
 
@@ -868,6 +1121,8 @@ fn test_https_client() -> anyhow::Result<()> {
 
 #[cfg(feature = "ttgo")]
 fn ttgo_hello_world(
+    pwm: &pwm::PwmService,
+    display_service: Arc<display::DisplayService>,
     backlight: gpio::Gpio4,
     dc: gpio::Gpio16,
     rst: gpio::Gpio23,
@@ -878,8 +1133,7 @@ fn ttgo_hello_world(
 ) -> Result<()> {
     info!("About to initialize the TTGO ST7789 LED driver");
 
-    let mut backlight = gpio::PinDriver::output(backlight)?;
-    backlight.set_high()?;
+    pwm.attach_backlight(backlight)?;
 
     let di = SPIInterfaceNoCS::new(
         spi::SpiDeviceDriver::new_single(
@@ -888,7 +1142,7 @@ fn ttgo_hello_world(
             sdo,
             Option::<gpio::Gpio21>::None,
             Some(cs),
-            &spi::SpiDriverConfig::new().dma(spi::Dma::Disabled),
+            &spi::SpiDriverConfig::new().dma(spi::Dma::Auto(4096)),
             &spi::SpiConfig::new().baudrate(26.MHz().into()),
         )?,
         gpio::PinDriver::output(dc)?,
@@ -906,12 +1160,13 @@ fn ttgo_hello_world(
     let top_left = Point::new(52, 40);
     let size = Size::new(135, 240);
 
-    led_draw(&mut display.cropped(&Rectangle::new(top_left, size)))
-        .map_err(|e| anyhow::anyhow!("Display error: {:?}", e))
+    spawn_buffered_screen(display_service, display, Rectangle::new(top_left, size))
 }
 
 #[cfg(feature = "kaluga")]
 fn kaluga_hello_world(
+    pwm: &pwm::PwmService,
+    display_service: Arc<display::DisplayService>,
     backlight: gpio::Gpio6,
     dc: gpio::Gpio13,
     rst: gpio::Gpio16,
@@ -922,8 +1177,7 @@ fn kaluga_hello_world(
 ) -> Result<()> {
     info!("About to initialize the Kaluga ST7789 SPI LED driver");
 
-    let mut backlight = gpio::PinDriver::output(backlight)?;
-    backlight.set_high()?;
+    pwm.attach_backlight(backlight)?;
 
     let di = SPIInterfaceNoCS::new(
         spi::SpiDeviceDriver::new_single(
@@ -932,7 +1186,7 @@ fn kaluga_hello_world(
             sdo,
             Option::<gpio::AnyIOPin>::None,
             Some(cs),
-            &spi::SpiDriverConfig::new().dma(spi::Dma::Disabled),
+            &spi::SpiDriverConfig::new().dma(spi::Dma::Auto(4096)),
             &spi::SpiConfig::new().baudrate(80.MHz().into()),
         )?,
         gpio::PinDriver::output(dc)?,
@@ -946,13 +1200,15 @@ fn kaluga_hello_world(
         .set_orientation(mipidsi::options::Orientation::Landscape(false))
         .map_err(|e| anyhow::anyhow!("Display error: {:?}", e))?;
 
-    led_draw(&mut display).map_err(|e| anyhow::anyhow!("Display error: {:?}", e))?;
+    let area = display.bounding_box();
 
-    Ok(())
+    spawn_buffered_screen(display_service, display, area)
 }
 
 #[cfg(feature = "heltec")]
 fn heltec_hello_world(
+    i2c_inventory: &i2c_scan::I2cInventory,
+    display_service: Arc<display::DisplayService>,
     rst: gpio::Gpio16,
     i2c: i2c::I2C0,
     sda: gpio::Gpio4,
@@ -960,12 +1216,12 @@ fn heltec_hello_world(
 ) -> Result<()> {
     info!("About to initialize the Heltec SSD1306 I2C LED driver");
 
-    let di = ssd1306::I2CDisplayInterface::new(i2c::I2cDriver::new(
+    let mut i2c = i2c::I2cDriver::new(
         i2c,
         sda,
         scl,
         &i2c::I2cConfig::new().baudrate(400.kHz().into()),
-    )?);
+    )?;
 
     let mut reset = gpio::PinDriver::output(rst)?;
 
@@ -977,6 +1233,10 @@ fn heltec_hello_world(
 
     reset.set_high()?;
 
+    i2c_inventory.scan("i2c0", &mut i2c)?;
+
+    let di = ssd1306::I2CDisplayInterface::new(i2c);
+
     let mut display = ssd1306::Ssd1306::new(
         di,
         ssd1306::size::DisplaySize128x64,
@@ -1001,11 +1261,12 @@ fn heltec_hello_world(
         .flush()
         .map_err(|e| anyhow::anyhow!("Display error: {:?}", e))?;
 
-    Ok(())
+    spawn_binary_screen(display_service, display)
 }
 
 #[cfg(feature = "ssd1306g_spi")]
 fn ssd1306g_hello_world_spi(
+    display_service: Arc<display::DisplayService>,
     dc: gpio::AnyOutputPin,
     rst: gpio::AnyOutputPin,
     spi: impl peripheral::Peripheral<P = impl spi::SpiAnyPins> + 'static,
@@ -1062,11 +1323,13 @@ fn ssd1306g_hello_world_spi(
         .flush()
         .map_err(|e| anyhow::anyhow!("Display error: {:?}", e))?;
 
-    Ok(())
+    spawn_binary_screen(display_service, display)
 }
 
 #[cfg(feature = "ssd1306g")]
 fn ssd1306g_hello_world(
+    i2c_inventory: &i2c_scan::I2cInventory,
+    display_service: Arc<display::DisplayService>,
     i2c: impl peripheral::Peripheral<P = impl i2c::I2c> + 'static,
     pwr: gpio::AnyOutputPin,
     scl: gpio::AnyIOPin,
@@ -1074,12 +1337,12 @@ fn ssd1306g_hello_world(
 ) -> Result<gpio::PinDriver<'static, gpio::AnyOutputPin, gpio::Output>> {
     info!("About to initialize a generic SSD1306 I2C LED driver");
 
-    let di = ssd1306::I2CDisplayInterface::new(i2c::I2cDriver::new(
+    let mut i2c = i2c::I2cDriver::new(
         i2c,
         sda,
         scl,
         &i2c::I2cConfig::new().baudrate(400.kHz().into()),
-    )?);
+    )?;
 
     let mut power = gpio::PinDriver::output(pwr)?;
 
@@ -1091,6 +1354,10 @@ fn ssd1306g_hello_world(
     power.set_high()?;
     delay::Ets::delay_ms(10_u32);
 
+    i2c_inventory.scan("i2c0", &mut i2c)?;
+
+    let di = ssd1306::I2CDisplayInterface::new(i2c);
+
     let mut display = ssd1306::Ssd1306::new(
         di,
         ssd1306::size::DisplaySize128x64,
@@ -1115,11 +1382,62 @@ fn ssd1306g_hello_world(
         .flush()
         .map_err(|e| anyhow::anyhow!("Display error: {:?}", e))?;
 
+    spawn_binary_screen(display_service, display)?;
+
     Ok(power)
 }
 
+#[cfg(any(
+    feature = "heltec",
+    feature = "ssd1306g_spi",
+    feature = "ssd1306g",
+    feature = "spi_display"
+))]
+fn spawn_binary_screen<D>(
+    display_service: Arc<display::DisplayService>,
+    mut display: D,
+) -> Result<()>
+where
+    D: display::Oled + Send + 'static,
+    D::Error: fmt::Debug,
+{
+    let theme = display::Theme::binary();
+    let size = display.bounding_box().size;
+
+    let mut screen = display::Screen::new(theme);
+    let mirror = display_service.mirror(size, BinaryColor::Off);
+
+    display_service.set_picture_format(size, theme.picture.mode);
+
+    let mut power = display::PowerState::On;
+
+    display_service.spawn(Duration::from_secs(1), move |frame| {
+        // OLEDs have no backlight, so dim and blank the panel itself
+        if frame.power != power {
+            power = frame.power;
+
+            display.set_power(power)?;
+        }
+
+        if power == display::PowerState::Blanked {
+            return Ok(());
+        }
+
+        screen
+            .draw(
+                &mut display::Mirrored::new(&mut display, &mut mirror.lock().unwrap()),
+                frame,
+            )
+            .map_err(|e| anyhow::anyhow!("Display error: {:?}", e))?;
+
+        display.flush()
+    })
+}
+
 #[cfg(feature = "esp32s3_usb_otg")]
 fn esp32s3_usb_otg_hello_world(
+    pwm: &pwm::PwmService,
+    display_service: Arc<display::DisplayService>,
     backlight: gpio::Gpio9,
     dc: gpio::Gpio4,
     rst: gpio::Gpio8,
@@ -1130,8 +1448,7 @@ fn esp32s3_usb_otg_hello_world(
 ) -> Result<()> {
     info!("About to initialize the ESP32-S3-USB-OTG SPI LED driver ST7789VW");
 
-    let mut backlight = gpio::PinDriver::output(backlight)?;
-    backlight.set_high()?;
+    pwm.attach_backlight(backlight)?;
 
     let di = SPIInterfaceNoCS::new(
         spi::SpiDeviceDriver::new_single(
@@ -1140,7 +1457,7 @@ fn esp32s3_usb_otg_hello_world(
             sdo,
             Option::<gpio::AnyIOPin>::None,
             Some(cs),
-            &spi::SpiDriverConfig::new().dma(spi::Dma::Disabled),
+            &spi::SpiDriverConfig::new().dma(spi::Dma::Auto(4096)),
             &spi::SpiConfig::new().baudrate(80.MHz().into()),
         )?,
         gpio::PinDriver::output(dc)?,
@@ -1154,62 +1471,81 @@ fn esp32s3_usb_otg_hello_world(
         .set_orientation(mipidsi::options::Orientation::Landscape(false))
         .map_err(|e| anyhow::anyhow!("Display error: {:?}", e))?;
 
-    led_draw(&mut display).map_err(|e| anyhow::anyhow!("Led draw error: {:?}", e))
-}
+    let area = display.bounding_box();
 
-#[allow(dead_code)]
-fn led_draw<D>(display: &mut D) -> Result<(), D::Error>
-where
-    D: DrawTarget + Dimensions,
-    D::Color: RgbColor,
-{
-    led_draw_custom(
-        display,
-        RgbColor::BLACK,
-        RgbColor::WHITE,
-        RgbColor::BLUE,
-        RgbColor::YELLOW,
-    )
+    spawn_buffered_screen(display_service, display, area)
 }
 
-#[allow(dead_code)]
-fn led_draw_custom<D>(
-    display: &mut D,
-    bg: D::Color,
-    fg: D::Color,
-    fill: D::Color,
-    stroke: D::Color,
-) -> Result<(), D::Error>
+#[cfg(any(
+    feature = "ttgo",
+    feature = "kaluga",
+    feature = "esp32s3_usb_otg",
+    feature = "spi_display"
+))]
+fn spawn_buffered_screen<D>(
+    display_service: Arc<display::DisplayService>,
+    display: D,
+    area: Rectangle,
+) -> Result<()>
 where
-    D: DrawTarget + Dimensions,
+    D: DrawTarget<Color = Rgb565> + Send + 'static,
+    D::Error: fmt::Debug,
 {
-    display.clear(bg)?;
-
-    Rectangle::new(display.bounding_box().top_left, display.bounding_box().size)
-        .into_styled(
-            PrimitiveStyleBuilder::new()
-                .fill_color(fill)
-                .stroke_color(stroke)
-                .stroke_width(1)
-                .build(),
-        )
-        .draw(display)?;
-
-    Text::new(
-        "Hello Rust!",
-        Point::new(10, (display.bounding_box().size.height - 10) as i32 / 2),
-        MonoTextStyle::new(&FONT_10X20, fg),
-    )
-    .draw(display)?;
+    let mut back_buffer = display_service.back_buffer(display, area, Rgb565::BLACK)?;
 
-    info!("LED rendering done");
+    display_service.set_picture_format(area.size, display::Theme::rgb().picture.mode);
 
-    Ok(())
+    // With the back buffer, the SPI transfers to the panel happen on a thread of its own
+    back_buffer
+        .draw(|buffer| led_draw(buffer))
+        .map_err(|e| anyhow::anyhow!("Display error: {:?}", e))?;
+
+    #[cfg(not(feature = "slint"))]
+    let result = {
+        let mut screen = display::Screen::new(display::Theme::rgb());
+
+        display_service.spawn(Duration::from_secs(1), move |frame| {
+            // The backlight is off, so save the SPI transfers
+            if frame.power == display::PowerState::Blanked {
+                return Ok(());
+            }
+
+            back_buffer
+                .draw(|buffer| screen.draw(buffer, frame))
+                .map_err(|e| anyhow::anyhow!("Display error: {:?}", e))
+        })
+    };
+
+    // Slint is single-threaded, so the UI lives on the renderer thread, which also needs a larger
+    // stack for the software renderer
+    #[cfg(feature = "slint")]
+    let result = display_service.spawn_with(Duration::from_secs(1), 32 * 1024, move || {
+        let mut ui =
+            display::UiScreen::new(area.size).map_err(|e| anyhow::anyhow!("Slint error: {}", e))?;
+
+        Ok(move |frame: &display::Frame| {
+            if frame.power == display::PowerState::Blanked {
+                return Ok(());
+            }
+
+            back_buffer
+                .draw(|buffer| ui.draw(buffer, frame))
+                .map_err(|e| anyhow::anyhow!("Display error: {:?}", e))
+        })
+    });
+
+    result
 }
 
 #[allow(unused_variables)]
 fn httpd(
     mutex: Arc<(Mutex<Option<u32>>, Condvar)>,
+    adc_cal: Arc<adc_cal::AdcCalibration>,
+    gpios: Arc<gpio_api::GpioService>,
+    pwm: Arc<pwm::PwmService>,
+    i2c_inventory: Arc<i2c_scan::I2cInventory>,
+    display_service: Arc<display::DisplayService>,
+    mqtt_config: Arc<mqtt_config::MqttConfigStore>,
 ) -> Result<esp_idf_svc::http::server::EspHttpServer<'static>> {
     use esp_idf_svc::http::server::{
         fn_handler, Connection, EspHttpServer, Handler, Method, Middleware,
@@ -1257,7 +1593,12 @@ fn httpd(
         }
     }
 
-    let mut server = EspHttpServer::new(&Default::default())?;
+    let mut server = EspHttpServer::new(&esp_idf_svc::http::server::Configuration {
+        // One per endpoint and method registered below - about 30 with all features - which is
+        // close to the default of 32, beyond which registering fails
+        max_uri_handlers: 48,
+        ..Default::default()
+    })?;
 
     server
         .fn_handler("/", Method::Get, |req| {
@@ -1286,12 +1627,113 @@ fn httpd(
             }))),
         )?;
 
+    adc_cal::httpd_endpoints(&mut server, adc_cal)?;
+
+    gpio_api::httpd_endpoints(&mut server, gpios)?;
+
+    pwm::httpd_endpoints(&mut server, pwm)?;
+
+    i2c_scan::httpd_endpoints(&mut server, i2c_inventory)?;
+
+    mqtt_config::httpd_endpoints(&mut server, mqtt_config)?;
+
+    #[cfg(esp_idf_httpd_ws_support)]
+    live_view::httpd_endpoints(&mut server, display_service.clone())?;
+
+    display::httpd_endpoints(&mut server, display_service)?;
+
     #[cfg(esp32s2)]
     httpd_ulp_endpoints(&mut server, mutex)?;
 
     Ok(server)
 }
 
+/// Reads the URL-encoded form in the body of the request
+///
+/// Forms larger than `MAX_FORM_SIZE` are answered with `413 Payload Too Large` instead, and `None`
+/// returned.
+fn read_form(req: &mut Request<&mut EspHttpConnection>) -> Result<Option<Vec<(String, String)>>> {
+    let len = req
+        .header("Content-Length")
+        .and_then(|len| len.parse().ok())
+        .unwrap_or(0);
+
+    if len > MAX_FORM_SIZE {
+        let conn = req.connection();
+
+        conn.initiate_response(413, Some("Payload Too Large"), &[])?;
+        conn.write_all(format!("Forms are limited to {} bytes", MAX_FORM_SIZE).as_bytes())?;
+
+        return Ok(None);
+    }
+
+    let mut body = Vec::with_capacity(len);
+    let mut buf = [0_u8; 256];
+
+    while body.len() < len {
+        let read = req.read(&mut buf[..(len - body.len()).min(256)])?;
+        if read == 0 {
+            break;
+        }
+
+        body.extend_from_slice(&buf[..read]);
+    }
+
+    Ok(Some(
+        url::form_urlencoded::parse(&body).into_owned().collect(),
+    ))
+}
+
+fn read_query(req: &Request<&mut EspHttpConnection>) -> Vec<(String, String)> {
+    req.uri()
+        .split_once('?')
+        .map(|(_, query)| {
+            url::form_urlencoded::parse(query.as_bytes())
+                .into_owned()
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn forbidden(req: Request<&mut EspHttpConnection>, err: impl fmt::Display) -> Result<()> {
+    req.into_response(403, Some("Forbidden"), &[])?
+        .write_all(err.to_string().as_bytes())?;
+
+    Ok(())
+}
+
+fn bad_request(req: Request<&mut EspHttpConnection>, err: impl fmt::Display) -> Result<()> {
+    req.into_response(400, Some("Bad Request"), &[])?
+        .write_all(err.to_string().as_bytes())?;
+
+    Ok(())
+}
+
+/// The range of the on-die temperature sensor, in degrees Celsius
+#[allow(dead_code)]
+fn temp_sensor_range() -> Result<core::ops::RangeInclusive<i32>> {
+    match option_env!("RUST_ESP32_STD_DEMO_TEMP_SENSOR_RANGE") {
+        Some(range) => {
+            let (min, max) = range.split_once(',').ok_or_else(|| {
+                anyhow::anyhow!(
+                    "Invalid temperature sensor range {}, expected <min>,<max>",
+                    range
+                )
+            })?;
+
+            Ok(min.parse()?..=max.parse()?)
+        }
+        None => Ok(TEMP_SENSOR_RANGE),
+    }
+}
+
+fn form_param<'a>(form: &'a [(String, String)], name: &str) -> Result<&'a str> {
+    form.iter()
+        .find(|(key, _)| key == name)
+        .map(|(_, value)| value.as_str())
+        .ok_or_else(|| anyhow::anyhow!("No parameter {}", name))
+}
+
 #[cfg(esp32s2)]
 fn httpd_ulp_endpoints(
     server: &mut esp_idf_svc::http::server::EspHttpServer,
@@ -1429,7 +1871,7 @@ fn wifi(
             ..Default::default()
         },
         AccessPointConfiguration {
-            ssid: "aptest".try_into().unwrap(),
+            ssid: AP_SSID.try_into().unwrap(),
             channel: channel.unwrap_or(1),
             ..Default::default()
         },
@@ -1503,6 +1945,7 @@ fn enable_napt(wifi: &mut EspWifi) -> Result<()> {
 
 #[cfg(feature = "waveshare_epd")]
 fn waveshare_epd_hello_world(
+    display_service: Arc<display::DisplayService>,
     spi: impl peripheral::Peripheral<P = impl spi::SpiAnyPins> + 'static,
     sclk: gpio::AnyOutputPin,
     sdo: gpio::AnyOutputPin,
@@ -1513,7 +1956,7 @@ fn waveshare_epd_hello_world(
 ) -> Result<()> {
     info!("About to initialize Waveshare 4.2 e-paper display");
 
-    let mut driver = spi::SpiDeviceDriver::new_single(
+    let driver = spi::SpiDeviceDriver::new_single(
         spi,
         sclk,
         sdo,
@@ -1523,30 +1966,41 @@ fn waveshare_epd_hello_world(
         &spi::SpiConfig::new().baudrate(26.MHz().into()),
     )?;
 
-    // Setup EPD
-    let mut epd = Epd4in2::new(
-        &mut driver,
-        gpio::PinDriver::output(cs)?,
-        gpio::PinDriver::input(busy_in)?,
-        gpio::PinDriver::output(dc)?,
-        gpio::PinDriver::output(rst)?,
-        &mut delay::Ets,
-    )
-    .unwrap();
+    let config = epaper::EpaperConfig::default();
 
-    // Use display graphics from embedded-graphics
-    let mut buffer =
-        vec![DEFAULT_BACKGROUND_COLOR.get_byte_value(); WIDTH as usize / 8 * HEIGHT as usize];
-    let mut display = VarDisplay::new(WIDTH, HEIGHT, &mut buffer);
+    // Setup EPD
+    let mut epd = epaper::Epaper::new(driver, cs, busy_in, dc, rst, &config)?;
 
     let style = MonoTextStyle::new(&FONT_10X20, BinaryColor::On);
 
     // Create a text at position (20, 30) and draw it using the previously defined style
-    Text::new("Hello Rust!", Point::new(20, 30), style).draw(&mut display)?;
+    Text::new("Hello Rust!", Point::new(20, 30), style).draw(&mut epd.display())?;
 
     // Display updated frame
-    epd.update_frame(&mut driver, &display.buffer(), &mut delay::Ets)?;
-    epd.display_frame(&mut driver, &mut delay::Ets)?;
+    epd.update()?;
 
-    Ok(())
+    // Unlike on the OLEDs, `BinaryColor::On` is a black pixel here
+    let theme = display::Theme::epaper();
+    let mut screen = display::Screen::new(theme);
+
+    display_service.set_picture_format(epd.display().bounding_box().size, theme.picture.mode);
+
+    info!(
+        "E-paper redrawn every {:?}, with a full refresh every {} updates",
+        config.interval, config.full_refresh_every
+    );
+
+    // What is on the panel is kept anyway, for deciding on partial refreshes
+    display_service.set_mirror(epd.mirror());
+
+    display_service.spawn(config.interval, move |frame| {
+        // The panel keeps showing the last frame while asleep, so blanking just stops the refreshes
+        if frame.power == display::PowerState::Blanked {
+            return Ok(());
+        }
+
+        screen.draw(&mut epd.display(), frame)?;
+
+        epd.update()
+    })
 }
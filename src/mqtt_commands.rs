@@ -0,0 +1,321 @@
+//! Remote control of the board over MQTT
+//!
+//! Commands are published to `<prefix>/<device-id>/cmd/<command>`, with the arguments of the
+//! command - if any - as a JSON object. Each command is answered on `<prefix>/<device-id>/reply`,
+//! or on the topic in the `reply_to` argument, with the `id` argument of the command for
+//! correlating the two:
+//!
+//! - `{"id": ..., "command": "ping", "ok": true, "result": {...}}`
+//! - `{"id": ..., "command": "ping", "ok": false, "error": "..."}`
+//!
+//! The commands are received by the MQTT connection thread, which hands them over to the thread
+//! of this module, as they may take a while (pings, HTTP requests) and need to publish replies.
+
+use core::time::Duration;
+
+use std::net::{IpAddr, ToSocketAddrs};
+use std::sync::mpsc::Receiver;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use anyhow::{anyhow, bail, Result};
+
+use log::*;
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use esp_idf_svc::hal::gpio::Pull;
+use esp_idf_svc::http::client::{Configuration as HttpConfiguration, EspHttpConnection};
+use esp_idf_svc::http::Method;
+use esp_idf_svc::io::Read;
+use esp_idf_svc::mqtt::client::{EspMqttClient, QoS};
+use esp_idf_svc::ping;
+use esp_idf_svc::timer::EspTimer;
+
+use crate::gpio_api::{GpioService, Mode};
+use crate::home_assistant::StateTrigger;
+use crate::mqtt_config::MqttConfig;
+
+// The periodic timer cannot be much faster without flooding the broker
+const MIN_TELEMETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+// Publishing the reply to `reboot` takes a moment
+const REBOOT_DELAY: Duration = Duration::from_secs(1);
+
+// Pings are one second apart, and block the commands thread meanwhile
+const MAX_PING_COUNT: u32 = 20;
+
+/// A command, as received by the MQTT connection thread
+pub struct Command {
+    pub name: String,
+    pub payload: Vec<u8>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct Envelope {
+    id: Option<Value>,
+    reply_to: Option<String>,
+}
+
+pub struct CommandService {
+    gpios: Arc<GpioService>,
+    telemetry: Mutex<Option<EspTimer<'static>>>,
+    // Publishes the changes of the commands to Home Assistant right away
+    state: StateTrigger,
+}
+
+impl CommandService {
+    pub fn new(gpios: Arc<GpioService>, state: StateTrigger) -> Self {
+        Self {
+            gpios,
+            telemetry: Mutex::new(None),
+            state,
+        }
+    }
+
+    /// Hands over the periodic timer which publishes the telemetry, for the `telemetry` command
+    pub fn set_telemetry_timer(&self, timer: EspTimer<'static>) {
+        *self.telemetry.lock().unwrap() = Some(timer);
+    }
+
+    fn execute(&self, name: &str, args: &Value) -> Result<Value> {
+        match name {
+            "reboot" => Ok(json!({ "reboot_in_ms": REBOOT_DELAY.as_millis() as u64 })),
+            "gpio" => self.gpio(args),
+            "log_level" => log_level(args),
+            "ping" => ping(args),
+            "ota_check" => ota_check(),
+            "telemetry" => self.telemetry(args),
+            _ => bail!("Unknown command {}", name),
+        }
+    }
+
+    /// `{"pin": 4, "level": 1}` for a pin configured as an output, or with `"mode": "output"` to
+    /// configure it as one first
+    fn gpio(&self, args: &Value) -> Result<Value> {
+        let pin = arg_u64(args, "pin")?;
+        let pin = i32::try_from(pin).map_err(|_| anyhow!("Invalid GPIO {}", pin))?;
+        let high = arg_u64(args, "level")? != 0;
+
+        if args.get("mode").is_some() {
+            match arg_str(args, "mode")?.parse()? {
+                Mode::Output => self.gpios.configure(pin, Mode::Output, Pull::Floating)?,
+                Mode::Input => bail!("Only the output mode can be set with a level"),
+            }
+        }
+
+        self.gpios.write(pin, high)?;
+
+        Ok(json!({ "pin": pin, "level": high as u8 }))
+    }
+
+    /// `{"secs": 30}`
+    fn telemetry(&self, args: &Value) -> Result<Value> {
+        let interval = Duration::from_secs(arg_u64(args, "secs")?);
+
+        if interval < MIN_TELEMETRY_INTERVAL {
+            bail!(
+                "The telemetry interval is at least {}s",
+                MIN_TELEMETRY_INTERVAL.as_secs()
+            );
+        }
+
+        self.telemetry
+            .lock()
+            .unwrap()
+            .as_ref()
+            .ok_or_else(|| anyhow!("The telemetry timer is not running yet"))?
+            .every(interval)?;
+
+        info!("Telemetry interval set to {:?}", interval);
+
+        Ok(json!({ "secs": interval.as_secs() }))
+    }
+}
+
+/// Spawns the thread which executes the commands received over `commands`, and publishes the
+/// replies
+pub fn spawn(
+    client: Arc<Mutex<EspMqttClient<'static>>>,
+    config: &MqttConfig,
+    service: Arc<CommandService>,
+    commands: Receiver<Command>,
+) -> Result<()> {
+    let reply_topic = config.device_topic("reply");
+
+    thread::Builder::new()
+        .name("mqtt-commands".into())
+        .stack_size(8192)
+        .spawn(move || {
+            for command in commands {
+                let args = if command.payload.is_empty() {
+                    Ok(json!({}))
+                } else {
+                    serde_json::from_slice::<Value>(&command.payload).map_err(anyhow::Error::from)
+                };
+
+                let envelope = args
+                    .as_ref()
+                    .ok()
+                    .and_then(|args| Envelope::deserialize(args).ok())
+                    .unwrap_or_default();
+
+                info!("MQTT command {} (id {:?})", command.name, envelope.id);
+
+                let result = args.and_then(|args| service.execute(&command.name, &args));
+
+                if result.is_ok() {
+                    service.state.trigger();
+                }
+
+                let reply = match &result {
+                    Ok(result) => json!({
+                        "id": envelope.id,
+                        "command": command.name,
+                        "ok": true,
+                        "result": result,
+                    }),
+                    Err(err) => json!({
+                        "id": envelope.id,
+                        "command": command.name,
+                        "ok": false,
+                        "error": err.to_string(),
+                    }),
+                };
+
+                let topic = envelope.reply_to.as_deref().unwrap_or(&reply_topic);
+
+                if let Err(err) = client.lock().unwrap().publish(
+                    topic,
+                    QoS::AtLeastOnce,
+                    false,
+                    reply.to_string().as_bytes(),
+                ) {
+                    warn!("Replying to MQTT command {} failed: {}", command.name, err);
+                }
+
+                if command.name == "reboot" && result.is_ok() {
+                    thread::sleep(REBOOT_DELAY);
+
+                    unsafe { esp_idf_svc::sys::esp_restart() };
+                }
+            }
+        })?;
+
+    Ok(())
+}
+
+/// `{"level": "debug"}`, optionally with the `target` - i.e. the module or ESP-IDF component - to
+/// change the level of
+fn log_level(args: &Value) -> Result<Value> {
+    let level: log::LevelFilter = arg_str(args, "level")?.parse()?;
+    let target = args.get("target").and_then(Value::as_str).unwrap_or("*");
+
+    crate::log_console::set_level(target, level)?;
+
+    Ok(json!({ "target": target, "level": level.as_str().to_lowercase() }))
+}
+
+/// `{"host": "192.168.71.254", "count": 4}`, where the host can also be a name and the count
+/// is at most `MAX_PING_COUNT`
+fn ping(args: &Value) -> Result<Value> {
+    let host = arg_str(args, "host")?;
+    let count = args
+        .get("count")
+        .and_then(Value::as_u64)
+        .unwrap_or(4)
+        .clamp(1, MAX_PING_COUNT as _) as u32;
+
+    let ip = (host, 0)
+        .to_socket_addrs()?
+        .find_map(|addr| match addr.ip() {
+            IpAddr::V4(ip) => Some(ip),
+            IpAddr::V6(_) => None,
+        })
+        .ok_or_else(|| anyhow!("No IPv4 address for {}", host))?;
+
+    let summary = ping::EspPing::default().ping(
+        ip,
+        &ping::Configuration {
+            count,
+            ..Default::default()
+        },
+    )?;
+
+    Ok(json!({
+        "ip": ip.to_string(),
+        "transmitted": summary.transmitted,
+        "received": summary.received,
+        "time_ms": summary.time.as_millis() as u64,
+    }))
+}
+
+/// Compares the version of the firmware with the one in the JSON manifest at
+/// `RUST_ESP32_STD_DEMO_OTA_URL`, like `{"version": "0.30.0"}`
+///
+/// Only checks, as the partition table of the demo has no OTA slots to update into.
+fn ota_check() -> Result<Value> {
+    let url = option_env!("RUST_ESP32_STD_DEMO_OTA_URL")
+        .ok_or_else(|| anyhow!("Set RUST_ESP32_STD_DEMO_OTA_URL to check for updates"))?;
+
+    let mut conn = EspHttpConnection::new(&HttpConfiguration {
+        crt_bundle_attach: Some(esp_idf_svc::sys::esp_crt_bundle_attach),
+        timeout: Some(Duration::from_secs(10)),
+
+        ..Default::default()
+    })?;
+
+    conn.initiate_request(Method::Get, url, &[])?;
+    conn.initiate_response()?;
+
+    if conn.status() != 200 {
+        bail!("Fetching {} failed with HTTP status {}", url, conn.status());
+    }
+
+    let mut body = Vec::new();
+    let mut buf = [0_u8; 256];
+
+    loop {
+        let read = conn.read(&mut buf)?;
+        if read == 0 {
+            break;
+        }
+
+        body.extend_from_slice(&buf[..read]);
+    }
+
+    let manifest: Value = serde_json::from_slice(&body)?;
+    let latest = arg_str(&manifest, "version")?;
+
+    let current = env!("CARGO_PKG_VERSION");
+
+    Ok(json!({
+        "current": current,
+        "latest": latest,
+        "update_available": version(latest)? > version(current)?,
+    }))
+}
+
+fn version(version: &str) -> Result<Vec<u32>> {
+    version
+        .split('.')
+        .map(|part| {
+            part.parse()
+                .map_err(|_| anyhow!("Invalid version {}", version))
+        })
+        .collect()
+}
+
+fn arg_str<'a>(args: &'a Value, name: &str) -> Result<&'a str> {
+    args.get(name)
+        .and_then(Value::as_str)
+        .ok_or_else(|| anyhow!("No string argument {}", name))
+}
+
+fn arg_u64(args: &Value, name: &str) -> Result<u64> {
+    args.get(name)
+        .and_then(Value::as_u64)
+        .ok_or_else(|| anyhow!("No number argument {}", name))
+}
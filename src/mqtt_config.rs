@@ -0,0 +1,298 @@
+//! The MQTT connection settings, persisted in NVS
+//!
+//! Without any saved settings, the client connects to the public `broker.emqx.io` over TLS, and
+//! verifies it with the ESP-IDF certificate bundle. The settings are changed over HTTP and take
+//! effect on the next boot, as the MQTT client is only created once.
+
+use std::ffi::CString;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+
+use log::*;
+
+use serde::{Deserialize, Serialize};
+
+use esp_idf_svc::http::server::{EspHttpServer, Method};
+use esp_idf_svc::io::Write;
+use esp_idf_svc::mqtt::client::{LwtConfiguration, MqttClientConfiguration, QoS};
+use esp_idf_svc::nvs::{EspDefaultNvs, EspDefaultNvsPartition};
+use esp_idf_svc::tls::X509;
+
+use crate::mqtt_presence::OFFLINE;
+
+const NAMESPACE: &str = "mqtt";
+const KEY: &str = "config";
+
+// Certificates and keys are stored along the other settings as one blob. The NVS partition of
+// `partitions.csv` is only 24KB - 6 pages, one of which is kept free - shared with the WiFi
+// settings and the ADC calibrations, and a changed blob is written before the old one is erased.
+// Enough for an EC client certificate, its key and a CA certificate.
+const MAX_SIZE: usize = 4 * 1024;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MqttConfig {
+    pub url: String,
+    pub client_id: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// PEM client certificate and key, for brokers which authenticate the clients with TLS
+    pub client_cert: Option<String>,
+    pub client_key: Option<String>,
+    /// PEM CA certificate of the broker, instead of the ESP-IDF certificate bundle
+    pub ca_cert: Option<String>,
+    /// 0 disables the keep-alive pings
+    pub keep_alive_secs: u32,
+    pub clean_session: bool,
+    /// The topics of the device are `<topic_prefix>/<device_id>/...`
+    pub topic_prefix: String,
+    /// Derived from the MAC address if not set
+    pub device_id: Option<String>,
+}
+
+impl Default for MqttConfig {
+    fn default() -> Self {
+        Self {
+            url: "mqtts://broker.emqx.io:8883".into(),
+            client_id: "rust-esp32-std-demo".into(),
+            username: None,
+            password: None,
+            client_cert: None,
+            client_key: None,
+            ca_cert: None,
+            keep_alive_secs: 120,
+            clean_session: true,
+            topic_prefix: "rust-esp32-std-demo".into(),
+            device_id: None,
+        }
+    }
+}
+
+impl MqttConfig {
+    pub fn device_id(&self) -> String {
+        self.device_id.clone().unwrap_or_else(|| {
+            let mut mac = [0_u8; 6];
+
+            // Cannot fail with a valid buffer
+            unsafe { esp_idf_svc::sys::esp_efuse_mac_get_default(mac.as_mut_ptr()) };
+
+            format!("esp32-{:02x}{:02x}{:02x}", mac[3], mac[4], mac[5])
+        })
+    }
+
+    /// `<topic_prefix>/<device_id>/<topic>`
+    pub fn device_topic(&self, topic: &str) -> String {
+        format!("{}/{}/{}", self.topic_prefix, self.device_id(), topic)
+    }
+
+    /// The configuration of the ESP-IDF client, with a retained `offline` last will on
+    /// `status_topic`
+    ///
+    /// The client keeps referring to the certificates for as long as it is running, so they are
+    /// leaked - which is fine for the one client created at boot.
+    pub fn client_configuration<'a>(
+        &'a self,
+        status_topic: &'a str,
+    ) -> Result<MqttClientConfiguration<'a>> {
+        fn pem(pem: &Option<String>) -> Result<Option<X509<'static>>> {
+            pem.as_ref()
+                .map(|pem| {
+                    let pem = Box::leak(CString::new(pem.as_str())?.into_boxed_c_str());
+
+                    Ok(X509::pem(pem))
+                })
+                .transpose()
+        }
+
+        let server_certificate = pem(&self.ca_cert)?;
+
+        Ok(MqttClientConfiguration {
+            client_id: Some(&self.client_id),
+            username: self.username.as_deref(),
+            password: self.password.as_deref(),
+            client_certificate: pem(&self.client_cert)?,
+            private_key: pem(&self.client_key)?,
+            crt_bundle_attach: server_certificate
+                .is_none()
+                .then_some(esp_idf_svc::sys::esp_crt_bundle_attach),
+            server_certificate,
+            keep_alive_interval: (self.keep_alive_secs > 0)
+                .then(|| Duration::from_secs(self.keep_alive_secs as _)),
+            disable_clean_session: !self.clean_session,
+            lwt: Some(LwtConfiguration {
+                topic: status_topic,
+                payload: OFFLINE.as_bytes(),
+                qos: QoS::AtLeastOnce,
+                retain: true,
+            }),
+
+            ..Default::default()
+        })
+    }
+
+    /// Applies the form parameters which are present; an empty value clears an optional setting
+    fn update(&mut self, form: &[(String, String)]) -> Result<()> {
+        fn optional(value: &str) -> Option<String> {
+            (!value.is_empty()).then(|| value.into())
+        }
+
+        for (name, value) in form {
+            match name.as_str() {
+                "url" => {
+                    if !["mqtt://", "mqtts://", "ws://", "wss://"]
+                        .iter()
+                        .any(|scheme| value.starts_with(scheme))
+                    {
+                        bail!("Invalid broker URL {}", value);
+                    }
+
+                    self.url = value.clone();
+                }
+                "client_id" => self.client_id = value.clone(),
+                "username" => self.username = optional(value),
+                "password" => self.password = optional(value),
+                "client_cert" => self.client_cert = optional(value),
+                "client_key" => self.client_key = optional(value),
+                "ca_cert" => self.ca_cert = optional(value),
+                "keep_alive_secs" => self.keep_alive_secs = value.parse()?,
+                "clean_session" => self.clean_session = value.parse()?,
+                "topic_prefix" => {
+                    if value.is_empty() || value.contains(['+', '#']) {
+                        bail!("Invalid topic prefix {}", value);
+                    }
+
+                    self.topic_prefix = value.clone();
+                }
+                "device_id" => {
+                    if value.contains(['/', '+', '#']) {
+                        bail!("Invalid device ID {}", value);
+                    }
+
+                    self.device_id = optional(value);
+                }
+                _ => bail!("Unknown MQTT setting {}", name),
+            }
+        }
+
+        if self.client_cert.is_some() != self.client_key.is_some() {
+            bail!("The client certificate and key have to be set together");
+        }
+
+        Ok(())
+    }
+
+    /// The settings as JSON, without the password and the client key
+    fn to_json(&self) -> serde_json::Value {
+        let mut json = serde_json::to_value(self).unwrap();
+
+        for secret in ["password", "client_key"] {
+            if !json[secret].is_null() {
+                json[secret] = "<set>".into();
+            }
+        }
+
+        json
+    }
+}
+
+pub struct MqttConfigStore {
+    nvs: Mutex<EspDefaultNvs>,
+}
+
+impl MqttConfigStore {
+    pub fn new(partition: EspDefaultNvsPartition) -> Result<Self> {
+        Ok(Self {
+            nvs: Mutex::new(EspDefaultNvs::new(partition, NAMESPACE, true)?),
+        })
+    }
+
+    /// The saved settings, or the defaults if there are none
+    pub fn load(&self) -> Result<MqttConfig> {
+        let nvs = self.nvs.lock().unwrap();
+
+        let mut buf = vec![0; nvs.blob_len(KEY)?.unwrap_or(0)];
+
+        match nvs.get_blob(KEY, &mut buf)? {
+            Some(data) => match serde_json::from_slice(data) {
+                Ok(config) => Ok(config),
+                Err(err) => {
+                    warn!("Ignoring the NVS MQTT settings: {}", err);
+                    Ok(Default::default())
+                }
+            },
+            None => Ok(Default::default()),
+        }
+    }
+
+    pub fn save(&self, config: &MqttConfig) -> Result<()> {
+        let data = serde_json::to_vec(config)?;
+
+        if data.len() > MAX_SIZE {
+            bail!("The MQTT settings are larger than {} bytes", MAX_SIZE);
+        }
+
+        self.nvs.lock().unwrap().set_blob(KEY, &data)?;
+
+        info!("Saved the MQTT settings for broker {}", config.url);
+
+        Ok(())
+    }
+
+    /// Reverts to the default settings
+    pub fn reset(&self) -> Result<()> {
+        self.nvs.lock().unwrap().remove(KEY)?;
+
+        info!("Removed the MQTT settings");
+
+        Ok(())
+    }
+}
+
+pub fn httpd_endpoints(
+    server: &mut EspHttpServer<'static>,
+    store: Arc<MqttConfigStore>,
+) -> Result<()> {
+    let get = store.clone();
+    let set = store.clone();
+    let reset = store;
+
+    server
+        .fn_handler("/mqtt/config", Method::Get, move |req| {
+            let json = get.load()?.to_json().to_string();
+
+            req.into_response(200, Some("OK"), &[("Content-Type", "application/json")])?
+                .write_all(json.as_bytes())?;
+
+            Result::<_, anyhow::Error>::Ok(())
+        })?
+        .fn_handler("/mqtt/config", Method::Post, move |mut req| {
+            let Some(form) = crate::read_form(&mut req)? else {
+                return Ok(());
+            };
+
+            let mut config = set.load()?;
+
+            if let Err(err) = config.update(&form) {
+                return crate::bad_request(req, err);
+            }
+
+            set.save(&config)?;
+
+            req.into_ok_response()?.write_all(
+                "Saved, restart the board to connect with the new settings".as_bytes(),
+            )?;
+
+            Result::<_, anyhow::Error>::Ok(())
+        })?
+        .fn_handler("/mqtt/config/reset", Method::Post, move |req| {
+            reset.reset()?;
+
+            req.into_ok_response()?;
+
+            Result::<_, anyhow::Error>::Ok(())
+        })?;
+
+    Ok(())
+}
@@ -0,0 +1,97 @@
+//! Whether the device is online, and what it is, for the consumers of its MQTT topics
+//!
+//! The broker publishes the retained `offline` last will of the client on
+//! `<prefix>/<device-id>/status` when the connection drops. Each time the client (re)connects,
+//! it publishes a retained `online` on the same topic, and the retained device info - firmware
+//! version, IP address and enabled features - as JSON on `<prefix>/<device-id>/info`.
+//!
+//! The client cannot publish from the thread which pumps its connection, as the ESP-IDF client
+//! waits for that thread while it holds its lock. So the connection thread only signals the
+//! connects, and the messages are published from a thread of their own.
+
+use core::ffi::CStr;
+
+use std::sync::mpsc::Receiver;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use anyhow::Result;
+
+use log::*;
+
+use esp_idf_svc::mqtt::client::{EspMqttClient, QoS};
+
+use crate::dashboard::Status;
+use crate::mqtt_config::MqttConfig;
+
+/// The retained payloads of the status topic
+pub const ONLINE: &str = "online";
+pub const OFFLINE: &str = "offline";
+
+const FEATURES: &[(&str, bool)] = &[
+    ("qemu", cfg!(feature = "qemu")),
+    ("kaluga", cfg!(feature = "kaluga")),
+    ("ttgo", cfg!(feature = "ttgo")),
+    ("heltec", cfg!(feature = "heltec")),
+    ("ssd1306g_spi", cfg!(feature = "ssd1306g_spi")),
+    ("ssd1306g", cfg!(feature = "ssd1306g")),
+    ("esp32s3_usb_otg", cfg!(feature = "esp32s3_usb_otg")),
+    ("spi_display", cfg!(feature = "spi_display")),
+    ("slint", cfg!(feature = "slint")),
+    ("waveshare_epd", cfg!(feature = "waveshare_epd")),
+    ("ip101", cfg!(feature = "ip101")),
+    ("w5500", cfg!(feature = "w5500")),
+];
+
+/// Spawns the thread which announces the device whenever `connected` signals a connect
+pub fn spawn(
+    client: Arc<Mutex<EspMqttClient<'static>>>,
+    config: &MqttConfig,
+    status: Arc<Status>,
+    connected: Receiver<()>,
+) -> Result<()> {
+    let device_id = config.device_id();
+    let status_topic = config.device_topic("status");
+    let info_topic = config.device_topic("info");
+
+    thread::Builder::new()
+        .name("mqtt-presence".into())
+        .stack_size(6144)
+        .spawn(move || {
+            for () in connected {
+                let info = device_info(&device_id, &status).to_string();
+
+                let mut client = client.lock().unwrap();
+
+                let result = client
+                    .publish(&status_topic, QoS::AtLeastOnce, true, ONLINE.as_bytes())
+                    .and_then(|_| {
+                        client.publish(&info_topic, QoS::AtLeastOnce, true, info.as_bytes())
+                    });
+
+                match result {
+                    Ok(_) => info!("Announced the device on {}", status_topic),
+                    Err(err) => warn!("Announcing the device failed: {}", err),
+                }
+            }
+        })?;
+
+    Ok(())
+}
+
+fn device_info(device_id: &str, status: &Status) -> serde_json::Value {
+    let esp_idf = unsafe { CStr::from_ptr(esp_idf_svc::sys::esp_get_idf_version()) };
+
+    serde_json::json!({
+        "device_id": device_id,
+        "firmware": env!("CARGO_PKG_NAME"),
+        "version": env!("CARGO_PKG_VERSION"),
+        "esp_idf": esp_idf.to_string_lossy(),
+        "ip": status.snapshot().ip.map(|ip| ip.to_string()),
+        "features": FEATURES
+            .iter()
+            .filter(|(_, enabled)| *enabled)
+            .map(|(feature, _)| feature)
+            .collect::<Vec<_>>(),
+    })
+}
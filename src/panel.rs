@@ -0,0 +1,657 @@
+//! SPI panels which are not on one of the supported boards, with the controller, the size, the
+//! orientation and the pins chosen at build time
+//!
+//! The configuration is read from these environment variables:
+//! - `RUST_ESP32_STD_DEMO_PANEL`: the controller - `st7735`, `st7789`, `ili9341`, `ili9488`,
+//!   `gc9a01`, `sh1106` or `ssd1306`
+//! - `RUST_ESP32_STD_DEMO_PANEL_PINS`: e.g. `sclk=18,sdo=23,cs=5,dc=16,rst=17,backlight=4`, where
+//!   `cs`, `rst` and `backlight` are optional
+//! - `RUST_ESP32_STD_DEMO_PANEL_SIZE`: `<width>x<height>` for panels smaller than the RAM of their
+//!   controller, in the orientation of the RAM - portrait for the color panels, and landscape for
+//!   the OLEDs
+//! - `RUST_ESP32_STD_DEMO_PANEL_OFFSET`: `<x>,<y>` of the panel in the RAM, in the chosen
+//!   orientation
+//! - `RUST_ESP32_STD_DEMO_PANEL_ORIENTATION`: `portrait`, `landscape`, `portrait_flipped` or
+//!   `landscape_flipped`
+//! - `RUST_ESP32_STD_DEMO_PANEL_INVERT`: `true` or `false`, for panels which show the colors
+//!   inverted
+//! - `RUST_ESP32_STD_DEMO_PANEL_COLOR_ORDER`: `rgb` or `bgr`, for panels which swap red and blue
+//! - `RUST_ESP32_STD_DEMO_PANEL_MHZ`: the SPI clock
+//!
+//! All but the controller and the pins default to what is typical for the controller.
+
+use core::str::FromStr;
+
+use std::sync::Arc;
+
+use anyhow::{anyhow, bail, Result};
+
+use log::info;
+
+use display_interface::{DataFormat, DisplayError, WriteOnlyDataCommand};
+use display_interface_spi::SPIInterfaceNoCS;
+
+use embedded_graphics::pixelcolor::Rgb565;
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::Rectangle;
+
+use embedded_hal::blocking::delay::DelayUs;
+use embedded_hal::digital::v2::OutputPin;
+
+use esp_idf_svc::hal::delay;
+use esp_idf_svc::hal::gpio;
+use esp_idf_svc::hal::prelude::*;
+use esp_idf_svc::hal::spi;
+
+use ssd1306::mode::DisplayConfig;
+
+use mipidsi::error::InitError;
+use mipidsi::models::Model;
+use mipidsi::{ColorOrder, ModelOptions};
+
+use crate::display::DisplayService;
+use crate::gpio_api::GpioService;
+use crate::pwm::PwmService;
+use crate::sh1106::Sh1106;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Controller {
+    St7735,
+    St7789,
+    Ili9341,
+    Ili9488,
+    Gc9a01,
+    Sh1106,
+    Ssd1306,
+}
+
+impl Controller {
+    /// The size of the RAM, in its own orientation; see `is_oled()`
+    fn ram_size(&self) -> Size {
+        match self {
+            Self::St7735 => Size::new(132, 162),
+            Self::St7789 | Self::Ili9341 => Size::new(240, 320),
+            Self::Ili9488 => Size::new(320, 480),
+            Self::Gc9a01 => Size::new(240, 240),
+            Self::Sh1106 | Self::Ssd1306 => Size::new(128, 64),
+        }
+    }
+
+    /// The size of the most common panel with the controller, in the orientation of the RAM
+    fn default_size(&self) -> Size {
+        match self {
+            Self::St7735 => Size::new(128, 160),
+            other => other.ram_size(),
+        }
+    }
+
+    /// Whether the most common panels with the controller need the colors inverted
+    fn default_invert(&self) -> bool {
+        matches!(self, Self::St7789 | Self::Gc9a01)
+    }
+
+    fn default_color_order(&self) -> ColorOrder {
+        match self {
+            Self::St7789 => ColorOrder::Rgb,
+            _ => ColorOrder::Bgr,
+        }
+    }
+
+    /// The RAM of the OLEDs is in landscape orientation, and of the color panels in portrait
+    fn is_oled(&self) -> bool {
+        matches!(self, Self::Sh1106 | Self::Ssd1306)
+    }
+
+    /// The ILI9488 does not support 16 bit colors over SPI
+    fn is_rgb666(&self) -> bool {
+        matches!(self, Self::Ili9488)
+    }
+}
+
+impl FromStr for Controller {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "st7735" => Ok(Self::St7735),
+            "st7789" => Ok(Self::St7789),
+            "ili9341" => Ok(Self::Ili9341),
+            "ili9488" => Ok(Self::Ili9488),
+            "gc9a01" => Ok(Self::Gc9a01),
+            "sh1106" => Ok(Self::Sh1106),
+            "ssd1306" => Ok(Self::Ssd1306),
+            _ => bail!("Invalid panel controller: {}", s),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Orientation {
+    Portrait,
+    Landscape,
+    PortraitFlipped,
+    LandscapeFlipped,
+}
+
+impl Orientation {
+    fn is_landscape(&self) -> bool {
+        matches!(self, Self::Landscape | Self::LandscapeFlipped)
+    }
+
+    fn is_flipped(&self) -> bool {
+        matches!(self, Self::PortraitFlipped | Self::LandscapeFlipped)
+    }
+
+    fn mipidsi(&self) -> mipidsi::Orientation {
+        match self {
+            Self::Portrait => mipidsi::Orientation::Portrait(false),
+            Self::Landscape => mipidsi::Orientation::Landscape(false),
+            Self::PortraitFlipped => mipidsi::Orientation::PortraitInverted(false),
+            Self::LandscapeFlipped => mipidsi::Orientation::LandscapeInverted(false),
+        }
+    }
+}
+
+impl FromStr for Orientation {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "portrait" => Ok(Self::Portrait),
+            "landscape" => Ok(Self::Landscape),
+            "portrait_flipped" => Ok(Self::PortraitFlipped),
+            "landscape_flipped" => Ok(Self::LandscapeFlipped),
+            _ => bail!("Invalid panel orientation: {}", s),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct PanelPins {
+    pub sclk: i32,
+    pub sdo: i32,
+    pub dc: i32,
+    pub cs: Option<i32>,
+    pub rst: Option<i32>,
+    pub backlight: Option<i32>,
+}
+
+impl FromStr for PanelPins {
+    type Err = anyhow::Error;
+
+    /// `sclk=18,sdo=23,cs=5,dc=16,rst=17,backlight=4`
+    fn from_str(s: &str) -> Result<Self> {
+        let (mut sclk, mut sdo, mut dc) = (None, None, None);
+        let (mut cs, mut rst, mut backlight) = (None, None, None);
+
+        for assignment in s.split(',').map(str::trim).filter(|a| !a.is_empty()) {
+            let (name, pin) = assignment.split_once('=').ok_or_else(|| {
+                anyhow!("Invalid panel pin {}, expected <name>=<pin>", assignment)
+            })?;
+
+            let pin = Some(pin.trim().parse::<i32>()?);
+
+            match name.trim() {
+                "sclk" => sclk = pin,
+                "sdo" => sdo = pin,
+                "dc" => dc = pin,
+                "cs" => cs = pin,
+                "rst" => rst = pin,
+                "backlight" => backlight = pin,
+                other => bail!("Invalid panel pin name: {}", other),
+            }
+        }
+
+        let required =
+            |pin: Option<i32>, name| pin.ok_or_else(|| anyhow!("Panel pin {} is missing", name));
+
+        Ok(Self {
+            sclk: required(sclk, "sclk")?,
+            sdo: required(sdo, "sdo")?,
+            dc: required(dc, "dc")?,
+            cs,
+            rst,
+            backlight,
+        })
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct PanelConfig {
+    pub controller: Controller,
+    pub pins: PanelPins,
+    /// In the orientation of the RAM of the controller
+    pub size: Size,
+    /// Of the panel in the RAM of the controller, in `orientation`
+    pub offset: Point,
+    pub orientation: Orientation,
+    pub invert: bool,
+    pub color_order: ColorOrder,
+    pub mhz: u32,
+}
+
+impl PanelConfig {
+    pub fn from_env() -> Result<Self> {
+        let controller: Controller = option_env!("RUST_ESP32_STD_DEMO_PANEL")
+            .ok_or_else(|| anyhow!("Set RUST_ESP32_STD_DEMO_PANEL to the controller of the panel"))?
+            .parse()?;
+
+        let pins = option_env!("RUST_ESP32_STD_DEMO_PANEL_PINS")
+            .ok_or_else(|| anyhow!("Set RUST_ESP32_STD_DEMO_PANEL_PINS to the pins of the panel"))?
+            .parse()?;
+
+        let size = match option_env!("RUST_ESP32_STD_DEMO_PANEL_SIZE") {
+            Some(size) => {
+                let (width, height) = size.split_once('x').ok_or_else(|| {
+                    anyhow!("Invalid panel size {}, expected <width>x<height>", size)
+                })?;
+
+                Size::new(width.parse()?, height.parse()?)
+            }
+            None => controller.default_size(),
+        };
+
+        let offset = match option_env!("RUST_ESP32_STD_DEMO_PANEL_OFFSET") {
+            Some(offset) => {
+                let (x, y) = offset
+                    .split_once(',')
+                    .ok_or_else(|| anyhow!("Invalid panel offset {}, expected <x>,<y>", offset))?;
+
+                Point::new(x.parse()?, y.parse()?)
+            }
+            None => Point::zero(),
+        };
+
+        let orientation = option_env!("RUST_ESP32_STD_DEMO_PANEL_ORIENTATION")
+            .map(str::parse)
+            .transpose()?
+            .unwrap_or(if controller.is_oled() {
+                Orientation::Landscape
+            } else {
+                Orientation::Portrait
+            });
+
+        let invert = option_env!("RUST_ESP32_STD_DEMO_PANEL_INVERT")
+            .map(str::parse)
+            .transpose()?
+            .unwrap_or_else(|| controller.default_invert());
+
+        let color_order = match option_env!("RUST_ESP32_STD_DEMO_PANEL_COLOR_ORDER") {
+            Some("rgb") => ColorOrder::Rgb,
+            Some("bgr") => ColorOrder::Bgr,
+            Some(other) => bail!("Invalid panel color order: {}", other),
+            None => controller.default_color_order(),
+        };
+
+        let mhz = option_env!("RUST_ESP32_STD_DEMO_PANEL_MHZ")
+            .map(str::parse)
+            .transpose()?
+            .unwrap_or(if controller.is_oled() { 10 } else { 40 });
+
+        let config = Self {
+            controller,
+            pins,
+            size,
+            offset,
+            orientation,
+            invert,
+            color_order,
+            mhz,
+        };
+
+        config.check()?;
+
+        Ok(config)
+    }
+
+    /// The part of the RAM of the controller which is visible, in `orientation`
+    fn area(&self) -> Rectangle {
+        Rectangle::new(self.offset, self.orient(self.size))
+    }
+
+    /// The size in `orientation` of something with `size` in the orientation of the RAM
+    fn orient(&self, size: Size) -> Size {
+        if self.orientation.is_landscape() != self.controller.is_oled() {
+            Size::new(size.height, size.width)
+        } else {
+            size
+        }
+    }
+
+    fn check(&self) -> Result<()> {
+        let ram = Rectangle::new(Point::zero(), self.orient(self.controller.ram_size()));
+
+        let area = self.area();
+
+        if area.size.width == 0
+            || area.size.height == 0
+            || !ram.contains(area.top_left)
+            || !ram.contains(area.bottom_right().unwrap())
+        {
+            bail!(
+                "A {}x{} panel at {},{} does not fit into the RAM of the {:?}",
+                self.size.width,
+                self.size.height,
+                self.offset.x,
+                self.offset.y,
+                self.controller
+            );
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns the reset pin of the OLEDs, which has to be kept high for as long as the panel is used
+pub fn hello_world(
+    config: &PanelConfig,
+    gpios: &GpioService,
+    pwm: &PwmService,
+    display_service: Arc<DisplayService>,
+    spi: spi::SPI2,
+) -> Result<Option<gpio::PinDriver<'static, gpio::AnyIOPin, gpio::Output>>> {
+    info!(
+        "About to initialize the {:?} SPI panel: {:?}",
+        config.controller, config
+    );
+
+    let claim = |pin| gpios.claim_output(pin, "display");
+
+    let pins = &config.pins;
+
+    if let Some(backlight) = pins.backlight {
+        pwm.attach_backlight(claim(backlight)?)?;
+    }
+
+    let di = SPIInterfaceNoCS::new(
+        spi::SpiDeviceDriver::new_single(
+            spi,
+            claim(pins.sclk)?,
+            claim(pins.sdo)?,
+            Option::<gpio::AnyIOPin>::None,
+            pins.cs.map(claim).transpose()?,
+            &spi::SpiDriverConfig::new().dma(spi::Dma::Auto(4096)),
+            &spi::SpiConfig::new().baudrate(config.mhz.MHz().into()),
+        )?,
+        gpio::PinDriver::output(claim(pins.dc)?)?,
+    );
+
+    let mut rst = pins
+        .rst
+        .map(|rst| claim(rst).and_then(|rst| Ok(gpio::PinDriver::output(rst)?)))
+        .transpose()?;
+
+    match config.controller {
+        Controller::Sh1106 | Controller::Ssd1306 => {
+            if let Some(rst) = &mut rst {
+                rst.set_high()?;
+                delay::Ets::delay_ms(1_u32);
+
+                rst.set_low()?;
+                delay::Ets::delay_ms(10_u32);
+
+                rst.set_high()?;
+            }
+
+            oled_hello_world(config, display_service, di)?;
+
+            Ok(rst)
+        }
+        controller => {
+            let ram = controller.ram_size();
+
+            let display = mipidsi::Builder::with_model(
+                di,
+                Panel {
+                    controller,
+                    invert: config.invert,
+                },
+            )
+            .with_display_size(ram.width as _, ram.height as _)
+            .with_framebuffer_size(ram.width as _, ram.height as _)
+            .with_orientation(config.orientation.mipidsi())
+            .with_color_order(config.color_order)
+            .init(&mut delay::Ets, rst)
+            .map_err(|e| anyhow!("Display error: {:?}", e))?;
+
+            crate::spawn_buffered_screen(display_service, display, config.area())?;
+
+            Ok(None)
+        }
+    }
+}
+
+fn oled_hello_world<DI>(
+    config: &PanelConfig,
+    display_service: Arc<DisplayService>,
+    di: DI,
+) -> Result<()>
+where
+    DI: WriteOnlyDataCommand + Send + 'static,
+{
+    use ssd1306::rotation::DisplayRotation;
+    use ssd1306::size::{DisplaySize128x32, DisplaySize128x64};
+
+    let orientation = config.orientation;
+
+    if config.controller == Controller::Sh1106 {
+        if !orientation.is_landscape() {
+            bail!("The SH1106 panels can only be used in landscape orientation");
+        }
+
+        return crate::spawn_binary_screen(
+            display_service,
+            Sh1106::new(di, orientation.is_flipped())?,
+        );
+    }
+
+    let rotation = match orientation {
+        Orientation::Landscape => DisplayRotation::Rotate0,
+        Orientation::Portrait => DisplayRotation::Rotate90,
+        Orientation::LandscapeFlipped => DisplayRotation::Rotate180,
+        Orientation::PortraitFlipped => DisplayRotation::Rotate270,
+    };
+
+    // The SSD1306 sizes are types, so each one is a separate driver
+    match (config.size.width, config.size.height) {
+        (128, 64) => {
+            let mut display = ssd1306::Ssd1306::new(di, DisplaySize128x64, rotation)
+                .into_buffered_graphics_mode();
+
+            display
+                .init()
+                .map_err(|e| anyhow!("Display error: {:?}", e))?;
+
+            crate::spawn_binary_screen(display_service, display)
+        }
+        (128, 32) => {
+            let mut display = ssd1306::Ssd1306::new(di, DisplaySize128x32, rotation)
+                .into_buffered_graphics_mode();
+
+            display
+                .init()
+                .map_err(|e| anyhow!("Display error: {:?}", e))?;
+
+            crate::spawn_binary_screen(display_service, display)
+        }
+        (width, height) => bail!("Unsupported SSD1306 panel size: {}x{}", width, height),
+    }
+}
+
+/// The color controllers, which all take the MIPI DCS commands, but differ in their setup
+///
+/// `mipidsi` only has models for some of them, and always inverts the colors of the ST7735.
+struct Panel {
+    controller: Controller,
+    invert: bool,
+}
+
+// The MIPI DCS commands
+const SWRESET: u8 = 0x01;
+const SLPOUT: u8 = 0x11;
+const NORON: u8 = 0x13;
+const INVOFF: u8 = 0x20;
+const INVON: u8 = 0x21;
+const DISPON: u8 = 0x29;
+const RAMWR: u8 = 0x2c;
+const MADCTL: u8 = 0x36;
+const COLMOD: u8 = 0x3a;
+
+/// The vendor initialization sequence of the GC9A01, which does not work with its reset values
+const GC9A01_INIT: &[(u8, &[u8])] = &[
+    (0xef, &[]),
+    (0xeb, &[0x14]),
+    (0xfe, &[]),
+    (0xef, &[]),
+    (0xeb, &[0x14]),
+    (0x84, &[0x40]),
+    (0x85, &[0xff]),
+    (0x86, &[0xff]),
+    (0x87, &[0xff]),
+    (0x88, &[0x0a]),
+    (0x89, &[0x21]),
+    (0x8a, &[0x00]),
+    (0x8b, &[0x80]),
+    (0x8c, &[0x01]),
+    (0x8d, &[0x01]),
+    (0x8e, &[0xff]),
+    (0x8f, &[0xff]),
+    (0xb6, &[0x00, 0x20]),
+    (0x90, &[0x08, 0x08, 0x08, 0x08]),
+    (0xbd, &[0x06]),
+    (0xbc, &[0x00]),
+    (0xff, &[0x60, 0x01, 0x04]),
+    (0xc3, &[0x13]),
+    (0xc4, &[0x13]),
+    (0xc9, &[0x22]),
+    (0xbe, &[0x11]),
+    (0xe1, &[0x10, 0x0e]),
+    (0xdf, &[0x21, 0x0c, 0x02]),
+    (0xf0, &[0x45, 0x09, 0x08, 0x08, 0x26, 0x2a]),
+    (0xf1, &[0x43, 0x70, 0x72, 0x36, 0x37, 0x6f]),
+    (0xf2, &[0x45, 0x09, 0x08, 0x08, 0x26, 0x2a]),
+    (0xf3, &[0x43, 0x70, 0x72, 0x36, 0x37, 0x6f]),
+    (0xed, &[0x1b, 0x0b]),
+    (0xae, &[0x77]),
+    (0xcd, &[0x63]),
+    (
+        0x70,
+        &[0x07, 0x07, 0x04, 0x0e, 0x0f, 0x09, 0x07, 0x08, 0x03],
+    ),
+    (0xe8, &[0x34]),
+    (
+        0x62,
+        &[
+            0x18, 0x0d, 0x71, 0xed, 0x70, 0x70, 0x18, 0x0f, 0x71, 0xef, 0x70, 0x70,
+        ],
+    ),
+    (
+        0x63,
+        &[
+            0x18, 0x11, 0x71, 0xf1, 0x70, 0x70, 0x18, 0x13, 0x71, 0xf3, 0x70, 0x70,
+        ],
+    ),
+    (0x64, &[0x28, 0x29, 0xf1, 0x01, 0xf1, 0x00, 0x07]),
+    (
+        0x66,
+        &[0x3c, 0x00, 0xcd, 0x67, 0x45, 0x45, 0x10, 0x00, 0x00, 0x00],
+    ),
+    (
+        0x67,
+        &[0x00, 0x3c, 0x00, 0x00, 0x00, 0x01, 0x54, 0x10, 0x32, 0x98],
+    ),
+    (0x74, &[0x10, 0x85, 0x80, 0x00, 0x00, 0x4e, 0x00]),
+    (0x98, &[0x3e, 0x07]),
+];
+
+impl Model for Panel {
+    type ColorFormat = Rgb565;
+
+    fn init<RST, DELAY, DI>(
+        &mut self,
+        di: &mut DI,
+        delay: &mut DELAY,
+        madctl: u8,
+        rst: &mut Option<RST>,
+    ) -> Result<u8, InitError<RST::Error>>
+    where
+        RST: OutputPin,
+        DELAY: DelayUs<u32>,
+        DI: WriteOnlyDataCommand,
+    {
+        match rst {
+            Some(ref mut rst) => self.hard_reset(rst, delay)?,
+            None => command(di, SWRESET, &[])?,
+        }
+
+        delay.delay_us(150_000);
+
+        if self.controller == Controller::Gc9a01 {
+            for (cmd, params) in GC9A01_INIT {
+                command(di, *cmd, params)?;
+            }
+        }
+
+        command(di, SLPOUT, &[])?;
+        delay.delay_us(120_000);
+
+        command(di, MADCTL, &[madctl])?;
+
+        // 18 or 16 bits per pixel
+        let colmod = if self.controller.is_rgb666() {
+            0x66
+        } else {
+            0x55
+        };
+        command(di, COLMOD, &[colmod])?;
+
+        command(di, if self.invert { INVON } else { INVOFF }, &[])?;
+
+        command(di, NORON, &[])?;
+        command(di, DISPON, &[])?;
+
+        // DISPON requires some time otherwise we risk SPI data issues
+        delay.delay_us(120_000);
+
+        Ok(madctl)
+    }
+
+    fn write_pixels<DI, I>(&mut self, di: &mut DI, colors: I) -> Result<(), DisplayError>
+    where
+        DI: WriteOnlyDataCommand,
+        I: IntoIterator<Item = Self::ColorFormat>,
+    {
+        command(di, RAMWR, &[])?;
+
+        if self.controller.is_rgb666() {
+            // The upper 6 bits of each byte are used
+            let mut iter = colors
+                .into_iter()
+                .flat_map(|c| [c.r() << 3, c.g() << 2, c.b() << 3]);
+
+            di.send_data(DataFormat::U8Iter(&mut iter))
+        } else {
+            let mut iter = colors.into_iter().map(|c| c.into_storage());
+
+            di.send_data(DataFormat::U16BEIter(&mut iter))
+        }
+    }
+
+    fn default_options() -> ModelOptions {
+        // Always replaced with the sizes of the configured controller
+        ModelOptions::with_sizes((240, 320), (240, 320))
+    }
+}
+
+fn command<DI>(di: &mut DI, command: u8, params: &[u8]) -> Result<(), DisplayError>
+where
+    DI: WriteOnlyDataCommand,
+{
+    di.send_commands(DataFormat::U8(&[command]))?;
+
+    if !params.is_empty() {
+        di.send_data(DataFormat::U8(params))?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,493 @@
+//! PWM outputs on top of the LEDC peripheral
+//!
+//! Outputs can be attached at runtime to any pin in the GPIO allowlist (for LEDs, fans and the like),
+//! or to a pin owned by a board feature (the display backlight). Each output gets its own LEDC channel,
+//! while outputs with the same frequency and resolution share a LEDC timer.
+
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, bail, Result};
+
+use log::*;
+
+use serde::Serialize;
+
+use esp_idf_svc::hal::gpio::OutputPin;
+use esp_idf_svc::hal::ledc::{config::TimerConfig, LedcDriver, LedcTimerDriver, Resolution, LEDC};
+use esp_idf_svc::hal::peripheral::Peripheral;
+use esp_idf_svc::hal::units::Hertz;
+use esp_idf_svc::http::server::{EspHttpServer, Method};
+use esp_idf_svc::io::Write;
+
+use crate::gpio_api::GpioService;
+
+const TIMERS: usize = 4;
+
+#[cfg(any(esp32, esp32s2, esp32s3))]
+const CHANNELS: usize = 8;
+#[cfg(not(any(esp32, esp32s2, esp32s3)))]
+const CHANNELS: usize = 6;
+
+pub const BACKLIGHT: &str = "backlight";
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct PwmConfig {
+    pub frequency: u32,
+    pub resolution: u32,
+}
+
+impl PwmConfig {
+    pub const fn new(frequency: u32, resolution: u32) -> Self {
+        Self {
+            frequency,
+            resolution,
+        }
+    }
+
+    fn timer_config(&self) -> Result<TimerConfig> {
+        let resolution = match self.resolution {
+            1 => Resolution::Bits1,
+            2 => Resolution::Bits2,
+            3 => Resolution::Bits3,
+            4 => Resolution::Bits4,
+            5 => Resolution::Bits5,
+            6 => Resolution::Bits6,
+            7 => Resolution::Bits7,
+            8 => Resolution::Bits8,
+            9 => Resolution::Bits9,
+            10 => Resolution::Bits10,
+            11 => Resolution::Bits11,
+            12 => Resolution::Bits12,
+            13 => Resolution::Bits13,
+            14 => Resolution::Bits14,
+            #[cfg(esp32)]
+            15 => Resolution::Bits15,
+            #[cfg(esp32)]
+            16 => Resolution::Bits16,
+            #[cfg(esp32)]
+            17 => Resolution::Bits17,
+            #[cfg(esp32)]
+            18 => Resolution::Bits18,
+            #[cfg(esp32)]
+            19 => Resolution::Bits19,
+            #[cfg(esp32)]
+            20 => Resolution::Bits20,
+            bits => bail!("Unsupported PWM resolution: {} bits", bits),
+        };
+
+        Ok(TimerConfig::new()
+            .frequency(Hertz(self.frequency))
+            .resolution(resolution))
+    }
+}
+
+impl Default for PwmConfig {
+    fn default() -> Self {
+        Self::new(5000, 10)
+    }
+}
+
+#[derive(Serialize)]
+pub struct PwmStatus {
+    pub pin: i32,
+    pub name: &'static str,
+    #[serde(flatten)]
+    config: PwmConfig,
+    pub duty: f32,
+}
+
+struct Timer {
+    driver: LedcTimerDriver<'static>,
+    config: PwmConfig,
+}
+
+struct Channel {
+    pin: i32,
+    name: &'static str,
+    timer: usize,
+    driver: LedcDriver<'static>,
+    config: PwmConfig,
+    // Whether the pin was claimed from the GPIO allowlist, as opposed to being owned by a board feature
+    remote: bool,
+}
+
+fn check_duty(duty: f32) -> Result<()> {
+    if !(0.0..=100.0).contains(&duty) {
+        bail!("Invalid PWM duty: {}%", duty);
+    }
+
+    Ok(())
+}
+
+impl Channel {
+    fn duty(&self) -> f32 {
+        self.driver.get_duty() as f32 * 100.0 / self.driver.get_max_duty() as f32
+    }
+
+    fn set_duty(&mut self, duty: f32) -> Result<()> {
+        check_duty(duty)?;
+
+        let max_duty = self.driver.get_max_duty();
+
+        self.driver
+            .set_duty((duty * max_duty as f32 / 100.0).round() as u32)?;
+
+        Ok(())
+    }
+}
+
+struct State {
+    ledc: LEDC,
+    timers: [Option<Timer>; TIMERS],
+    channels: [Option<Channel>; CHANNELS],
+}
+
+impl State {
+    fn timer(&mut self, config: PwmConfig) -> Result<usize> {
+        if let Some(index) = self
+            .timers
+            .iter()
+            .position(|timer| matches!(timer, Some(timer) if timer.config == config))
+        {
+            return Ok(index);
+        }
+
+        let index = self
+            .timers
+            .iter()
+            .position(Option::is_none)
+            .ok_or_else(|| anyhow!("All LEDC timers are in use"))?;
+
+        let timer_config = config.timer_config()?;
+
+        // Safe, because the service owns the LEDC peripheral, and creates at most one driver per timer
+        let driver = unsafe {
+            match index {
+                0 => LedcTimerDriver::new(self.ledc.timer0.clone_unchecked(), &timer_config),
+                1 => LedcTimerDriver::new(self.ledc.timer1.clone_unchecked(), &timer_config),
+                2 => LedcTimerDriver::new(self.ledc.timer2.clone_unchecked(), &timer_config),
+                _ => LedcTimerDriver::new(self.ledc.timer3.clone_unchecked(), &timer_config),
+            }
+        }?;
+
+        self.timers[index] = Some(Timer { driver, config });
+
+        Ok(index)
+    }
+
+    fn attach(
+        &mut self,
+        pin: impl OutputPin,
+        name: &'static str,
+        remote: bool,
+        config: PwmConfig,
+        duty: f32,
+    ) -> Result<()> {
+        check_duty(duty)?;
+
+        let pin_number = pin.pin();
+
+        let index = self
+            .channels
+            .iter()
+            .position(Option::is_none)
+            .ok_or_else(|| anyhow!("All LEDC channels are in use"))?;
+
+        let timer = self.timer(config)?;
+        let timer_driver = &self.timers[timer].as_ref().unwrap().driver;
+
+        // Safe, because the service owns the LEDC peripheral, and creates at most one driver per channel
+        let driver = unsafe {
+            match index {
+                0 => LedcDriver::new(self.ledc.channel0.clone_unchecked(), timer_driver, pin),
+                1 => LedcDriver::new(self.ledc.channel1.clone_unchecked(), timer_driver, pin),
+                2 => LedcDriver::new(self.ledc.channel2.clone_unchecked(), timer_driver, pin),
+                3 => LedcDriver::new(self.ledc.channel3.clone_unchecked(), timer_driver, pin),
+                4 => LedcDriver::new(self.ledc.channel4.clone_unchecked(), timer_driver, pin),
+                #[cfg(any(esp32, esp32s2, esp32s3))]
+                5 => LedcDriver::new(self.ledc.channel5.clone_unchecked(), timer_driver, pin),
+                #[cfg(any(esp32, esp32s2, esp32s3))]
+                6 => LedcDriver::new(self.ledc.channel6.clone_unchecked(), timer_driver, pin),
+                #[cfg(any(esp32, esp32s2, esp32s3))]
+                _ => LedcDriver::new(self.ledc.channel7.clone_unchecked(), timer_driver, pin),
+                #[cfg(not(any(esp32, esp32s2, esp32s3)))]
+                _ => LedcDriver::new(self.ledc.channel5.clone_unchecked(), timer_driver, pin),
+            }
+        };
+
+        let driver = match driver {
+            Ok(driver) => driver,
+            Err(err) => {
+                self.release_timer(timer);
+                Err(err)?
+            }
+        };
+
+        let mut channel = Channel {
+            pin: pin_number,
+            name,
+            timer,
+            driver,
+            config,
+            remote,
+        };
+
+        if let Err(err) = channel.set_duty(duty) {
+            // The driver of the channel goes before its timer
+            drop(channel);
+            self.release_timer(timer);
+
+            return Err(err);
+        }
+
+        self.channels[index] = Some(channel);
+
+        info!(
+            "Attached PWM {} to GPIO{}: {:?}, {}% duty",
+            name, pin_number, config, duty
+        );
+
+        Ok(())
+    }
+
+    fn detach(&mut self, pin: i32) -> Result<()> {
+        let slot = self
+            .channels
+            .iter_mut()
+            .find(|channel| matches!(channel, Some(channel) if channel.pin == pin))
+            .ok_or_else(|| anyhow!("No PWM attached to GPIO{}", pin))?;
+
+        if !slot.as_ref().unwrap().remote {
+            bail!("PWM {} cannot be detached", slot.as_ref().unwrap().name);
+        }
+
+        let timer = slot.as_ref().unwrap().timer;
+
+        // Dropping the driver stops the PWM signal
+        *slot = None;
+
+        self.release_timer(timer);
+
+        Ok(())
+    }
+
+    fn channel(&mut self, pin: i32) -> Result<&mut Channel> {
+        self.channels
+            .iter_mut()
+            .flatten()
+            .find(|channel| channel.pin == pin)
+            .ok_or_else(|| anyhow!("No PWM attached to GPIO{}", pin))
+    }
+
+    fn release_timer(&mut self, timer: usize) {
+        if !self
+            .channels
+            .iter()
+            .flatten()
+            .any(|channel| channel.timer == timer)
+        {
+            self.timers[timer] = None;
+        }
+    }
+}
+
+pub struct PwmService {
+    gpios: Arc<GpioService>,
+    state: Mutex<State>,
+}
+
+impl PwmService {
+    pub fn new(ledc: LEDC, gpios: Arc<GpioService>) -> Self {
+        Self {
+            gpios,
+            state: Mutex::new(State {
+                ledc,
+                timers: Default::default(),
+                channels: Default::default(),
+            }),
+        }
+    }
+
+    /// Attaches the display backlight at full brightness
+    pub fn attach_backlight(&self, pin: impl OutputPin) -> Result<()> {
+        self.state
+            .lock()
+            .unwrap()
+            .attach(pin, BACKLIGHT, false, PwmConfig::default(), 100.0)
+    }
+
+    /// Sets the backlight brightness in percent, if there is a backlight
+    pub fn set_backlight(&self, brightness: f32) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+
+        if let Some(channel) = state
+            .channels
+            .iter_mut()
+            .flatten()
+            .find(|channel| channel.name == BACKLIGHT)
+        {
+            channel.set_duty(brightness)?;
+        }
+
+        Ok(())
+    }
+
+    /// Attaches an output to a pin from the GPIO allowlist
+    pub fn attach(&self, pin: i32, config: PwmConfig, duty: f32) -> Result<()> {
+        let gpio = self.gpios.claim_output(pin, "pwm")?;
+
+        let result = self
+            .state
+            .lock()
+            .unwrap()
+            .attach(gpio, "pwm", true, config, duty);
+
+        if result.is_err() {
+            self.gpios.release(pin);
+        }
+
+        result
+    }
+
+    pub fn detach(&self, pin: i32) -> Result<()> {
+        self.state.lock().unwrap().detach(pin)?;
+
+        self.gpios.release(pin);
+
+        info!("Detached PWM from GPIO{}", pin);
+
+        Ok(())
+    }
+
+    /// Sets the duty cycle of the output in percent
+    pub fn set_duty(&self, pin: i32, duty: f32) -> Result<()> {
+        self.state.lock().unwrap().channel(pin)?.set_duty(duty)
+    }
+
+    pub fn status(&self) -> Vec<PwmStatus> {
+        self.state
+            .lock()
+            .unwrap()
+            .channels
+            .iter()
+            .flatten()
+            .map(|channel| PwmStatus {
+                pin: channel.pin,
+                name: channel.name,
+                config: channel.config,
+                duty: channel.duty(),
+            })
+            .collect()
+    }
+}
+
+pub fn httpd_endpoints(server: &mut EspHttpServer<'static>, pwm: Arc<PwmService>) -> Result<()> {
+    fn param<T>(form: &[(String, String)], name: &str, default: T) -> Result<T>
+    where
+        T: core::str::FromStr,
+        T::Err: std::error::Error + Send + Sync + 'static,
+    {
+        match crate::form_param(form, name) {
+            Ok(value) => Ok(value.parse()?),
+            Err(_) => Ok(default),
+        }
+    }
+
+    fn pin(form: &[(String, String)]) -> Result<i32> {
+        Ok(crate::form_param(form, "pin")?.parse()?)
+    }
+
+    fn not_allowed(pin: i32) -> anyhow::Error {
+        anyhow!("GPIO{} is not in the allowlist", pin)
+    }
+
+    let list = pwm.clone();
+    let attach = pwm.clone();
+    let duty_service = pwm.clone();
+    let detach = pwm;
+
+    server
+        .fn_handler("/pwm", Method::Get, move |req| {
+            let json = serde_json::to_string(&list.status())?;
+
+            req.into_response(200, Some("OK"), &[("Content-Type", "application/json")])?
+                .write_all(json.as_bytes())?;
+
+            Result::<_, anyhow::Error>::Ok(())
+        })?
+        .fn_handler("/pwm/attach", Method::Post, move |mut req| {
+            let Some(form) = crate::read_form(&mut req)? else {
+                return Ok(());
+            };
+
+            let params = pin(&form).and_then(|pin| {
+                let config = PwmConfig::new(
+                    param(&form, "frequency", PwmConfig::default().frequency)?,
+                    param(&form, "resolution", PwmConfig::default().resolution)?,
+                );
+
+                Ok((pin, config, param(&form, "duty", 0.0)?))
+            });
+
+            let (pin, config, duty) = match params {
+                Ok(params) => params,
+                Err(err) => return crate::bad_request(req, err),
+            };
+
+            if !crate::gpio_api::is_allowed(pin) {
+                return crate::forbidden(req, not_allowed(pin));
+            }
+
+            if let Err(err) = attach.attach(pin, config, duty) {
+                return crate::bad_request(req, err);
+            }
+
+            req.into_ok_response()?;
+
+            Result::<_, anyhow::Error>::Ok(())
+        })?
+        .fn_handler("/pwm/duty", Method::Post, move |mut req| {
+            let Some(form) = crate::read_form(&mut req)? else {
+                return Ok(());
+            };
+
+            let result = pin(&form).and_then(|pin| {
+                let duty = crate::form_param(&form, "duty")?.parse()?;
+
+                duty_service.set_duty(pin, duty)
+            });
+
+            if let Err(err) = result {
+                return crate::bad_request(req, err);
+            }
+
+            req.into_ok_response()?;
+
+            Result::<_, anyhow::Error>::Ok(())
+        })?
+        .fn_handler("/pwm/detach", Method::Post, move |mut req| {
+            let Some(form) = crate::read_form(&mut req)? else {
+                return Ok(());
+            };
+
+            let pin = match pin(&form) {
+                Ok(pin) => pin,
+                Err(err) => return crate::bad_request(req, err),
+            };
+
+            // Only the pins attached from the allowlist can be detached
+            if !crate::gpio_api::is_allowed(pin) {
+                return crate::forbidden(req, not_allowed(pin));
+            }
+
+            if let Err(err) = detach.detach(pin) {
+                return crate::bad_request(req, err);
+            }
+
+            req.into_ok_response()?;
+
+            Result::<_, anyhow::Error>::Ok(())
+        })?;
+
+    Ok(())
+}
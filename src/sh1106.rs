@@ -0,0 +1,160 @@
+//! A minimal driver for the 128x64 SH1106 OLEDs, which the `ssd1306` crate does not support
+//!
+//! The SH1106 is mostly command-compatible with the SSD1306, but has 132 columns of RAM (with the
+//! panel in the middle of them) and no horizontal addressing mode, so the frame buffer is sent
+//! page by page. Only the pages which changed since the previous flush are sent.
+
+use core::convert::Infallible;
+
+use anyhow::Result;
+
+use display_interface::{DataFormat, DisplayError, WriteOnlyDataCommand};
+
+use embedded_graphics::pixelcolor::BinaryColor;
+use embedded_graphics::prelude::*;
+
+use crate::display::{Oled, PowerState};
+
+pub const WIDTH: u32 = 128;
+pub const HEIGHT: u32 = 64;
+
+const PAGES: usize = HEIGHT as usize / 8;
+
+// The panel is connected to columns 2..130 of the 132 columns of RAM
+const COLUMN_OFFSET: u8 = 2;
+
+const CONTRAST_NORMAL: u8 = 0xcf;
+const CONTRAST_DIMMED: u8 = 0x01;
+
+pub struct Sh1106<DI> {
+    di: DI,
+    buffer: [[u8; WIDTH as usize]; PAGES],
+    dirty: [bool; PAGES],
+}
+
+impl<DI> Sh1106<DI>
+where
+    DI: WriteOnlyDataCommand,
+{
+    /// Initializes the panel, upside down if `flipped`
+    ///
+    /// The reset pin - if the panel has one - must have been pulsed before.
+    pub fn new(di: DI, flipped: bool) -> Result<Self> {
+        let mut sh1106 = Self {
+            di,
+            buffer: [[0; WIDTH as usize]; PAGES],
+            // So that the first flush clears whatever the RAM contains after the reset
+            dirty: [true; PAGES],
+        };
+
+        let (segment_remap, scan_direction) = if flipped { (0xa0, 0xc0) } else { (0xa1, 0xc8) };
+
+        for command in [
+            &[0xae][..],              // Display off
+            &[0xd5, 0x80],            // Clock divider
+            &[0xa8, 0x3f],            // Multiplex ratio: 64
+            &[0xd3, 0x00],            // Display offset
+            &[0x40],                  // Start line 0
+            &[0xad, 0x8b],            // Charge pump on
+            &[segment_remap],         // Column order
+            &[scan_direction],        // Row order
+            &[0xda, 0x12],            // COM pins
+            &[0x81, CONTRAST_NORMAL], // Contrast
+            &[0xd9, 0x1f],            // Pre-charge period
+            &[0xdb, 0x40],            // VCOMH deselect level
+            &[0xa4],                  // Display the RAM contents
+            &[0xa6],                  // Not inverted
+            &[0xaf],                  // Display on
+        ] {
+            sh1106.command(command)?;
+        }
+
+        Ok(sh1106)
+    }
+
+    fn command(&mut self, command: &[u8]) -> Result<()> {
+        self.di
+            .send_commands(DataFormat::U8(command))
+            .map_err(sh1106_error)
+    }
+}
+
+impl<DI> Oled for Sh1106<DI>
+where
+    DI: WriteOnlyDataCommand,
+{
+    fn flush(&mut self) -> Result<()> {
+        for page in 0..PAGES {
+            if !self.dirty[page] {
+                continue;
+            }
+
+            self.command(&[
+                0xb0 | page as u8,
+                COLUMN_OFFSET & 0x0f,
+                0x10 | (COLUMN_OFFSET >> 4),
+            ])?;
+
+            self.di
+                .send_data(DataFormat::U8(&self.buffer[page]))
+                .map_err(sh1106_error)?;
+
+            self.dirty[page] = false;
+        }
+
+        Ok(())
+    }
+
+    fn set_power(&mut self, power: PowerState) -> Result<()> {
+        match power {
+            PowerState::On => self.command(&[0x81, CONTRAST_NORMAL])?,
+            PowerState::Dimmed => self.command(&[0x81, CONTRAST_DIMMED])?,
+            PowerState::Blanked => return self.command(&[0xae]),
+        }
+
+        self.command(&[0xaf])
+    }
+}
+
+impl<DI> DrawTarget for Sh1106<DI> {
+    type Color = BinaryColor;
+    type Error = Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        for Pixel(point, color) in pixels {
+            if point.x < 0 || point.y < 0 || point.x >= WIDTH as i32 || point.y >= HEIGHT as i32 {
+                continue;
+            }
+
+            let page = point.y as usize / 8;
+            let bit = 1 << (point.y % 8);
+            let byte = &mut self.buffer[page][point.x as usize];
+
+            let updated = if color.is_on() {
+                *byte | bit
+            } else {
+                *byte & !bit
+            };
+
+            if *byte != updated {
+                *byte = updated;
+                self.dirty[page] = true;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<DI> OriginDimensions for Sh1106<DI> {
+    fn size(&self) -> Size {
+        Size::new(WIDTH, HEIGHT)
+    }
+}
+
+fn sh1106_error(err: DisplayError) -> anyhow::Error {
+    anyhow::anyhow!("Display error: {:?}", err)
+}
@@ -0,0 +1,55 @@
+//! The on-die temperature sensor of the ESP32-S2, ESP32-S3, ESP32-C3 and later chips
+//!
+//! Not (yet) wrapped by `esp-idf-hal`, hence using the ESP-IDF driver directly.
+
+use core::ops::RangeInclusive;
+use core::ptr;
+
+use esp_idf_svc::sys::*;
+
+pub struct TempSensor(temperature_sensor_handle_t);
+
+impl TempSensor {
+    /// Installs and enables the sensor
+    ///
+    /// The hardware supports several measurement ranges with different accuracy; ESP-IDF picks the
+    /// most accurate one which covers the supplied range, or fails if no such range exists.
+    pub fn new(range: RangeInclusive<i32>) -> Result<Self, EspError> {
+        let config = temperature_sensor_config_t {
+            range_min: *range.start(),
+            range_max: *range.end(),
+            clk_src: soc_periph_temperature_sensor_clk_src_t_TEMPERATURE_SENSOR_CLK_SRC_DEFAULT,
+            ..Default::default()
+        };
+
+        let mut handle: temperature_sensor_handle_t = ptr::null_mut();
+
+        esp!(unsafe { temperature_sensor_install(&config, &mut handle) })?;
+
+        esp!(unsafe { temperature_sensor_enable(handle) }).map_err(|err| {
+            unsafe { temperature_sensor_uninstall(handle) };
+            err
+        })?;
+
+        Ok(Self(handle))
+    }
+
+    pub fn read_celsius(&mut self) -> Result<f32, EspError> {
+        let mut celsius = 0_f32;
+
+        esp!(unsafe { temperature_sensor_get_celsius(self.0, &mut celsius) })?;
+
+        Ok(celsius)
+    }
+}
+
+impl Drop for TempSensor {
+    fn drop(&mut self) {
+        unsafe {
+            temperature_sensor_disable(self.0);
+            temperature_sensor_uninstall(self.0);
+        }
+    }
+}
+
+unsafe impl Send for TempSensor {}
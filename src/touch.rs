@@ -0,0 +1,250 @@
+//! Touches of the capacitive touch pads of the ESP32-S2 and ESP32-S3, posted to the event loop
+//!
+//! The pads are set with `RUST_ESP32_STD_DEMO_TOUCH_PADS` as `<name>=<pad>` pairs, like
+//! `previous=1,home=2,next=3` - which is also the default on the Kaluga, for the volume up,
+//! play and volume down pads of its touch panel. Touch pad N is on GPIO N.
+//!
+//! The raw readings rise when a pad is touched. The baseline of each pad is measured at startup,
+//! when the pads are assumed not to be touched, and then follows the slow drift of the untouched
+//! readings. A touch starts well above the baseline and only ends closer to it again, so that
+//! noisy readings do not turn into a burst of touches.
+
+use core::ffi::CStr;
+
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Result};
+
+use log::*;
+
+use esp_idf_svc::eventloop::*;
+use esp_idf_svc::hal::delay;
+use esp_idf_svc::sys::*;
+
+use crate::display::{DisplayService, Page};
+use crate::gpio_api::GpioService;
+
+#[cfg(feature = "kaluga")]
+const DEFAULT_PADS: &str = "previous=1,home=2,next=3";
+#[cfg(not(feature = "kaluga"))]
+const DEFAULT_PADS: &str = "";
+
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+const CALIBRATION_SAMPLES: u32 = 16;
+
+// Above the baseline, in percent of it
+const TOUCH_THRESHOLD: u32 = 3;
+const RELEASE_THRESHOLD: u32 = 1;
+
+// How quickly the baseline follows the untouched readings, as the shift of the reading's weight
+const BASELINE_SHIFT: u32 = 6;
+
+#[derive(Copy, Clone, Debug)]
+pub struct TouchEvent {
+    pub pad: &'static str,
+    pub touched: bool,
+}
+
+unsafe impl EspEventSource for TouchEvent {
+    fn source() -> Option<&'static CStr> {
+        // String should be unique across the whole project and ESP IDF
+        Some(CStr::from_bytes_with_nul(b"DEMO-TOUCH\0").unwrap())
+    }
+}
+
+impl EspEventSerializer for TouchEvent {
+    type Data<'a> = TouchEvent;
+
+    fn serialize<F, R>(event: &Self::Data<'_>, f: F) -> R
+    where
+        F: FnOnce(&EspEventPostData) -> R,
+    {
+        // The payload is `Copy` and only refers to static strings
+        f(&unsafe { EspEventPostData::new(Self::source().unwrap(), Self::event_id(), event) })
+    }
+}
+
+impl EspEventDeserializer for TouchEvent {
+    type Data<'a> = TouchEvent;
+
+    fn deserialize<'a>(data: &EspEvent<'a>) -> Self::Data<'a> {
+        *unsafe { data.as_payload::<TouchEvent>() }
+    }
+}
+
+/// The pads from `RUST_ESP32_STD_DEMO_TOUCH_PADS`, or the default ones of the board
+pub fn pads_from_env() -> Result<Vec<(&'static str, u32)>> {
+    option_env!("RUST_ESP32_STD_DEMO_TOUCH_PADS")
+        .unwrap_or(DEFAULT_PADS)
+        .split(',')
+        .filter(|pad| !pad.is_empty())
+        .map(|pad| {
+            let (name, num) = pad
+                .split_once('=')
+                .ok_or_else(|| anyhow!("Invalid touch pad {}, expected <name>=<pad>", pad))?;
+
+            let num = num.parse()?;
+
+            if !(1..=14).contains(&num) {
+                bail!("Invalid touch pad {}, the pads are 1 to 14", num);
+            }
+
+            Ok((name, num))
+        })
+        .collect()
+}
+
+/// Claims the pins of the pads, calibrates them and spawns the thread which posts their events
+///
+/// Pads whose pins are in use by something else - like the display of the Kaluga, which shares
+/// its pins with some of the touch panel pads - are skipped.
+pub fn spawn(
+    pads: &[(&'static str, u32)],
+    gpios: &GpioService,
+    eventloop: EspBackgroundEventLoop,
+) -> Result<()> {
+    let mut claimed = Vec::new();
+
+    for &(name, num) in pads {
+        match gpios.claim(num as i32, "touch") {
+            Ok(_) => claimed.push((name, num)),
+            Err(err) => warn!("Not using the {} touch pad: {}", name, err),
+        }
+    }
+
+    if claimed.is_empty() {
+        return Ok(());
+    }
+
+    esp!(unsafe { touch_pad_init() })?;
+
+    for (_, num) in &claimed {
+        esp!(unsafe { touch_pad_config(*num as touch_pad_t) })?;
+    }
+
+    esp!(unsafe { touch_pad_set_fsm_mode(touch_fsm_mode_t_TOUCH_FSM_MODE_TIMER) })?;
+    esp!(unsafe { touch_pad_fsm_start() })?;
+
+    let mut pads = claimed
+        .into_iter()
+        .map(|(name, num)| TouchPad::calibrate(name, num))
+        .collect::<Result<Vec<_>>>()?;
+
+    info!(
+        "Touch pads calibrated: {:?}",
+        pads.iter()
+            .map(|pad| (pad.name, pad.num, pad.baseline))
+            .collect::<Vec<_>>()
+    );
+
+    thread::Builder::new()
+        .name("touch".into())
+        .stack_size(4096)
+        .spawn(move || loop {
+            thread::sleep(POLL_INTERVAL);
+
+            for pad in &mut pads {
+                match pad.update() {
+                    Ok(Some(touched)) => {
+                        let event = TouchEvent {
+                            pad: pad.name,
+                            touched,
+                        };
+
+                        if let Err(err) = eventloop.post::<TouchEvent>(&event, delay::NON_BLOCK) {
+                            warn!("Posting {:?} failed: {}", event, err);
+                        }
+                    }
+                    Ok(None) => (),
+                    Err(err) => warn!("Reading the {} touch pad failed: {}", pad.name, err),
+                }
+            }
+        })?;
+
+    Ok(())
+}
+
+/// Navigate the display pages with the `previous`, `next` and `home` pads, and wake the display
+/// on any touch
+pub fn subscribe_default_actions(
+    eventloop: &EspBackgroundEventLoop,
+    display_service: Arc<DisplayService>,
+) -> Result<EspBackgroundSubscription<'static>> {
+    let subscription = eventloop.subscribe::<TouchEvent, _>(move |event| {
+        info!("Touch pad {} touched: {}", event.pad, event.touched);
+
+        if !event.touched {
+            return;
+        }
+
+        match event.pad {
+            "previous" => display_service.set_page(display_service.page().previous()),
+            "next" => display_service.set_page(display_service.page().next()),
+            "home" => display_service.set_page(Page::Dashboard),
+            _ => display_service.wake(),
+        }
+    })?;
+
+    Ok(subscription)
+}
+
+struct TouchPad {
+    name: &'static str,
+    num: u32,
+    baseline: u32,
+    touched: bool,
+}
+
+impl TouchPad {
+    fn calibrate(name: &'static str, num: u32) -> Result<Self> {
+        let mut sum = 0;
+
+        for _ in 0..CALIBRATION_SAMPLES {
+            thread::sleep(POLL_INTERVAL);
+
+            sum += read(num)?;
+        }
+
+        Ok(Self {
+            name,
+            num,
+            baseline: sum / CALIBRATION_SAMPLES,
+            touched: false,
+        })
+    }
+
+    /// Returns whether the pad is now touched, if that changed
+    fn update(&mut self) -> Result<Option<bool>> {
+        let value = read(self.num)?;
+
+        let touched = if self.touched {
+            value > self.baseline + self.baseline * RELEASE_THRESHOLD / 100
+        } else {
+            value > self.baseline + self.baseline * TOUCH_THRESHOLD / 100
+        };
+
+        if !touched {
+            // Follow the drift of the readings with e.g. the temperature and humidity
+            self.baseline =
+                self.baseline - (self.baseline >> BASELINE_SHIFT) + (value >> BASELINE_SHIFT);
+        }
+
+        if touched == self.touched {
+            return Ok(None);
+        }
+
+        self.touched = touched;
+
+        Ok(Some(touched))
+    }
+}
+
+fn read(num: u32) -> Result<u32> {
+    let mut value = 0;
+
+    esp!(unsafe { touch_pad_read_raw_data(num as touch_pad_t, &mut value) })?;
+
+    Ok(value)
+}